@@ -0,0 +1,219 @@
+//! Access log of every HTTP request (`--access-log`).
+//!
+//! One line per request, written once the response body has been sent (or
+//! the client went away), so the byte count and duration cover the whole
+//! transfer:
+//!
+//! ```text
+//! 2026-01-02T03:04:05.678Z 192.0.2.7:51234 "GET /data/ab12 HTTP/1.1" 200 4194304 312ms "restic/0.17.3" 9f86d081884c7d659a2feaa0c55ad015
+//! ```
+//!
+//! Lines are handed to a background task, so a slow disk never holds up a
+//! request.
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use bytes::Bytes;
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::restic::request_id::REQUEST_ID_HEADER;
+
+/// Handle to the access log writer; cheap to clone.
+#[derive(Clone)]
+pub struct AccessLog {
+    lines: mpsc::UnboundedSender<String>,
+}
+
+impl AccessLog {
+    /// Append to the file at `path`, or write to stdout when it is `-`.
+    pub async fn open(path: &Path) -> std::io::Result<Self> {
+        let mut out: Box<dyn tokio::io::AsyncWrite + Send + Unpin> = if path == Path::new("-") {
+            Box::new(tokio::io::stdout())
+        } else {
+            Box::new(
+                tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?,
+            )
+        };
+        let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+        tokio::spawn(async move {
+            while let Some(line) = rx.recv().await {
+                let mut buf = line;
+                // Batch whatever else is already queued into one write.
+                while let Ok(more) = rx.try_recv() {
+                    buf.push_str(&more);
+                }
+                if let Err(e) = async {
+                    out.write_all(buf.as_bytes()).await?;
+                    out.flush().await
+                }
+                .await
+                {
+                    tracing::warn!("Failed to write access log: {}", e);
+                }
+            }
+        });
+        Ok(Self { lines: tx })
+    }
+}
+
+/// Middleware recording each request in the access log.
+pub async fn record(
+    State(log): State<AccessLog>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let entry = Entry {
+        log,
+        start: Instant::now(),
+        peer: peer.map_or_else(|| "-".to_string(), |ConnectInfo(addr)| addr.to_string()),
+        request: format!("{} {} {:?}", req.method(), req.uri(), req.version()),
+        user_agent: header_or_dash(&req, header::USER_AGENT),
+        request_id: header_or_dash(&req, REQUEST_ID_HEADER),
+        status: 0,
+        bytes: 0,
+    };
+
+    let response = next.run(req).await;
+    let (parts, body) = response.into_parts();
+    let body = LoggedBody {
+        inner: body,
+        entry: Entry {
+            status: parts.status.as_u16(),
+            ..entry
+        },
+    };
+    Response::from_parts(parts, Body::new(body))
+}
+
+fn header_or_dash(req: &Request, name: header::HeaderName) -> String {
+    req.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("-")
+        .to_string()
+}
+
+struct Entry {
+    log: AccessLog,
+    start: Instant,
+    peer: String,
+    request: String,
+    user_agent: String,
+    request_id: String,
+    status: u16,
+    bytes: u64,
+}
+
+impl Entry {
+    fn line(&self) -> String {
+        format!(
+            "{} {} \"{}\" {} {} {}ms \"{}\" {}\n",
+            chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            self.peer,
+            self.request,
+            self.status,
+            self.bytes,
+            self.start.elapsed().as_millis(),
+            self.user_agent,
+            self.request_id,
+        )
+    }
+}
+
+/// Response body that counts the bytes sent and logs the request when it is
+/// dropped.
+struct LoggedBody {
+    inner: Body,
+    entry: Entry,
+}
+
+impl HttpBody for LoggedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &poll
+            && let Some(data) = frame.data_ref()
+        {
+            self.entry.bytes += data.len() as u64;
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        let _ = self.entry.log.lines.send(self.entry.line());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, middleware, routing::get};
+
+    #[tokio::test]
+    async fn test_access_log_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let log = AccessLog::open(&path).await.unwrap();
+        let app = Router::new()
+            .route("/config", get(|| async { "hello" }))
+            .layer(middleware::from_fn_with_state(log, record));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/config", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+        });
+
+        let body = reqwest::get(&url).await.unwrap().text().await.unwrap();
+        assert_eq!(body, "hello");
+
+        let mut line = String::new();
+        for _ in 0..100 {
+            line = tokio::fs::read_to_string(&path).await.unwrap();
+            if !line.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        assert!(fields[1].starts_with("127.0.0.1:"));
+        assert_eq!(fields[2..5], ["\"GET", "/config", "HTTP/1.1\""]);
+        assert_eq!(fields[5..7], ["200", "5"]);
+        assert_eq!(fields[9], "-");
+    }
+}
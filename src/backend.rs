@@ -0,0 +1,89 @@
+//! Object storage behind the restic routes.
+//!
+//! The handlers in [`crate::restic`] address restic objects by type and name
+//! through [`Backend`] rather than through a 115 client, so the router can be
+//! embedded with other implementations (an in-memory mock, a local
+//! directory, another cloud) and tested without 115 tokens.
+//! [`Open115Client`](crate::open115::Open115Client) is the implementation the
+//! server runs. The admin API, health checks and background tasks are 115
+//! specific and still use the client directly.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::StreamExt;
+use futures::stream::BoxStream;
+
+use crate::config::DuplicatePolicy;
+use crate::error::Result;
+use crate::open115::ResticFileType;
+
+/// A stored object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectInfo {
+    pub name: String,
+    pub size: u64,
+    /// Backend-specific handle passed back to [`Backend::get`] (the pick
+    /// code on 115).
+    pub id: String,
+}
+
+/// Pages of a listing, read as they are polled.
+pub type ObjectPages = BoxStream<'static, Result<Vec<ObjectInfo>>>;
+
+#[async_trait]
+pub trait Backend: Send + Sync {
+    /// Namespace of the repository in events and logs.
+    fn repo_id(&self) -> &str;
+
+    /// Path of the repository, matched against credential prefixes.
+    fn repo_path(&self) -> &str;
+
+    /// How an upload under an existing name is handled.
+    fn duplicate_policy(&self) -> DuplicatePolicy {
+        DuplicatePolicy::Proceed
+    }
+
+    /// Create the repository.
+    async fn init(&self) -> Result<()>;
+
+    /// Delete the repository; false if it did not exist.
+    async fn destroy(&self) -> Result<bool>;
+
+    /// Objects of `file_type`; empty if the repository does not exist.
+    async fn list(&self, file_type: ResticFileType) -> Result<Vec<ObjectInfo>>;
+
+    /// Like [`Backend::list`], in pages read as the stream is polled. The
+    /// default is one page holding the whole listing.
+    async fn list_pages(&self, file_type: ResticFileType) -> Result<ObjectPages> {
+        let objects = self.list(file_type).await?;
+        Ok(futures::stream::once(async { Ok(objects) }).boxed())
+    }
+
+    async fn stat(&self, file_type: ResticFileType, name: &str) -> Result<Option<ObjectInfo>>;
+
+    /// Whether the object is known to exist. Unlike [`Backend::stat`] it may
+    /// answer from a cache alone.
+    async fn exists(&self, file_type: ResticFileType, name: &str) -> Result<bool> {
+        Ok(self.stat(file_type, name).await?.is_some())
+    }
+
+    /// Content of `object`, or of the inclusive byte `range` of it.
+    async fn get(&self, object: &ObjectInfo, range: Option<(u64, u64)>) -> Result<Bytes>;
+
+    /// Size of `object` as the storage itself reports it, rather than a
+    /// cached listing; `None` if it does not say. The default trusts
+    /// `object.size`.
+    async fn reported_size(&self, object: &ObjectInfo) -> Result<Option<u64>> {
+        Ok(Some(object.size))
+    }
+
+    /// SHA-1 (lowercase hex) of `object`, if known without another call.
+    async fn known_sha1(&self, _object: &ObjectInfo) -> Option<String> {
+        None
+    }
+
+    async fn put(&self, file_type: ResticFileType, name: &str, data: Bytes) -> Result<()>;
+
+    /// Delete the object; deleting a missing object is not an error.
+    async fn delete(&self, file_type: ResticFileType, name: &str) -> Result<()>;
+}
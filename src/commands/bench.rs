@@ -0,0 +1,265 @@
+//! `bench`: measure upload and download throughput to 115.
+//!
+//! For each size, `--count` blobs of random bytes are uploaded with
+//! `--concurrency` transfers in flight, then downloaded back and compared.
+//! Random content keeps 115's fast upload (content it already knows) from
+//! short-circuiting the transfer. The blobs go to a fresh folder, deleted
+//! afterwards unless `--keep` is given. Besides MB/s and latency percentiles
+//! the report counts what went wrong along the way: calls 115 refused or
+//! that had to be retried, and transfers that failed outright.
+
+use anyhow::bail;
+use bytes::Bytes;
+use futures::StreamExt;
+use sha1::Digest;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::config::{BenchArgs, Config};
+use crate::error::AppError;
+use crate::open115::Open115Client;
+
+/// Outcome of one direction of transfers at one size.
+#[derive(Debug, Default)]
+struct Phase {
+    latencies: Vec<Duration>,
+    bytes: u64,
+    elapsed: Duration,
+    /// Retries and failures by kind.
+    errors: BTreeMap<String, usize>,
+}
+
+impl Phase {
+    fn add(&mut self, size: u64, latency: Duration, retries: &[String], failure: Option<String>) {
+        for retry in retries {
+            *self.errors.entry(retry_kind(retry)).or_default() += 1;
+        }
+        match failure {
+            Some(kind) => *self.errors.entry(kind).or_default() += 1,
+            None => {
+                self.latencies.push(latency);
+                self.bytes += size;
+            }
+        }
+    }
+
+    fn report(&mut self, label: &str, size: u64) {
+        self.latencies.sort_unstable();
+        let secs = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        let ms = |p: f64| percentile(&self.latencies, p).as_secs_f64() * 1000.0;
+        let errors = if self.errors.is_empty() {
+            "none".to_string()
+        } else {
+            self.errors
+                .iter()
+                .map(|(kind, n)| format!("{kind}={n}"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        println!(
+            "{label:<8} {size:>10} B  {:>3} ok  {:>8.2} MB/s  p50 {:>7.0} ms  p90 {:>7.0} ms  p99 {:>7.0} ms  max {:>7.0} ms  errors: {errors}",
+            self.latencies.len(),
+            self.bytes as f64 / 1e6 / secs,
+            ms(0.5),
+            ms(0.9),
+            ms(0.99),
+            ms(1.0),
+        );
+    }
+}
+
+pub async fn run(config: &Config, args: &BenchArgs) -> anyhow::Result<()> {
+    if args.size.is_empty() || args.count == 0 {
+        bail!("bench: nothing to do");
+    }
+    let path = args.path.clone().unwrap_or_else(|| {
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        format!(
+            "{}/.restic-115-bench-{}",
+            config.repo_path.trim_end_matches('/'),
+            &id[..8]
+        )
+    });
+    // A client rooted at the scratch folder, so deleting its "repository"
+    // removes exactly what the bench created.
+    let mut scratch = config.clone();
+    scratch.repo_path = path.clone();
+    let client = Open115Client::new(scratch).await?;
+    if client.find_path_id(&path).await?.is_some() {
+        bail!("bench: {path} already exists; pick a new --path");
+    }
+    let dir_id = client.ensure_path(&path, true).await?;
+    tracing::info!(
+        "bench: {} blobs per size, {} in flight, in {}",
+        args.count,
+        args.concurrency,
+        path
+    );
+
+    let result = bench_all(&client, &dir_id, args).await;
+    if args.keep {
+        tracing::info!("bench: kept {}", path);
+    } else if let Err(e) = client.delete_repository().await {
+        tracing::warn!("bench: failed to delete {}: {}", path, e);
+    }
+    result
+}
+
+async fn bench_all(client: &Open115Client, dir_id: &str, args: &BenchArgs) -> anyhow::Result<()> {
+    for (n, &size) in args.size.iter().enumerate() {
+        let blobs: Vec<(String, Bytes)> = (0..args.count)
+            .map(|i| (format!("blob-{n}-{i}"), random_blob(size as usize)))
+            .collect();
+
+        let mut upload = Phase::default();
+        let start = Instant::now();
+        let uploads: Vec<_> = futures::stream::iter(&blobs)
+            .map(|(name, data)| async move {
+                let started = Instant::now();
+                let (result, retries) =
+                    Open115Client::with_retry_log(client.upload_file(dir_id, name, data.clone()))
+                        .await;
+                (started.elapsed(), retries, result.err().map(failure_kind))
+            })
+            .buffer_unordered(args.concurrency.max(1))
+            .collect()
+            .await;
+        upload.elapsed = start.elapsed();
+        for (latency, retries, failure) in uploads {
+            upload.add(size, latency, &retries, failure);
+        }
+        upload.report("upload", size);
+
+        let mut download = Phase::default();
+        let start = Instant::now();
+        let downloads: Vec<_> = futures::stream::iter(&blobs)
+            .map(|(name, data)| async move {
+                let started = Instant::now();
+                let (result, retries) =
+                    Open115Client::with_retry_log(download_one(client, dir_id, name, data)).await;
+                (started.elapsed(), retries, result.err())
+            })
+            .buffer_unordered(args.concurrency.max(1))
+            .collect()
+            .await;
+        download.elapsed = start.elapsed();
+        for (latency, retries, failure) in downloads {
+            download.add(size, latency, &retries, failure);
+        }
+        download.report("download", size);
+    }
+    Ok(())
+}
+
+/// Download one blob and compare it with what was uploaded; `Err` carries
+/// the failure kind.
+async fn download_one(
+    client: &Open115Client,
+    dir_id: &str,
+    name: &str,
+    expected: &Bytes,
+) -> Result<(), String> {
+    let file = client
+        .find_file(dir_id, name)
+        .await
+        .map_err(failure_kind)?
+        .ok_or_else(|| "failed_missing".to_string())?;
+    let data = client
+        .download_whole_file(&file.pick_code, expected.len() as u64)
+        .await
+        .map_err(failure_kind)?;
+    if sha1::Sha1::digest(&data) != sha1::Sha1::digest(expected) {
+        return Err("failed_corrupt".to_string());
+    }
+    Ok(())
+}
+
+/// Kind of a retry noted by the client, e.g. `retry_429`.
+fn retry_kind(event: &str) -> String {
+    if event.contains("HTTP 429") {
+        "retry_429".to_string()
+    } else if event.contains("token") {
+        "token_refresh".to_string()
+    } else if let Some(code) = event
+        .split("code=")
+        .nth(1)
+        .and_then(|rest| rest.split([',', ')']).next())
+    {
+        format!("retry_{code}")
+    } else if event.starts_with("OSS") {
+        "retry_oss".to_string()
+    } else {
+        "retry_other".to_string()
+    }
+}
+
+/// Kind of a failed transfer, e.g. `failed_406`.
+fn failure_kind(error: AppError) -> String {
+    match error {
+        AppError::Open115Api { code, .. } => format!("failed_{code}"),
+        AppError::HttpClient(_) => "failed_http".to_string(),
+        AppError::Timeout { .. } => "failed_timeout".to_string(),
+        _ => "failed_other".to_string(),
+    }
+}
+
+/// Nearest-rank percentile of sorted `latencies`.
+fn percentile(latencies: &[Duration], p: f64) -> Duration {
+    if latencies.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p * latencies.len() as f64).ceil() as usize;
+    latencies[rank.clamp(1, latencies.len()) - 1]
+}
+
+/// `size` pseudo-random bytes, different on every call.
+fn random_blob(size: usize) -> Bytes {
+    // xorshift64*, seeded from a random UUID.
+    let mut state = (uuid::Uuid::new_v4().as_u128() as u64) | 1;
+    let mut data = Vec::with_capacity(size + 8);
+    while data.len() < size {
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        data.extend_from_slice(&state.wrapping_mul(0x2545_F491_4F6C_DD1D).to_le_bytes());
+    }
+    data.truncate(size);
+    Bytes::from(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_helpers() {
+        let ms = Duration::from_millis;
+        let sorted: Vec<Duration> = (1..=10).map(ms).collect();
+        assert_eq!(percentile(&sorted, 0.5), ms(5));
+        assert_eq!(percentile(&sorted, 0.99), ms(10));
+        assert_eq!(percentile(&sorted, 0.0), ms(1));
+        assert_eq!(percentile(&[], 0.5), Duration::ZERO);
+
+        assert_eq!(
+            retry_kind("GET https://x/a: HTTP 429 (attempt 1)"),
+            "retry_429"
+        );
+        assert_eq!(
+            retry_kind("POST https://x/b: rate limited (code=406, attempt 2)"),
+            "retry_406"
+        );
+        assert_eq!(
+            retry_kind("GET https://x/c: token invalid (code=40140125), refreshed token"),
+            "token_refresh"
+        );
+        assert_eq!(
+            retry_kind("GET https://x/d: HTTP 401, refreshed token"),
+            "token_refresh"
+        );
+        assert_eq!(retry_kind("OSS upload failed: reset"), "retry_oss");
+
+        let (a, b) = (random_blob(1001), random_blob(1001));
+        assert_eq!(a.len(), 1001);
+        assert_ne!(a, b);
+    }
+}
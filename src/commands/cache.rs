@@ -0,0 +1,175 @@
+//! `cache export` / `cache import`: a portable snapshot of the cache DB.
+//!
+//! The snapshot is a JSON Lines file: a header line, then one line per row
+//! of the `tokens` and `file_nodes` tables, for every repository namespace
+//! in `DB_PATH`. Importing it on another host (or after a risky operation)
+//! gives a server a warm cache without listing 115 again. Stop the server
+//! using `DB_PATH` first; an import replaces the cached tree of each
+//! repository in the file while the server may be writing to it.
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufWriter, Write};
+use std::path::Path;
+
+use crate::config::{CacheCommand, Config};
+use crate::open115::database::{self, CacheRow, DbPool, create_db_dir, init_db_with, sqlite_url};
+use crate::open115::repo_namespace;
+
+const FORMAT: &str = "restic-115-cache";
+const VERSION: u32 = 1;
+
+/// First line of a snapshot.
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    format: String,
+    version: u32,
+    exported_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Open the cache DB at `DB_PATH`, creating or upgrading its schema.
+pub(crate) async fn open_db(config: &Config) -> anyhow::Result<sea_orm::DatabaseConnection> {
+    create_db_dir(&config.db_path)
+        .with_context(|| format!("create the directory of {}", config.db_path))?;
+    init_db_with(
+        &sqlite_url(&config.db_path, "rwc"),
+        &repo_namespace(&config.repo_path),
+        &DbPool::from_config(config).context("read the DB key")?,
+    )
+    .await
+    .with_context(|| format!("open {}", config.db_path))
+}
+
+pub async fn run(config: &Config, command: &CacheCommand) -> anyhow::Result<()> {
+    let db = open_db(config).await?;
+    match command {
+        CacheCommand::Export { file } => {
+            let rows = export(&db, file).await?;
+            println!("exported {rows} rows to {}", file.display());
+        }
+        CacheCommand::Import { file } => {
+            let rows = import(&db, file).await?;
+            println!("imported {rows} rows from {}", file.display());
+        }
+    }
+    Ok(())
+}
+
+async fn export(db: &sea_orm::DatabaseConnection, path: &Path) -> anyhow::Result<usize> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let file = options
+        .open(path)
+        .with_context(|| format!("create {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+    let header = Header {
+        format: FORMAT.to_string(),
+        version: VERSION,
+        exported_at: chrono::Utc::now(),
+    };
+    serde_json::to_writer(&mut out, &header)?;
+    out.write_all(b"\n")?;
+    let rows = database::export_cache(db, |row: &CacheRow| -> anyhow::Result<()> {
+        serde_json::to_writer(&mut out, row)?;
+        out.write_all(b"\n")?;
+        Ok(())
+    })
+    .await?;
+    out.into_inner()?.sync_all()?;
+    Ok(rows)
+}
+
+async fn import(db: &sea_orm::DatabaseConnection, path: &Path) -> anyhow::Result<usize> {
+    let file = std::fs::File::open(path).with_context(|| format!("open {}", path.display()))?;
+    let mut lines = std::io::BufReader::new(file).lines();
+    let header: Header = match lines.next() {
+        Some(line) => serde_json::from_str(&line?).context("snapshot header")?,
+        None => bail!("{} is empty", path.display()),
+    };
+    if header.format != FORMAT || header.version != VERSION {
+        bail!(
+            "{} is not a cache snapshot this version can read ({} v{})",
+            path.display(),
+            header.format,
+            header.version
+        );
+    }
+    let rows = lines
+        .enumerate()
+        .map(|(n, line)| -> anyhow::Result<CacheRow> {
+            serde_json::from_str(&line?).with_context(|| format!("{}:{}", path.display(), n + 2))
+        });
+    database::import_cache(db, rows).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::open115::database::entities::{file_nodes, tokens};
+    use crate::open115::database::init_db;
+    use sea_orm::{ActiveModelTrait, EntityTrait, IntoActiveModel};
+
+    #[tokio::test]
+    async fn test_export_import() {
+        let dir = tempfile::tempdir().unwrap();
+        let open = |name: &str| {
+            let url = sqlite_url(&dir.path().join(name).to_string_lossy(), "rwc");
+            async move { init_db(&url, "/repo").await.unwrap() }
+        };
+        let node = |repo: &str, id: &str| file_nodes::Model {
+            repo: repo.to_string(),
+            file_id: id.to_string(),
+            parent_id: "0".to_string(),
+            name: format!("n{id}"),
+            is_dir: false,
+            size: 1,
+            pick_code: format!("p{id}"),
+            sha1: None,
+        };
+
+        let src = open("src.db").await;
+        tokens::Model {
+            id: 1,
+            access_token: "a".to_string(),
+            refresh_token: "r".to_string(),
+            updated_at: chrono::Utc::now(),
+            expires_at: None,
+        }
+        .into_active_model()
+        .insert(&src)
+        .await
+        .unwrap();
+        for i in 0..1200 {
+            node("/repo", &i.to_string())
+                .into_active_model()
+                .insert(&src)
+                .await
+                .unwrap();
+        }
+        let snapshot = dir.path().join("cache.jsonl");
+        assert_eq!(export(&src, &snapshot).await.unwrap(), 1201);
+        assert!(export(&src, &snapshot).await.is_err(), "never overwrites");
+
+        // Rows of the imported namespace are replaced, others are kept.
+        let dst = open("dst.db").await;
+        for (repo, id) in [("/repo", "stale"), ("/other", "kept")] {
+            node(repo, id)
+                .into_active_model()
+                .insert(&dst)
+                .await
+                .unwrap();
+        }
+        assert_eq!(import(&dst, &snapshot).await.unwrap(), 1201);
+        let nodes = file_nodes::Entity::find().all(&dst).await.unwrap();
+        assert_eq!(nodes.len(), 1201);
+        assert!(nodes.iter().any(|n| n.file_id == "kept"));
+        assert!(!nodes.iter().any(|n| n.file_id == "stale"));
+        let token = tokens::Entity::find_by_id(1).one(&dst).await.unwrap();
+        assert_eq!(token.unwrap().refresh_token, "r");
+
+        std::fs::write(dir.path().join("bad.jsonl"), "{}\n").unwrap();
+        assert!(import(&dst, &dir.path().join("bad.jsonl")).await.is_err());
+    }
+}
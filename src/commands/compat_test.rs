@@ -0,0 +1,265 @@
+//! `compat-test`: run the same repository scenario with several restic releases.
+//!
+//! The server runs against the in-process 115 mock, so no 115 account is
+//! needed. Downloaded restic releases are checked against the release's
+//! `SHA256SUMS` before they are run.
+
+use anyhow::{Context, bail};
+use sha2::Digest;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::{Child, Command};
+
+use super::harness;
+use crate::config::CompatTestArgs;
+use crate::mock115::Mock115;
+
+/// restic releases exercised when no `--restic-version` is given.
+const PINNED_RESTIC_VERSIONS: &[&str] = &["0.16.5", "0.17.3", "0.18.0"];
+
+const SERVER_READY_TIMEOUT: Duration = Duration::from_secs(120);
+const PASSWORD: &str = "restic-115-compat";
+
+pub async fn run(args: &CompatTestArgs) -> anyhow::Result<()> {
+    let versions: Vec<String> = if args.restic_versions.is_empty() {
+        PINNED_RESTIC_VERSIONS
+            .iter()
+            .map(|v| v.to_string())
+            .collect()
+    } else {
+        args.restic_versions.clone()
+    };
+
+    let mock = Mock115::start().await.context("start the 115 mock")?;
+    let mut failures = Vec::new();
+    for version in &versions {
+        tracing::info!("compat-test: restic {version}");
+        match run_one(&mock, args, version).await {
+            Ok(()) => tracing::info!("compat-test: restic {version} passed"),
+            Err(e) => {
+                tracing::error!("compat-test: restic {version} failed: {e:#}");
+                failures.push(version.clone());
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        bail!(
+            "compat-test failed for restic {} of {} versions",
+            failures.join(", "),
+            versions.len()
+        );
+    }
+    tracing::info!("compat-test: all {} restic versions passed", versions.len());
+    Ok(())
+}
+
+async fn run_one(mock: &Mock115, args: &CompatTestArgs, version: &str) -> anyhow::Result<()> {
+    let restic_bin = ensure_restic(&args.restic_cache_dir, version).await?;
+
+    let work = tempfile::tempdir()?;
+    let source = work.path().join("source");
+    let restore = work.path().join("restore");
+    harness::create_sample_files(&source)?;
+
+    let repo_path = format!(
+        "{}-{}-{}",
+        args.repo_prefix.trim_end_matches('/'),
+        version,
+        chrono::Utc::now().timestamp_millis()
+    );
+    let mut server = ServerProcess::spawn(mock, &repo_path).await?;
+    let result = async {
+        server.wait_ready(SERVER_READY_TIMEOUT).await?;
+        let repo = server.repo_url();
+        let source_arg = source.to_string_lossy();
+        let restore_arg = restore.to_string_lossy();
+        harness::restic(&restic_bin, &repo, PASSWORD, &["init"]).await?;
+        harness::restic(&restic_bin, &repo, PASSWORD, &["backup", &source_arg]).await?;
+        harness::restic(&restic_bin, &repo, PASSWORD, &["check", "--read-data"]).await?;
+        harness::restic(
+            &restic_bin,
+            &repo,
+            PASSWORD,
+            &["restore", "latest", "--target", &restore_arg],
+        )
+        .await?;
+
+        let restored = harness::find_restored_dir(&restore, "source")?;
+        if harness::hash_tree(&source)? != harness::hash_tree(&restored)? {
+            bail!("restored files differ from the backup source");
+        }
+        Ok(())
+    }
+    .await;
+    server.shutdown().await;
+    result
+}
+
+/// A restic-115 server child process listening on a loopback port.
+struct ServerProcess {
+    child: Child,
+    port: u16,
+}
+
+impl ServerProcess {
+    /// Start the current executable in server mode for `repo_path`, talking
+    /// to `mock` instead of 115, so no 115 account or tokens are involved.
+    async fn spawn(mock: &Mock115, repo_path: &str) -> anyhow::Result<Self> {
+        let port = free_port()?;
+        let exe = std::env::current_exe().context("locate restic-115 executable")?;
+        let config = mock.config(repo_path);
+        let resolve: Vec<String> = config
+            .resolve
+            .iter()
+            .map(|r| format!("{}:{}", r.host, r.addr))
+            .collect();
+        let mut cmd = Command::new(exe);
+        cmd.args([
+            "--access-token",
+            config.access_token.as_deref().unwrap_or_default(),
+            "--refresh-token",
+            config.refresh_token.as_deref().unwrap_or_default(),
+            "--api-base",
+            &config.api_base,
+            "--resolve",
+            &resolve.join(","),
+            "--db-path",
+            &config.db_path,
+            "--repo-path",
+            repo_path,
+            "--response-parsing",
+            "strict",
+            "--listen-addr",
+            "127.0.0.1",
+            "--listen-port",
+            &port.to_string(),
+        ])
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+        let child = cmd.spawn().context("spawn restic-115 server")?;
+        Ok(Self { child, port })
+    }
+
+    fn repo_url(&self) -> String {
+        format!("rest:http://127.0.0.1:{}/", self.port)
+    }
+
+    /// Wait until the server answers HTTP requests (warm-up may take a while).
+    async fn wait_ready(&mut self, timeout: Duration) -> anyhow::Result<()> {
+        let url = format!("http://127.0.0.1:{}/config", self.port);
+        let deadline = tokio::time::Instant::now() + timeout;
+        while tokio::time::Instant::now() < deadline {
+            if let Some(status) = self.child.try_wait()? {
+                bail!("restic-115 server exited during startup: {status}");
+            }
+            if reqwest::Client::new().head(&url).send().await.is_ok() {
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        bail!("restic-115 server not ready after {timeout:?}")
+    }
+
+    async fn shutdown(mut self) {
+        let _ = self.child.kill().await;
+    }
+}
+
+fn free_port() -> anyhow::Result<u16> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    Ok(listener.local_addr()?.port())
+}
+
+/// Return the path of the restic `version` binary, downloading it if needed.
+async fn ensure_restic(cache_dir: &Path, version: &str) -> anyhow::Result<PathBuf> {
+    let (os, arch) = release_platform()?;
+    let name = format!("restic_{version}_{os}_{arch}");
+    let bin = cache_dir.join(&name);
+    if bin.exists() {
+        return Ok(bin);
+    }
+
+    let release = format!("https://github.com/restic/restic/releases/download/v{version}");
+    let file = format!("{name}.bz2");
+    let url = format!("{release}/{file}");
+    let sums = reqwest::get(format!("{release}/SHA256SUMS"))
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let Some(expected) = release_checksum(&sums, &file) else {
+        bail!("{file} is not listed in the SHA256SUMS of restic {version}");
+    };
+    tracing::info!("Downloading {url}");
+    let resp = reqwest::get(&url).await?.error_for_status()?;
+    let compressed = resp.bytes().await?;
+    let actual = hex::encode(sha2::Sha256::digest(&compressed));
+    if !actual.eq_ignore_ascii_case(expected) {
+        bail!("{url} has SHA-256 {actual}, but the release lists {expected}");
+    }
+    let mut binary = Vec::new();
+    bzip2::read::BzDecoder::new(compressed.as_ref())
+        .read_to_end(&mut binary)
+        .with_context(|| format!("decompress {url}"))?;
+
+    std::fs::create_dir_all(cache_dir)?;
+    let partial = cache_dir.join(format!("{name}.partial"));
+    std::fs::write(&partial, binary)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&partial, std::fs::Permissions::from_mode(0o755))?;
+    }
+    std::fs::rename(&partial, &bin)?;
+    Ok(bin)
+}
+
+/// The SHA-256 that a release's `SHA256SUMS` (`<hex>  <file>` lines) lists
+/// for `file`.
+fn release_checksum<'a>(sums: &'a str, file: &str) -> Option<&'a str> {
+    sums.lines().find_map(|line| {
+        let (sum, name) = line.split_once(char::is_whitespace)?;
+        (name.trim_start().trim_start_matches('*') == file).then_some(sum)
+    })
+}
+
+/// restic release naming for the host platform.
+fn release_platform() -> anyhow::Result<(&'static str, &'static str)> {
+    let os = match std::env::consts::OS {
+        "linux" => "linux",
+        "macos" => "darwin",
+        "freebsd" => "freebsd",
+        "openbsd" => "openbsd",
+        other => bail!("no bz2 restic release for OS {other}"),
+    };
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "x86" => "386",
+        "aarch64" => "arm64",
+        "arm" => "arm",
+        other => bail!("no restic release for architecture {other}"),
+    };
+    Ok((os, arch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_release_checksum() {
+        let sums = "\
+0123abcd  restic_0.18.0_darwin_arm64.bz2
+4567ef01  restic_0.18.0_linux_amd64.bz2
+89ab *restic_0.18.0_linux_arm64.bz2
+";
+        let sum = |file| release_checksum(sums, file);
+        assert_eq!(sum("restic_0.18.0_linux_amd64.bz2"), Some("4567ef01"));
+        assert_eq!(sum("restic_0.18.0_linux_arm64.bz2"), Some("89ab"));
+        assert_eq!(sum("restic_0.18.0_linux_386.bz2"), None);
+        assert_eq!(sum("restic_0.18.0_linux"), None);
+    }
+}
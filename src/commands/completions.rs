@@ -0,0 +1,113 @@
+//! `completions` and `--help-json`: the command-line surface for shells and
+//! for scripts (wrappers, Ansible roles, config generators) that need to
+//! stay in sync with the flags of the installed version.
+
+use clap::{ArgAction, CommandFactory};
+use serde_json::{Value, json};
+
+use crate::config::{CompletionsArgs, Config, ENV_ALIASES};
+
+pub fn run(args: &CompletionsArgs) {
+    clap_complete::generate(
+        args.shell,
+        &mut Config::command(),
+        "restic-115",
+        &mut std::io::stdout(),
+    );
+}
+
+/// Every option and subcommand, with environment variables and defaults.
+pub fn help_json() -> Value {
+    let mut root = describe(&Config::command());
+    root["version"] = env!("CARGO_PKG_VERSION").into();
+    root["deprecated_env"] = ENV_ALIASES
+        .iter()
+        .map(|a| (a.deprecated.to_string(), Value::from(a.canonical)))
+        .collect::<serde_json::Map<_, _>>()
+        .into();
+    root
+}
+
+fn describe(command: &clap::Command) -> Value {
+    let options: Vec<Value> = command
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set())
+        .map(|arg| {
+            let string = |s: Option<&std::ffi::OsStr>| s.map(|s| s.to_string_lossy().into_owned());
+            let defaults: Vec<String> = arg
+                .get_default_values()
+                .iter()
+                .map(|v| v.to_string_lossy().into_owned())
+                .collect();
+            let values: Vec<String> = arg
+                .get_possible_values()
+                .iter()
+                .filter(|v| !v.is_hide_set())
+                .map(|v| v.get_name().to_string())
+                .collect();
+            json!({
+                "name": arg.get_id().as_str(),
+                "long": arg.get_long().map(|l| format!("--{l}")),
+                "short": arg.get_short().map(|s| format!("-{s}")),
+                "positional": arg.is_positional(),
+                "env": string(arg.get_env()),
+                "default": match defaults.len() {
+                    0 => Value::Null,
+                    1 => defaults[0].clone().into(),
+                    _ => defaults.into(),
+                },
+                "possible_values": values,
+                "takes_value": arg.get_action().takes_values(),
+                "multiple": matches!(arg.get_action(), ArgAction::Append),
+                "required": arg.is_required_set(),
+                "help": arg.get_long_help().or(arg.get_help()).map(|h| h.to_string()),
+            })
+        })
+        .collect();
+    let subcommands: Vec<Value> = command.get_subcommands().map(describe).collect();
+    json!({
+        "name": command.get_name(),
+        "about": command.get_long_about().or(command.get_about()).map(|a| a.to_string()),
+        "options": options,
+        "subcommands": subcommands,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_help_json() {
+        let help = help_json();
+        let option = |options: &Value, name: &str| {
+            options
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|o| o["name"] == name)
+                .cloned()
+                .unwrap()
+        };
+        let repo_path = option(&help["options"], "repo_path");
+        assert_eq!(repo_path["long"], "--repo-path");
+        assert_eq!(repo_path["env"], "OPEN115_REPO_PATH");
+        assert_eq!(repo_path["default"], "/restic-backup");
+
+        let policy = option(&help["options"], "duplicate_policy");
+        assert_eq!(
+            policy["possible_values"],
+            json!(["proceed", "overwrite", "reject"])
+        );
+
+        let import = help["subcommands"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|c| c["name"] == "import")
+            .unwrap();
+        let source = option(&import["options"], "source");
+        assert_eq!(source["positional"], true);
+        assert_eq!(source["required"], true);
+    }
+}
@@ -0,0 +1,28 @@
+//! `db maintain`: checkpoint, analyze and vacuum the cache DB.
+//!
+//! A long-running server does the same every `OPEN115_DB_MAINTAIN_INTERVAL`
+//! minutes. Run by hand, it can share `DB_PATH` with a running server;
+//! `VACUUM` then holds the server's writes until it finishes.
+
+use super::cache::open_db;
+use crate::config::{Config, DbCommand};
+use crate::open115::database;
+
+pub async fn run(config: &Config, command: &DbCommand) -> anyhow::Result<()> {
+    match command {
+        DbCommand::Maintain => {
+            let db = open_db(config).await?;
+            let report = database::maintain(&db).await?;
+            let mib = |pages: i64| (pages * report.page_size) as f64 / (1 << 20) as f64;
+            println!(
+                "{}: {:.1} MiB -> {:.1} MiB ({} free pages reclaimed), {} WAL frames checkpointed",
+                config.db_path,
+                mib(report.pages_before),
+                mib(report.pages_after),
+                report.free_pages_before,
+                report.wal_frames
+            );
+        }
+    }
+    Ok(())
+}
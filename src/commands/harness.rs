@@ -0,0 +1,105 @@
+//! Helpers for driving the restic CLI against a restic-115 server.
+
+use anyhow::{Context, bail};
+use sha1::Digest;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::process::Command;
+
+/// Run `restic <args>` against `repo_url`, failing with its stderr on error.
+pub async fn restic(
+    restic_bin: &Path,
+    repo_url: &str,
+    password: &str,
+    args: &[&str],
+) -> anyhow::Result<String> {
+    let output = Command::new(restic_bin)
+        .args(["-r", repo_url])
+        .args(args)
+        .env("RESTIC_PASSWORD", password)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .with_context(|| format!("run {} {}", restic_bin.display(), args.join(" ")))?;
+    if !output.status.success() {
+        bail!(
+            "restic {} failed ({}): {}",
+            args.first().unwrap_or(&""),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Fill `dir` with a small, deterministic but incompressible file set.
+pub fn create_sample_files(dir: &Path) -> anyhow::Result<()> {
+    let sizes = [0usize, 1, 4096, 300 * 1024, 5 * 1024 * 1024];
+    std::fs::create_dir_all(dir.join("nested"))?;
+    for (i, size) in sizes.iter().enumerate() {
+        let name = if i % 2 == 0 {
+            PathBuf::from(format!("file_{i}.bin"))
+        } else {
+            Path::new("nested").join(format!("file_{i}.bin"))
+        };
+        std::fs::write(dir.join(name), pseudo_random_bytes(i as u64, *size))?;
+    }
+    Ok(())
+}
+
+/// SHA-1 counter stream; cheap, portable and does not compress.
+fn pseudo_random_bytes(seed: u64, len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len + 20);
+    let mut counter = 0u64;
+    while out.len() < len {
+        let mut hasher = sha1::Sha1::new();
+        hasher.update(seed.to_le_bytes());
+        hasher.update(counter.to_le_bytes());
+        out.extend_from_slice(&hasher.finalize());
+        counter += 1;
+    }
+    out.truncate(len);
+    out
+}
+
+/// SHA-1 of every file below `root`, keyed by path relative to `root`.
+pub fn hash_tree(root: &Path) -> anyhow::Result<BTreeMap<PathBuf, String>> {
+    let mut hashes = BTreeMap::new();
+    let mut stack = vec![root.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                stack.push(path);
+            } else {
+                let data = std::fs::read(&path)?;
+                let rel = path.strip_prefix(root)?.to_path_buf();
+                hashes.insert(rel, hex::encode(sha1::Sha1::digest(&data)));
+            }
+        }
+    }
+    Ok(hashes)
+}
+
+/// restic restores the absolute source path below the target; find the
+/// directory named `name` inside `target`.
+pub fn find_restored_dir(target: &Path, name: &str) -> anyhow::Result<PathBuf> {
+    let mut stack = vec![target.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                if entry.file_name() == name {
+                    return Ok(entry.path());
+                }
+                stack.push(entry.path());
+            }
+        }
+    }
+    bail!(
+        "restored directory {name} not found in {}",
+        target.display()
+    )
+}
@@ -0,0 +1,28 @@
+//! `healthcheck`: query a running server's `/healthz` and exit non-zero
+//! unless it is healthy, so container images need no curl.
+
+use anyhow::bail;
+use std::time::Duration;
+
+use crate::config::HealthcheckArgs;
+
+pub async fn run(args: &HealthcheckArgs) -> anyhow::Result<()> {
+    let url = format!("{}/healthz", args.url.trim_end_matches('/'));
+    let http = reqwest::Client::builder()
+        .timeout(Duration::from_secs(args.timeout))
+        .danger_accept_invalid_certs(args.insecure)
+        // The server is local; a proxy from the environment would only get in the way.
+        .no_proxy()
+        .build()?;
+    let resp = match http.get(&url).send().await {
+        Ok(resp) => resp,
+        Err(e) => bail!("{url}: {e}"),
+    };
+    let status = resp.status();
+    let body = resp.text().await.unwrap_or_default();
+    if !status.is_success() {
+        bail!("{url}: HTTP {status}: {body}");
+    }
+    println!("{body}");
+    Ok(())
+}
@@ -0,0 +1,315 @@
+//! `import`: copy an existing local restic repository into 115.
+//!
+//! Objects are uploaded one type at a time: packs, then indexes, snapshots,
+//! keys and finally `config`, so an interrupted import never leaves a
+//! repository on 115 that restic can open but that references missing packs.
+//!
+//! Progress is checkpointed per object in the cache DB: the bytes seen on
+//! 115 and whether 115 reported the same SHA-1 as the local file. A re-run
+//! skips verified objects, verifies ones that were uploaded but not yet
+//! checked, and uploads everything else again, so an interrupted import
+//! resumes where the previous one stopped.
+
+use anyhow::{Context, bail};
+use bytes::Bytes;
+use futures::StreamExt;
+use sha1::Digest;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, ImportArgs};
+use crate::open115::{FileInfo, Open115Client, ResticFileType};
+
+/// Upload order; see the module docs.
+const PHASES: &[ResticFileType] = &[
+    ResticFileType::Data,
+    ResticFileType::Index,
+    ResticFileType::Snapshots,
+    ResticFileType::Keys,
+    ResticFileType::Config,
+];
+
+/// One object of the local repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LocalObject {
+    file_type: ResticFileType,
+    name: String,
+    path: PathBuf,
+    size: u64,
+}
+
+enum Outcome {
+    Uploaded(u64),
+    Skipped,
+}
+
+pub async fn run(config: &Config, args: &ImportArgs) -> anyhow::Result<()> {
+    let objects = scan(&args.source)?;
+    let total_bytes: u64 = objects.iter().map(|o| o.size).sum();
+    tracing::info!(
+        "import: {} objects ({} bytes) from {} into {}",
+        objects.len(),
+        total_bytes,
+        args.source.display(),
+        config.repo_path
+    );
+
+    let client = Open115Client::new(config.clone()).await?;
+    client.warm_cache(config.force_cache_rebuild).await?;
+    client.init_repository().await?;
+
+    let mut uploaded = 0usize;
+    let mut uploaded_bytes = 0u64;
+    let mut skipped = 0usize;
+    for &phase in PHASES {
+        let batch: Vec<&LocalObject> = objects.iter().filter(|o| o.file_type == phase).collect();
+        if batch.is_empty() {
+            continue;
+        }
+        tracing::info!("import: {} {} objects", batch.len(), phase.dirname());
+        let results: Vec<_> = futures::stream::iter(batch)
+            .map(|object| {
+                let client = &client;
+                async move { (object, import_one(client, object).await) }
+            })
+            .buffer_unordered(args.concurrency.max(1))
+            .collect()
+            .await;
+
+        let mut failed = 0usize;
+        for (object, result) in results {
+            match result {
+                Ok(Outcome::Uploaded(size)) => {
+                    uploaded += 1;
+                    uploaded_bytes += size;
+                }
+                Ok(Outcome::Skipped) => skipped += 1,
+                Err(e) => {
+                    tracing::error!("import: {} failed: {e:#}", object.path.display());
+                    failed += 1;
+                }
+            }
+        }
+        if failed > 0 {
+            // Later phases would reference what is missing; re-run to resume.
+            bail!(
+                "import: {failed} {} objects failed; re-run to resume",
+                phase.dirname()
+            );
+        }
+    }
+
+    tracing::info!(
+        "import: done, {} uploaded ({} bytes), {} already present",
+        uploaded,
+        uploaded_bytes,
+        skipped
+    );
+    Ok(())
+}
+
+async fn import_one(client: &Open115Client, object: &LocalObject) -> anyhow::Result<Outcome> {
+    let dir_id = if object.file_type == ResticFileType::Data {
+        client.get_data_file_dir_id(&object.name).await?
+    } else {
+        client.get_type_dir_id(object.file_type).await?
+    };
+    let checkpoint = client
+        .import_checkpoint(object.file_type, &object.name)
+        .await?;
+    let existing = client.find_file(&dir_id, &object.name).await?;
+    if let (Some(checkpoint), Some(existing)) = (&checkpoint, &existing)
+        && checkpoint.verified
+        && checkpoint.size as u64 == object.size
+        && existing.size as u64 == object.size
+    {
+        tracing::debug!("import: {} already verified", object.name);
+        return Ok(Outcome::Skipped);
+    }
+
+    let data = tokio::fs::read(&object.path)
+        .await
+        .with_context(|| format!("read {}", object.path.display()))?;
+    if data.len() as u64 != object.size {
+        bail!("{} changed during import", object.path.display());
+    }
+    let sha1 = hex::encode(sha1::Sha1::digest(&data));
+
+    // Present from an earlier run (or uploaded out of band) but never
+    // verified: check it instead of uploading again.
+    if let Some(existing) = &existing
+        && existing.size as u64 == object.size
+        && verify(client, object, existing, &sha1).await?
+    {
+        tracing::info!("import: {} already present, verified", object.name);
+        return Ok(Outcome::Skipped);
+    }
+    if let Some(checkpoint) = &checkpoint {
+        tracing::info!(
+            "import: retrying {}/{} ({} of {} bytes confirmed)",
+            object.file_type.dirname(),
+            object.name,
+            checkpoint.bytes_confirmed,
+            object.size
+        );
+    }
+
+    client
+        .save_import_checkpoint(object.file_type, &object.name, object.size, 0, &sha1, false)
+        .await?;
+    client
+        .upload_file(&dir_id, &object.name, Bytes::from(data))
+        .await?;
+    let uploaded = client
+        .find_file(&dir_id, &object.name)
+        .await?
+        .with_context(|| format!("{} not found on 115 after upload", object.name))?;
+    if uploaded.size as u64 != object.size || !verify(client, object, &uploaded, &sha1).await? {
+        bail!(
+            "{}: copy on 115 does not match ({} of {} bytes)",
+            object.name,
+            uploaded.size,
+            object.size
+        );
+    }
+    tracing::info!(
+        "import: uploaded {}/{} ({} bytes)",
+        object.file_type.dirname(),
+        object.name,
+        object.size
+    );
+    Ok(Outcome::Uploaded(object.size))
+}
+
+/// Compare the SHA-1 115 reports for `remote` with the local `sha1` and
+/// checkpoint the result. A file whose SHA-1 115 does not report counts as
+/// verified by size alone.
+async fn verify(
+    client: &Open115Client,
+    object: &LocalObject,
+    remote: &FileInfo,
+    sha1: &str,
+) -> anyhow::Result<bool> {
+    let verified = match client.remote_sha1(remote).await? {
+        Some(remote_sha1) => remote_sha1 == sha1,
+        None => {
+            tracing::warn!("import: 115 reports no SHA-1 for {}", object.name);
+            true
+        }
+    };
+    client
+        .save_import_checkpoint(
+            object.file_type,
+            &object.name,
+            object.size,
+            remote.size as u64,
+            sha1,
+            verified,
+        )
+        .await?;
+    if !verified {
+        tracing::warn!("import: {} on 115 has a different SHA-1", object.name);
+    }
+    Ok(verified)
+}
+
+/// List the objects of the restic repository at `source`. Locks are skipped.
+fn scan(source: &Path) -> anyhow::Result<Vec<LocalObject>> {
+    let config = source.join("config");
+    if !config.is_file() {
+        bail!(
+            "{} does not look like a restic repository (no config file)",
+            source.display()
+        );
+    }
+
+    let mut objects = vec![LocalObject {
+        file_type: ResticFileType::Config,
+        name: "config".to_string(),
+        size: config.metadata()?.len(),
+        path: config,
+    }];
+    for &file_type in &PHASES[..PHASES.len() - 1] {
+        let dir = source.join(file_type.dirname());
+        if !dir.is_dir() {
+            continue;
+        }
+        if file_type == ResticFileType::Data {
+            // data/<first two hex digits>/<pack>
+            for sub in read_dir_sorted(&dir)? {
+                if sub.is_dir() {
+                    scan_dir(&sub, file_type, &mut objects)?;
+                }
+            }
+        } else {
+            scan_dir(&dir, file_type, &mut objects)?;
+        }
+    }
+    Ok(objects)
+}
+
+fn scan_dir(
+    dir: &Path,
+    file_type: ResticFileType,
+    objects: &mut Vec<LocalObject>,
+) -> anyhow::Result<()> {
+    for path in read_dir_sorted(dir)? {
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        // Skip editor/backup leftovers and partially written files.
+        if !path.is_file() || name.starts_with('.') || name.contains(".tmp") {
+            continue;
+        }
+        objects.push(LocalObject {
+            file_type,
+            name: name.to_string(),
+            size: path.metadata()?.len(),
+            path,
+        });
+    }
+    Ok(())
+}
+
+fn read_dir_sorted(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = std::fs::read_dir(dir)
+        .with_context(|| format!("read {}", dir.display()))?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_local_repository() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        assert!(scan(root).is_err());
+
+        std::fs::write(root.join("config"), b"cfg").unwrap();
+        for sub in ["keys", "locks", "snapshots", "index", "data/ab"] {
+            std::fs::create_dir_all(root.join(sub)).unwrap();
+        }
+        std::fs::write(root.join("keys/k1"), b"key").unwrap();
+        std::fs::write(root.join("locks/l1"), b"lock").unwrap();
+        std::fs::write(root.join("data/ab/ab12"), b"pack").unwrap();
+        std::fs::write(root.join("data/ab/.ab13.tmp"), b"partial").unwrap();
+
+        let found: Vec<(ResticFileType, String, u64)> = scan(root)
+            .unwrap()
+            .into_iter()
+            .map(|o| (o.file_type, o.name, o.size))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (ResticFileType::Config, "config".to_string(), 3),
+                (ResticFileType::Data, "ab12".to_string(), 4),
+                (ResticFileType::Keys, "k1".to_string(), 3),
+            ]
+        );
+    }
+}
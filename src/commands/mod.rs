@@ -0,0 +1,14 @@
+//! CLI subcommands.
+
+pub mod bench;
+pub mod cache;
+#[cfg(feature = "compat-test")]
+pub mod compat_test;
+pub mod completions;
+pub mod db;
+pub mod harness;
+pub mod healthcheck;
+pub mod import;
+pub mod self_test;
+pub mod token;
+pub mod undelete;
@@ -0,0 +1,62 @@
+//! `token history` and `token restore`: the 115 token pairs in the cache DB.
+//!
+//! Every refresh stores the new pair as a row of its own, so when 115
+//! invalidates a refresh token the history shows when each rotation
+//! happened, and `restore` can make the last pair that still works current
+//! again. Tokens are shortened unless `--show-tokens` is given.
+
+use anyhow::bail;
+use sea_orm::EntityTrait;
+
+use super::cache::open_db;
+use crate::config::{Config, TokenCommand};
+use crate::open115::database::{self, entities::tokens};
+
+pub async fn run(config: &Config, command: &TokenCommand) -> anyhow::Result<()> {
+    let db = open_db(config).await?;
+    match command {
+        TokenCommand::History { limit, show_tokens } => {
+            let pairs = database::token_history(&db, *limit).await?;
+            if pairs.is_empty() {
+                println!("no tokens stored in {}", config.db_path);
+            }
+            let shown = |token: &str| {
+                if *show_tokens {
+                    token.to_string()
+                } else {
+                    shorten(token)
+                }
+            };
+            for t in pairs {
+                println!(
+                    "{:>5}  rotated {}  expires {}  access {}  refresh {}",
+                    t.id,
+                    t.updated_at.to_rfc3339(),
+                    t.expires_at
+                        .map_or_else(|| "unknown".to_string(), |at| at.to_rfc3339()),
+                    shown(&t.access_token),
+                    shown(&t.refresh_token)
+                );
+            }
+        }
+        TokenCommand::Restore { id } => {
+            let Some(t) = tokens::Entity::find_by_id(*id).one(&db).await? else {
+                bail!("no token pair {id} in {}", config.db_path);
+            };
+            database::save_tokens(&db, &t.access_token, &t.refresh_token, t.expires_at).await?;
+            println!(
+                "token pair {id} (rotated {}) is current again; restart a running server to use it",
+                t.updated_at.to_rfc3339()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The first characters of `token`, enough to tell pairs apart.
+fn shorten(token: &str) -> String {
+    match token.char_indices().nth(8) {
+        Some((end, _)) => format!("{}...", &token[..end]),
+        None => token.to_string(),
+    }
+}
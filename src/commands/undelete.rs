@@ -0,0 +1,50 @@
+//! `undelete`: restore objects deleted through the server from the 115
+//! recycle bin.
+//!
+//! Every delete is recorded in the delete journal in the cache DB first, so
+//! this has to run against the same `DB_PATH` as the server. Restored
+//! objects are put back in the cache; a running server sees them once it
+//! re-reads the directory (or after `/admin/undelete`, which does the same
+//! without a second process).
+
+use anyhow::bail;
+
+use crate::config::{Config, UndeleteArgs};
+use crate::open115::Open115Client;
+
+pub async fn run(config: &Config, args: &UndeleteArgs) -> anyhow::Result<()> {
+    let since = chrono::Duration::try_minutes(args.minutes.try_into()?)
+        .and_then(|window| chrono::Utc::now().checked_sub_signed(window))
+        .unwrap_or(chrono::DateTime::UNIX_EPOCH);
+    let client = Open115Client::new(config.clone()).await?;
+
+    if args.dry_run {
+        let deleted = client.deleted_objects(since).await?;
+        for entry in deleted
+            .iter()
+            .filter(|d| args.names.is_empty() || args.names.contains(&d.name))
+        {
+            println!(
+                "{}\t{}\t{}",
+                entry.deleted_at.to_rfc3339(),
+                entry.size,
+                entry.name
+            );
+        }
+        return Ok(());
+    }
+
+    let restored = client.undelete(since, &args.names).await?;
+    for entry in &restored {
+        println!("restored {}", entry.name);
+    }
+    let missing: Vec<&String> = args
+        .names
+        .iter()
+        .filter(|name| !restored.iter().any(|r| &r.name == *name))
+        .collect();
+    if !missing.is_empty() {
+        bail!("not restored: {missing:?}");
+    }
+    Ok(())
+}
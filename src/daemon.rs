@@ -0,0 +1,121 @@
+//! Running as a classic daemon (`--daemon`, `--pid-file`, `--log-file`).
+//!
+//! Detaching has to happen before the Tokio runtime starts its threads, so
+//! [`daemonize`] is called from a synchronous `main`. The PID file is written
+//! by the final process and removed again on a clean shutdown (SIGTERM or
+//! Ctrl-C), so init scripts can `kill $(cat restic-115.pid)`.
+
+use anyhow::{Context, bail};
+use std::path::{Path, PathBuf};
+
+/// Detach from the terminal and the parent process, sending stdout and
+/// stderr (and so the logs) to `log_file`, or discarding them without one.
+/// The working directory is kept, so relative paths such as `DB_PATH` keep
+/// working.
+#[cfg(all(unix, feature = "daemon"))]
+pub fn daemonize(log_file: Option<&Path>) -> anyhow::Result<()> {
+    let mut daemon = daemonize::Daemonize::new()
+        .working_directory(std::env::current_dir()?)
+        .umask(0o027);
+    if let Some(path) = log_file {
+        let open = || {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("failed to open log file {}", path.display()))
+        };
+        daemon = daemon.stdout(open()?).stderr(open()?);
+    }
+    daemon.start().context("failed to daemonize")
+}
+
+#[cfg(not(all(unix, feature = "daemon")))]
+pub fn daemonize(_log_file: Option<&Path>) -> anyhow::Result<()> {
+    bail!("--daemon needs a Unix build with the `daemon` feature; use a service manager instead")
+}
+
+/// A PID file removed again when dropped.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Write the current PID to `path`, refusing to start if it names a
+    /// process that is still running. A stale file from a crash is replaced.
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        if let Ok(old) = std::fs::read_to_string(path)
+            && let Ok(pid) = old.trim().parse::<u32>()
+            && pid != std::process::id()
+            && is_running(pid)
+        {
+            bail!(
+                "{} names running process {}; is restic-115 already running?",
+                path.display(),
+                pid
+            );
+        }
+        std::fs::write(path, format!("{}\n", std::process::id()))
+            .with_context(|| format!("failed to write PID file {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::warn!("Failed to remove PID file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Liveness is only checked where `/proc` exists; elsewhere an existing PID
+/// file is assumed stale.
+fn is_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// Resolve once SIGTERM or SIGINT (Ctrl-C) is received.
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = term.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            Err(e) => {
+                tracing::warn!("Cannot listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("restic-115.pid");
+
+        // A stale file naming a dead process is replaced.
+        std::fs::write(&path, "4294967295\n").unwrap();
+        let pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+}
@@ -2,11 +2,13 @@
 
 use axum::{
     Json,
-    http::StatusCode,
+    http::{HeaderValue, StatusCode, header},
     response::{IntoResponse, Response},
 };
 use serde_json::json;
 
+use crate::restic::request_id;
+
 /// Application-wide error type.
 #[derive(Debug, thiserror::Error)]
 pub enum AppError {
@@ -26,10 +28,26 @@ pub enum AppError {
     #[error("File not found: {0}")]
     NotFound(String),
 
+    /// Missing or wrong admin or restic credentials
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    /// Valid credentials of a user not allowed to use the repository
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
     /// Invalid request
     #[error("Invalid request: {0}")]
     BadRequest(String),
 
+    /// Object already exists and overwriting is not allowed
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    /// Request body exceeds the configured maximum
+    #[error("Payload too large: {0}")]
+    PayloadTooLarge(String),
+
     /// IO error
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
@@ -38,6 +56,34 @@ pub enum AppError {
     #[error("JSON error: {0}")]
     Json(#[from] serde_json::Error),
 
+    /// Server is at capacity; the client should retry after the given delay
+    #[error("Server busy: {message}")]
+    Overloaded {
+        message: String,
+        retry_after_secs: u64,
+    },
+
+    /// A client operation exceeded its overall deadline
+    #[error("{operation} timed out after {elapsed_secs}s ({} retries)", retries.len())]
+    Timeout {
+        operation: String,
+        elapsed_secs: u64,
+        /// Retries taken before the deadline, oldest first.
+        retries: Vec<String>,
+    },
+
+    /// A restic request used up its budget of 115 API calls
+    #[error("request exceeded its budget of {budget} 115 API calls (refused: {call})")]
+    CallBudgetExceeded { budget: u32, call: String },
+
+    /// A download's length disagrees with the object's cached size
+    #[error("{what}: 115 sent {actual} bytes where {expected} were expected")]
+    SizeMismatch {
+        what: String,
+        expected: u64,
+        actual: u64,
+    },
+
     /// Internal error
     #[error("Internal error: {0}")]
     Internal(String),
@@ -71,10 +117,28 @@ impl IntoResponse for AppError {
                 tracing::debug!("Not found: {}", msg);
                 (StatusCode::NOT_FOUND, msg.clone())
             }
+            AppError::Unauthorized(msg) => {
+                // Admin endpoints, and restic routes with --credentials-file;
+                // never for 115 authentication failures.
+                tracing::warn!("Unauthorized: {}", msg);
+                (StatusCode::UNAUTHORIZED, msg.clone())
+            }
+            AppError::Forbidden(msg) => {
+                tracing::warn!("Forbidden: {}", msg);
+                (StatusCode::FORBIDDEN, msg.clone())
+            }
             AppError::BadRequest(msg) => {
                 tracing::warn!("Bad request: {}", msg);
                 (StatusCode::BAD_REQUEST, msg.clone())
             }
+            AppError::Conflict(msg) => {
+                tracing::warn!("Conflict: {}", msg);
+                (StatusCode::CONFLICT, msg.clone())
+            }
+            AppError::PayloadTooLarge(msg) => {
+                tracing::warn!("Payload too large: {}", msg);
+                (StatusCode::PAYLOAD_TOO_LARGE, msg.clone())
+            }
             AppError::Io(e) => {
                 tracing::error!("IO error: {}", e);
                 (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
@@ -87,14 +151,54 @@ impl IntoResponse for AppError {
                 // as fatal "bad request". Use 502 to reflect upstream/serialization failure.
                 (StatusCode::BAD_GATEWAY, e.to_string())
             }
+            AppError::Overloaded { message, .. } => {
+                tracing::warn!("Overloaded: {}", message);
+                (StatusCode::SERVICE_UNAVAILABLE, message.clone())
+            }
+            AppError::Timeout { retries, .. } => {
+                tracing::error!("{} (retries: {:?})", self, retries);
+                (StatusCode::GATEWAY_TIMEOUT, self.to_string())
+            }
+            AppError::CallBudgetExceeded { .. } => {
+                // Local protection, not an upstream failure; restic retries 503.
+                tracing::warn!("{}", self);
+                (StatusCode::SERVICE_UNAVAILABLE, self.to_string())
+            }
+            AppError::SizeMismatch { .. } => {
+                // Never serve restic a pack of the wrong length; it retries 502.
+                tracing::error!("{}", self);
+                (StatusCode::BAD_GATEWAY, self.to_string())
+            }
             AppError::Internal(msg) => {
                 tracing::error!("Internal error: {}", msg);
                 (StatusCode::INTERNAL_SERVER_ERROR, msg.clone())
             }
         };
 
-        let body = Json(json!({ "error": message }));
-        (status, body).into_response()
+        let mut body = match &self {
+            AppError::Timeout { retries, .. } => json!({ "error": message, "retries": retries }),
+            _ => json!({ "error": message }),
+        };
+        if let Some(id) = request_id::current() {
+            body["request_id"] = json!(id.as_ref());
+        }
+        let body = Json(body);
+        let mut response = (status, body).into_response();
+        if let AppError::Overloaded {
+            retry_after_secs, ..
+        } = &self
+        {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(*retry_after_secs));
+        }
+        if let AppError::Unauthorized(_) = &self {
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static("Basic realm=\"restic-115\""),
+            );
+        }
+        response
     }
 }
 
@@ -0,0 +1,285 @@
+//! Repository events published to notifiers.
+//!
+//! Events are broadcast to live subscribers and the most recent ones are kept
+//! in memory so they can also be polled from `GET /admin/events`.
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use tokio::sync::broadcast;
+
+/// Number of past events retained for polling.
+const RECENT_EVENTS: usize = 256;
+
+/// Consecutive failed upstream requests that raise
+/// [`Event::UpstreamFailing`].
+const UPSTREAM_FAILURE_THRESHOLD: u32 = 5;
+
+/// Counter of completed snapshot uploads.
+pub const SNAPSHOTS_COMPLETED_TOTAL: &str = "restic115_snapshots_completed_total";
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A `snapshots/` object was uploaded and confirmed in the 115 cache,
+    /// i.e. a restic backup has finished writing its snapshot.
+    SnapshotCompleted {
+        repo: String,
+        /// restic snapshot id (the object name).
+        id: String,
+        size: u64,
+        /// Unix time in seconds.
+        timestamp: i64,
+    },
+    /// The pre-backup hook prepared the server for a scheduled backup.
+    BackupStarted { repo: String, timestamp: i64 },
+    /// The post-backup hook finalized a scheduled backup.
+    BackupFinished {
+        repo: String,
+        /// Snapshots completed since the matching `backup_started`.
+        snapshots: Vec<String>,
+        /// Recycle-bin entries purged, if purging was requested.
+        purged: Option<usize>,
+        timestamp: i64,
+    },
+    /// The repository was created (`POST /?create=true`).
+    RepoInitialized { repo: String, timestamp: i64 },
+    /// Several restic requests in a row failed because 115 returned errors
+    /// or could not be reached.
+    UpstreamFailing {
+        repo: String,
+        /// Failed requests in a row so far.
+        consecutive: u32,
+        /// HTTP status of the latest failure.
+        status: u16,
+        timestamp: i64,
+    },
+    /// A request succeeded again after [`Event::UpstreamFailing`].
+    UpstreamRecovered { repo: String, timestamp: i64 },
+}
+
+impl Event {
+    /// Every event name, as used in the `event` field and webhook filters.
+    pub const NAMES: &[&str] = &[
+        "snapshot_completed",
+        "backup_started",
+        "backup_finished",
+        "repo_initialized",
+        "upstream_failing",
+        "upstream_recovered",
+    ];
+
+    /// The event name, as in the `event` field.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::SnapshotCompleted { .. } => "snapshot_completed",
+            Event::BackupStarted { .. } => "backup_started",
+            Event::BackupFinished { .. } => "backup_finished",
+            Event::RepoInitialized { .. } => "repo_initialized",
+            Event::UpstreamFailing { .. } => "upstream_failing",
+            Event::UpstreamRecovered { .. } => "upstream_recovered",
+        }
+    }
+
+    /// One-line human-readable description, for chat webhooks.
+    pub fn summary(&self) -> String {
+        match self {
+            Event::SnapshotCompleted { repo, id, size, .. } => {
+                format!("restic-115 {repo}: snapshot {id} completed ({size} bytes)")
+            }
+            Event::BackupStarted { repo, .. } => format!("restic-115 {repo}: backup started"),
+            Event::BackupFinished {
+                repo, snapshots, ..
+            } => format!(
+                "restic-115 {repo}: backup finished with {} snapshot(s)",
+                snapshots.len()
+            ),
+            Event::RepoInitialized { repo, .. } => {
+                format!("restic-115 {repo}: repository initialized")
+            }
+            Event::UpstreamFailing {
+                repo,
+                consecutive,
+                status,
+                ..
+            } => format!(
+                "restic-115 {repo}: {consecutive} requests in a row failed upstream (latest: HTTP {status})"
+            ),
+            Event::UpstreamRecovered { repo, .. } => {
+                format!("restic-115 {repo}: upstream requests succeeding again")
+            }
+        }
+    }
+}
+
+pub struct EventBus {
+    tx: broadcast::Sender<Event>,
+    /// Retained events with their sequence numbers, oldest first.
+    recent: Mutex<VecDeque<(u64, Event)>>,
+    next_seq: AtomicU64,
+    /// Failed upstream requests since the last success.
+    upstream_failures: AtomicU32,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(RECENT_EVENTS);
+        Self {
+            tx,
+            recent: Mutex::new(VecDeque::with_capacity(RECENT_EVENTS)),
+            next_seq: AtomicU64::new(1),
+            upstream_failures: AtomicU32::new(0),
+        }
+    }
+
+    /// Publish an event to subscribers and the recent-events buffer.
+    pub fn emit(&self, event: Event) {
+        match &event {
+            Event::SnapshotCompleted { repo, id, size, .. } => {
+                tracing::info!(
+                    "Snapshot completed: repo={}, id={}, size={}",
+                    repo,
+                    id,
+                    size
+                );
+                metrics::counter!(SNAPSHOTS_COMPLETED_TOTAL).increment(1);
+            }
+            Event::BackupStarted { repo, .. } => tracing::info!("Backup started: repo={}", repo),
+            Event::BackupFinished {
+                repo, snapshots, ..
+            } => tracing::info!(
+                "Backup finished: repo={}, snapshots={}",
+                repo,
+                snapshots.len()
+            ),
+            Event::RepoInitialized { repo, .. } => {
+                tracing::info!("Repository initialized: repo={}", repo)
+            }
+            Event::UpstreamFailing {
+                repo,
+                consecutive,
+                status,
+                ..
+            } => tracing::warn!(
+                "Upstream failing: repo={}, consecutive={}, status={}",
+                repo,
+                consecutive,
+                status
+            ),
+            Event::UpstreamRecovered { repo, .. } => {
+                tracing::info!("Upstream recovered: repo={}", repo)
+            }
+        }
+        {
+            let mut recent = self.recent.lock();
+            if recent.len() == RECENT_EVENTS {
+                recent.pop_front();
+            }
+            // Taken under the lock so the buffer stays ordered by sequence.
+            let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+            recent.push_back((seq, event.clone()));
+        }
+        // No subscribers is fine; the event is still retained above.
+        let _ = self.tx.send(event);
+    }
+
+    /// Receive events emitted from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+
+    /// Retained events, oldest first.
+    pub fn recent(&self) -> Vec<Event> {
+        self.recent.lock().iter().map(|(_, e)| e.clone()).collect()
+    }
+
+    /// Retained events with their sequence numbers, oldest first.
+    pub fn recent_sequenced(&self) -> Vec<(u64, Event)> {
+        self.recent.lock().iter().cloned().collect()
+    }
+
+    /// Note a restic request that failed upstream with `status`; the
+    /// threshold-th failure in a row emits [`Event::UpstreamFailing`].
+    pub fn upstream_failed(&self, repo: &str, status: u16) {
+        let consecutive = self.upstream_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if consecutive == UPSTREAM_FAILURE_THRESHOLD {
+            self.emit(Event::UpstreamFailing {
+                repo: repo.to_string(),
+                consecutive,
+                status,
+                timestamp: chrono::Utc::now().timestamp(),
+            });
+        }
+    }
+
+    /// Note a restic request that reached 115 fine, emitting
+    /// [`Event::UpstreamRecovered`] if it ends a failure streak.
+    pub fn upstream_succeeded(&self, repo: &str) {
+        if self.upstream_failures.swap(0, Ordering::Relaxed) >= UPSTREAM_FAILURE_THRESHOLD {
+            self.emit(Event::UpstreamRecovered {
+                repo: repo.to_string(),
+                timestamp: chrono::Utc::now().timestamp(),
+            });
+        }
+    }
+
+    /// Whether the current failure streak has reached the threshold, i.e.
+    /// [`Event::UpstreamFailing`] was emitted and 115 has not recovered yet.
+    pub fn upstream_is_failing(&self) -> bool {
+        self.upstream_failures.load(Ordering::Relaxed) >= UPSTREAM_FAILURE_THRESHOLD
+    }
+
+    /// Ids of the snapshots of `repo` completed since its last
+    /// [`Event::BackupStarted`] (or among all retained events if none).
+    pub fn snapshots_since_backup_started(&self, repo: &str) -> Vec<String> {
+        let recent = self.recent.lock();
+        let mut ids: Vec<String> = recent
+            .iter()
+            .rev()
+            .map(|(_, e)| e)
+            .take_while(|e| !matches!(e, Event::BackupStarted { repo: r, .. } if r == repo))
+            .filter_map(|e| match e {
+                Event::SnapshotCompleted { repo: r, id, .. } if r == repo => Some(id.clone()),
+                _ => None,
+            })
+            .collect();
+        ids.reverse();
+        ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upstream_failure_streak() {
+        let bus = EventBus::new();
+        for _ in 0..UPSTREAM_FAILURE_THRESHOLD + 2 {
+            bus.upstream_failed("r", 502);
+        }
+        assert!(bus.upstream_is_failing());
+        bus.upstream_succeeded("r");
+        bus.upstream_failed("r", 504);
+        bus.upstream_succeeded("r");
+
+        assert!(!bus.upstream_is_failing());
+        let names: Vec<&str> = bus.recent().iter().map(Event::name).collect();
+        assert_eq!(names, ["upstream_failing", "upstream_recovered"]);
+        assert!(matches!(
+            bus.recent()[0],
+            Event::UpstreamFailing {
+                consecutive: UPSTREAM_FAILURE_THRESHOLD,
+                status: 502,
+                ..
+            }
+        ));
+    }
+}
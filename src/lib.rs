@@ -1,7 +1,19 @@
 //! Library entry for restic-115.
 
+pub mod access_log;
+pub mod backend;
+pub mod commands;
 pub mod config;
+pub mod daemon;
 pub mod error;
+pub mod events;
+#[cfg(feature = "mock115")]
+pub mod mock115;
 pub mod open115;
+pub mod read_cache;
 pub mod restic;
-
+pub mod scratch;
+pub mod server;
+pub mod spool;
+pub mod telemetry;
+pub mod webhooks;
@@ -1,33 +1,270 @@
 //! Restic REST API server backed by 115 open platform cloud storage.
 
-use clap::Parser;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use restic_115::config::Config;
+use restic_115::access_log::{self, AccessLog};
+use restic_115::config::{Command, Config, RunArgs, SelfTestArgs, TimeWindow};
+use restic_115::daemon;
+use restic_115::events::EventBus;
 use restic_115::open115::Open115Client;
-use restic_115::restic::create_router;
+use restic_115::read_cache::ReadCache;
+use restic_115::restic::{
+    Activity, AppState, Credentials, Health, InflightBudget, LockReaper, Prefetcher, RecentWrites,
+    create_router, request_id,
+};
+use restic_115::server::{self, ServerOptions};
+use restic_115::spool::Spool;
+use restic_115::telemetry;
+use restic_115::webhooks;
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let config = Config::parse();
+fn main() -> anyhow::Result<()> {
+    let (config, env_warnings) = Config::parse_with_aliases();
+    if config.help_json {
+        println!("{:#}", restic_115::commands::completions::help_json());
+        return Ok(());
+    }
+    // Forking must happen before the runtime starts its threads.
+    if config.daemon && config.command.is_none() {
+        daemon::daemonize(config.log_file.as_deref())?;
+    }
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(config, env_warnings))
+}
 
+async fn run(config: Config, env_warnings: Vec<String>) -> anyhow::Result<()> {
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| config.log_level.clone().into()),
         )
-        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::fmt::layer().with_ansi(!config.daemon))
         .init();
+    for warning in env_warnings {
+        tracing::warn!("{}", warning);
+    }
+
+    match &config.command {
+        Some(Command::CompatTest(args)) => compat_test(args).await,
+        Some(Command::Import(args)) => restic_115::commands::import::run(&config, args).await,
+        Some(Command::Undelete(args)) => restic_115::commands::undelete::run(&config, args).await,
+        Some(Command::Run(args)) => run_restic(config.clone(), args).await,
+        Some(Command::Healthcheck(args)) => restic_115::commands::healthcheck::run(args).await,
+        Some(Command::Bench(args)) => restic_115::commands::bench::run(&config, args).await,
+        Some(Command::SelfTest(args)) => self_test(config.clone(), args).await,
+        Some(Command::Cache(command)) => restic_115::commands::cache::run(&config, command).await,
+        Some(Command::Db(command)) => restic_115::commands::db::run(&config, command).await,
+        Some(Command::Token(command)) => restic_115::commands::token::run(&config, command).await,
+        Some(Command::Completions(args)) => {
+            restic_115::commands::completions::run(args);
+            Ok(())
+        }
+        None => serve(config).await,
+    }
+}
+
+#[cfg(feature = "compat-test")]
+async fn compat_test(args: &restic_115::config::CompatTestArgs) -> anyhow::Result<()> {
+    restic_115::commands::compat_test::run(args).await
+}
+
+#[cfg(not(feature = "compat-test"))]
+async fn compat_test(_args: &restic_115::config::CompatTestArgs) -> anyhow::Result<()> {
+    anyhow::bail!("compat-test requires building with `--features compat-test`")
+}
+
+async fn serve(config: Config) -> anyhow::Result<()> {
+    let addrs = config.listen_addrs().map_err(anyhow::Error::msg)?;
+    let options = ServerOptions::from_config(&config)?;
+    options.spawn_tls_reload();
+    let _pid_file = config
+        .pid_file
+        .as_deref()
+        .map(daemon::PidFile::create)
+        .transpose()?;
+    let (app, _spool) = build_app(config, None).await?;
+
+    let mut servers = Vec::new();
+    for &addr in &addrs {
+        let listener = bind(addr, &addrs)?;
+        tracing::info!("Server listening on {}://{}", options.scheme(), addr);
+        servers.push(server::serve(listener, app.clone(), options.clone()));
+    }
+    tokio::select! {
+        _ = futures::future::join_all(servers) => {}
+        _ = daemon::shutdown_signal() => tracing::info!("Shutting down"),
+    }
+    Ok(())
+}
+
+/// Bind one of the listen addresses. IPv6 sockets are made v6-only when an
+/// IPv4 address with the same port is also listed, so `[::]:8000,0.0.0.0:8000`
+/// does not collide on dual-stack systems.
+fn bind(addr: SocketAddr, all: &[SocketAddr]) -> anyhow::Result<tokio::net::TcpListener> {
+    use socket2::{Domain, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() && all.iter().any(|a| a.is_ipv4() && a.port() == addr.port()) {
+        socket.set_only_v6(true)?;
+    }
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket
+        .bind(&addr.into())
+        .map_err(|e| anyhow::anyhow!("failed to bind {addr}: {e}"))?;
+    socket.listen(1024)?;
+    Ok(tokio::net::TcpListener::from_std(socket.into())?)
+}
+
+/// User the restic child of `run` and `self-test` authenticates as.
+const LOOPBACK_USER: &str = "restic-115";
+
+/// The server on an ephemeral loopback port, for `run` and `self-test`.
+///
+/// It only accepts [`LOOPBACK_USER`] with a password made up for this run,
+/// which the repository URL carries, instead of the users of
+/// `--credentials-file`: those may be confined to the configured repository,
+/// and the loopback server is for the restic it starts alone.
+struct Loopback {
+    repository: String,
+    stop: tokio::sync::oneshot::Sender<()>,
+    server: tokio::task::JoinHandle<std::io::Result<()>>,
+}
+
+impl Loopback {
+    /// A fresh password for [`LOOPBACK_USER`].
+    fn password() -> String {
+        uuid::Uuid::new_v4().simple().to_string()
+    }
+
+    async fn start(app: axum::Router, password: &str) -> anyhow::Result<Self> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let repository = format!(
+            "rest:http://{LOOPBACK_USER}:{password}@{}/",
+            listener.local_addr()?
+        );
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(async {
+                let _ = stopped.await;
+            })
+            .await
+        });
+        Ok(Self {
+            repository,
+            stop,
+            server,
+        })
+    }
+
+    /// Drain the spool, then stop the server.
+    async fn stop(self, spool: Option<Spool>, command: &str) -> anyhow::Result<()> {
+        // Stop the server even when the flush gives up; pending packs stay
+        // spooled for the next start.
+        let flushed = match &spool {
+            Some(spool) => {
+                let pending = spool.pending().len();
+                if pending > 0 {
+                    tracing::info!("{}: waiting for {} spooled uploads", command, pending);
+                }
+                spool.flush().await
+            }
+            None => Ok(()),
+        };
+        let _ = self.stop.send(());
+        self.server.await??;
+        flushed?;
+        Ok(())
+    }
+}
+
+/// Serve on an ephemeral loopback port while one restic command runs against
+/// it, then drain the spool, stop the server and exit with restic's status.
+async fn run_restic(config: Config, args: &RunArgs) -> anyhow::Result<()> {
+    let password = Loopback::password();
+    let (app, spool) = build_app(config, Some(&password)).await?;
+    let loopback = Loopback::start(app, &password).await?;
+
+    tracing::info!("run: {} {}", args.restic_bin.display(), args.args.join(" "));
+    let status = tokio::process::Command::new(&args.restic_bin)
+        .args(&args.args)
+        .env("RESTIC_REPOSITORY", &loopback.repository)
+        .status()
+        .await
+        .map_err(|e| anyhow::anyhow!("failed to run {}: {e}", args.restic_bin.display()))?;
+    loopback.stop(spool, "run").await?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        tracing::warn!("run: restic exited with {}", status);
+        // Signals have no code; report them like a shell would.
+        std::process::exit(status.code().unwrap_or(128));
+    }
+}
+
+/// Serve a throwaway repository on a loopback port, run the `self-test`
+/// scenario against it, then delete the repository.
+async fn self_test(mut config: Config, args: &SelfTestArgs) -> anyhow::Result<()> {
+    let path = args.path.clone().unwrap_or_else(|| {
+        format!(
+            "/{}-self-test-{}",
+            config.repo_path.trim_matches('/'),
+            chrono::Utc::now().format("%Y%m%d%H%M%S")
+        )
+    });
+    config.repo_path = path.clone();
+    let client = Open115Client::new(config.clone()).await?;
+    if client.find_path_id(&path).await?.is_some() {
+        anyhow::bail!("self-test: {path} already exists; pick a new --path");
+    }
+    tracing::info!("self-test: using {}", path);
+
+    let password = Loopback::password();
+    let (app, spool) = build_app(config, Some(&password)).await?;
+    let loopback = Loopback::start(app, &password).await?;
+    let result = restic_115::commands::self_test::run(args, &loopback.repository).await;
+    // Delete the repository even when the server did not stop cleanly.
+    let stopped = loopback.stop(spool, "self-test").await;
+
+    if args.keep {
+        tracing::info!("self-test: kept {}", path);
+    } else if let Err(e) = client.delete_repository().await {
+        tracing::warn!("self-test: failed to delete {}: {}", path, e);
+    }
+    result?;
+    stopped?;
+    println!("self-test passed");
+    Ok(())
+}
 
+/// Connect to 115, warm the cache, start background jobs and build the
+/// router. The spool is returned so callers can drain it before exiting.
+///
+/// With `loopback_password`, restic requests need [`LOOPBACK_USER`] with that
+/// password instead of a user of `--credentials-file`.
+async fn build_app(
+    config: Config,
+    loopback_password: Option<&str>,
+) -> anyhow::Result<(axum::Router, Option<Spool>)> {
     tracing::info!("Starting restic-115");
     tracing::info!("Repository path: {}", config.repo_path);
-    tracing::info!(
-        "Listen address: {}:{}",
-        config.listen_addr,
-        config.listen_port
-    );
+
+    if config.enable_metrics {
+        telemetry::install()?;
+        tracing::info!("Prometheus metrics enabled at /metrics");
+    }
 
     let client = Open115Client::new(config.clone()).await?;
 
@@ -35,12 +272,345 @@ async fn main() -> anyhow::Result<()> {
         tracing::info!("Forced cache rebuild enabled, all directories will be refreshed");
     }
     client.warm_cache(config.force_cache_rebuild).await?;
+    if let Some(marker) = client.check_repo_marker().await? {
+        tracing::info!(
+            "Repository instance {} (layout version {})",
+            marker.instance_id,
+            marker.layout_version
+        );
+    }
+    let space = match client.space_info().await {
+        Ok(space) => {
+            tracing::info!(
+                "115 quota: {} of {} bytes used ({:.1}%), {} bytes free",
+                space.used,
+                space.total,
+                space.used_percent(),
+                space.free
+            );
+            Some(space)
+        }
+        Err(e) => {
+            tracing::warn!("Could not read 115 quota: {}", e);
+            None
+        }
+    };
+    if !config.maintenance_window.is_empty() {
+        tracing::info!(
+            "Cache eviction and recycle-bin purges restricted to {}",
+            config
+                .maintenance_window
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    let windows: Arc<[TimeWindow]> = config.maintenance_window.clone().into();
+    spawn_cache_maintenance(
+        client.clone(),
+        config
+            .cache_ttl_days
+            .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+        windows.clone(),
+    );
+    spawn_cache_db_watchdog(client.clone());
+    if let Some(minutes) = config.purge_trash_interval {
+        tracing::info!("Purging repository entries from the recycle bin every {minutes} minutes");
+        spawn_trash_purger(
+            client.clone(),
+            Duration::from_secs(minutes * 60),
+            windows.clone(),
+        );
+    }
+    let activity = Activity::new();
+    if let Some(minutes) = config.db_maintain_interval {
+        tracing::info!("Maintaining the cache DB every {minutes} minutes");
+        spawn_db_maintenance(
+            client.clone(),
+            Duration::from_secs(minutes.max(1) * 60),
+            windows.clone(),
+            activity.clone(),
+        );
+    }
+    if let Some(minutes) = config.janitor_interval {
+        tracing::info!(
+            "Removing same-name duplicates every {minutes} minutes{}",
+            if config.janitor_dry_run {
+                " (dry run)"
+            } else {
+                ""
+            }
+        );
+        spawn_janitor(
+            client.clone(),
+            Duration::from_secs(minutes.max(1) * 60),
+            config.janitor_dry_run,
+            windows,
+        );
+    }
+
+    let spool = match &config.spool_dir {
+        Some(dir) => {
+            tracing::info!("Write-behind spool enabled at {}", dir.display());
+            Some(
+                Spool::start(
+                    dir.clone(),
+                    client.clone(),
+                    config.spool_concurrency,
+                    config.spool_compress,
+                    Duration::from_secs(config.spool_flush_timeout),
+                )
+                .await?,
+            )
+        }
+        None => None,
+    };
+    let read_cache = match &config.read_cache_dir {
+        Some(dir) => Some(
+            ReadCache::open(
+                dir.clone(),
+                config.read_cache_size,
+                config.read_cache_compress,
+            )
+            .await?,
+        ),
+        None => None,
+    };
+    if config.admin_token.is_some() {
+        tracing::info!("Admin API enabled at /admin and /api");
+    }
+    let credentials = match (loopback_password, &config.credentials_file) {
+        (Some(password), _) => Some(Arc::new(Credentials::single(LOOPBACK_USER, password))),
+        (None, Some(path)) => {
+            let text = tokio::fs::read_to_string(path)
+                .await
+                .map_err(|e| anyhow::anyhow!("failed to read {}: {e}", path.display()))?;
+            let credentials = Credentials::parse(&text)
+                .map_err(|e| anyhow::anyhow!("invalid {}: {e}", path.display()))?;
+            let users = credentials.users_for(&config.repo_path);
+            if users.is_empty() {
+                tracing::warn!(
+                    "No user in {} may use {}; every restic request will be refused",
+                    path.display(),
+                    config.repo_path
+                );
+            } else {
+                tracing::info!(
+                    "Restic requests require credentials of: {}",
+                    users.join(", ")
+                );
+            }
+            Some(Arc::new(credentials))
+        }
+        (None, None) => None,
+    };
+    let locks = config.lock_ttl.map(|minutes| {
+        tracing::info!("Deleting restic locks older than {minutes} minutes");
+        let reaper = LockReaper::new(client.clone(), Duration::from_secs(minutes.max(1) * 60));
+        reaper.clone().spawn();
+        reaper
+    });
 
-    let app = create_router(client).layer(TraceLayer::new_for_http());
-    let addr: SocketAddr = format!("{}:{}", config.listen_addr, config.listen_port).parse()?;
+    let prefetch = config.prefetch_window.map(|secs| {
+        tracing::info!("Download-URL prefetch enabled ({secs}s window after index reads)");
+        Prefetcher::new(client.clone(), Duration::from_secs(secs))
+    });
 
-    tracing::info!("Server listening on http://{}", addr);
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
-    Ok(())
+    let events: Arc<EventBus> = Default::default();
+    if !config.webhook.is_empty() {
+        tracing::info!("Delivering events to {} webhook(s)", config.webhook.len());
+        webhooks::spawn(&events, config.webhook.clone())?;
+    }
+
+    let mut info = config.summary();
+    info["repo_namespace"] = client.repo_id().into();
+    info["quota_at_startup"] = serde_json::to_value(space)?;
+    info["started_at"] = chrono::Utc::now().to_rfc3339().into();
+    tracing::info!("Startup summary: {}", info);
+
+    let state = AppState {
+        info: Arc::new(info),
+        health: Some(Arc::new(Health::new(client.clone()))),
+        call_budget: config.request_call_budget,
+        credentials,
+        cache_rebuild: Default::default(),
+        backend: Arc::new(client.clone()),
+        client: Some(client),
+        read_cache,
+        events,
+        allow_repo_delete: config.allow_repo_delete,
+        auto_create_repo: config.auto_create_repo.then(Default::default),
+        max_blob_size: config.max_blob_size,
+        prefetch,
+        spool: spool.clone(),
+        inflight: config.max_inflight_bytes.map(InflightBudget::new),
+        admin_token: config.admin_token.clone(),
+        checksum_trailer: config.checksum_trailer,
+        locks,
+        activity,
+        recent: (config.write_grace > 0)
+            .then(|| Arc::new(RecentWrites::new(Duration::from_secs(config.write_grace)))),
+    };
+    let mut app = create_router(state)
+        .layer(TraceLayer::new_for_http().make_span_with(request_id::make_span));
+    if let Some(path) = &config.access_log {
+        let log = AccessLog::open(path).await?;
+        app = app.layer(axum::middleware::from_fn_with_state(
+            log,
+            access_log::record,
+        ));
+    }
+    let app = app.layer(axum::middleware::from_fn(request_id::assign));
+    Ok((app, spool))
+}
+
+/// Sleep until one of the `--maintenance-window`s is open.
+async fn wait_for_window(windows: &[TimeWindow], job: &str) {
+    let wait = TimeWindow::until_open(windows, chrono::Local::now().time());
+    if !wait.is_zero() {
+        tracing::debug!(
+            "{} waits {}s for its maintenance window",
+            job,
+            wait.as_secs()
+        );
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Hourly: record that this repository is in use and, with a TTL, evict the
+/// cache rows of repositories nobody has used for that long. Touching runs
+/// even without a TTL so another instance with one sees this repo as live.
+/// Eviction waits for a maintenance window; touching does not.
+fn spawn_cache_maintenance(
+    client: Open115Client,
+    ttl: Option<Duration>,
+    windows: Arc<[TimeWindow]>,
+) {
+    if let Some(ttl) = ttl {
+        let client = client.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                wait_for_window(&windows, "Stale cache eviction").await;
+                if let Err(e) = client.evict_stale_repos(ttl).await {
+                    tracing::warn!("Stale cache eviction failed: {}", e);
+                }
+            }
+        });
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            if let Err(e) = client.touch_repo().await {
+                tracing::warn!("Failed to record repository access: {}", e);
+            }
+        }
+    });
+}
+
+/// Probe the cache DB for writes every 30 seconds, so a filesystem remounted
+/// read-only under a running server degrades to an in-memory cache instead of
+/// failing every request.
+fn spawn_cache_db_watchdog(client: Open115Client) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(30));
+        loop {
+            interval.tick().await;
+            if client.cache_db_degraded() {
+                break;
+            }
+            if let Err(e) = client.check_cache_db().await {
+                tracing::warn!("Cache DB write probe failed: {}", e);
+            }
+        }
+    });
+}
+
+/// Periodically remove same-name duplicates from the repository (see
+/// [`Open115Client::remove_duplicates`]), within the maintenance windows.
+fn spawn_janitor(
+    client: Open115Client,
+    period: Duration,
+    dry_run: bool,
+    windows: Arc<[TimeWindow]>,
+) {
+    tokio::spawn(async move {
+        let start = tokio::time::Instant::now() + period;
+        let mut interval = tokio::time::interval_at(start, period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            wait_for_window(&windows, "Duplicate cleanup").await;
+            match client.remove_duplicates(dry_run).await {
+                Ok(duplicates) if !duplicates.is_empty() => tracing::info!(
+                    "Janitor: {} {} duplicates",
+                    if dry_run { "found" } else { "removed" },
+                    duplicates.len()
+                ),
+                Ok(_) => tracing::debug!("Janitor: no duplicates"),
+                Err(e) => tracing::warn!("Duplicate cleanup failed: {}", e),
+            }
+        }
+    });
+}
+
+/// How long no restic request may have been served before the periodic DB
+/// maintenance runs.
+const DB_MAINTENANCE_QUIET: Duration = Duration::from_secs(60);
+
+/// Periodically checkpoint, analyze and vacuum the cache DB, so a
+/// long-running server does not accumulate WAL and free pages. `VACUUM`
+/// holds the DB for its duration, so each run waits until no restic request
+/// has been served for [`DB_MAINTENANCE_QUIET`].
+fn spawn_db_maintenance(
+    client: Open115Client,
+    period: Duration,
+    windows: Arc<[TimeWindow]>,
+    activity: Arc<Activity>,
+) {
+    tokio::spawn(async move {
+        let start = tokio::time::Instant::now() + period;
+        let mut interval = tokio::time::interval_at(start, period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            wait_for_window(&windows, "Cache DB maintenance").await;
+            if !activity.is_idle(DB_MAINTENANCE_QUIET) {
+                tracing::debug!("Cache DB maintenance waits for restic to go quiet");
+                activity.wait_idle(DB_MAINTENANCE_QUIET).await;
+            }
+            match client.maintain_cache_db().await {
+                Ok(report) => tracing::info!(
+                    "Cache DB maintained: {} -> {} pages ({} were free), {} WAL frames checkpointed",
+                    report.pages_before,
+                    report.pages_after,
+                    report.free_pages_before,
+                    report.wal_frames
+                ),
+                Err(e) => tracing::warn!("Cache DB maintenance failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Periodically purge objects deleted from the repository out of the 115
+/// recycle bin, where they would otherwise keep counting against the quota.
+/// Objects still in the delete journal are kept so `undelete` works.
+fn spawn_trash_purger(client: Open115Client, period: Duration, windows: Arc<[TimeWindow]>) {
+    tokio::spawn(async move {
+        let start = tokio::time::Instant::now() + period;
+        let mut interval = tokio::time::interval_at(start, period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            wait_for_window(&windows, "Recycle bin purge").await;
+            if let Err(e) = client.purge_recycle_bin(true).await {
+                tracing::warn!("Recycle bin purge failed: {}", e);
+            }
+        }
+    });
 }
@@ -0,0 +1,705 @@
+//! In-process mock of the 115 Open Platform, for tests without credentials
+//! (`mock115` feature).
+//!
+//! [`Mock115`] keeps a folder tree in memory and serves the calls the client
+//! makes: listings, search, folder creation, renames, deletes, account info,
+//! upload init and tokens, download URLs and the downloads themselves. The
+//! OSS PutObject of an upload is checked like OSS does (signature,
+//! `Content-MD5`, security token) and answered with the 115 callback result,
+//! and an upload whose content the mock already holds completes at init
+//! like a 115 fast upload. [`Mock115::config`] points a [`Config`] at the
+//! mock; every call is counted, so tests can assert what the caches saved.
+//! [`Mock115::cut_downloads`] drops download connections mid-body, and
+//! [`Mock115::fail_renames`] fails renames.
+//!
+//! The token refresh endpoint and the recycle bin are not mocked.
+
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::{Path, Query, Request, State},
+    http::{HeaderMap, StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+};
+use base64::Engine;
+use clap::Parser;
+use hmac::{Hmac, Mac};
+use serde_json::{Value, json};
+use sha1::{Digest, Sha1};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::config::Config;
+
+/// OSS host of the upload endpoint; [`Mock115::config`] resolves it (with
+/// the bucket in front) to the mock.
+pub const OSS_HOST: &str = "oss.mock115.test";
+const BUCKET: &str = "mock-bucket";
+const ACCESS_KEY_ID: &str = "mock-key-id";
+const ACCESS_KEY_SECRET: &str = "mock-key-secret";
+const SECURITY_TOKEN: &str = "mock-security-token";
+/// Quota of the mock account.
+const TOTAL_SPACE: u64 = 1 << 40;
+/// First file id. Like 115's, ids are numbers that grow with each file; they
+/// start just below a power of ten so code comparing them as strings breaks.
+const FIRST_ID: u64 = 99_999_990;
+
+struct Node {
+    parent_id: String,
+    name: String,
+    is_dir: bool,
+    data: Bytes,
+    pick_code: String,
+    /// Uppercase hex, as 115 reports it.
+    sha1: String,
+}
+
+/// An upload between init and its OSS PutObject.
+struct PendingUpload {
+    parent_id: String,
+    name: String,
+    size: usize,
+    sha1: String,
+    pick_code: String,
+}
+
+#[derive(Default)]
+struct Tree {
+    next_id: u64,
+    /// By file id; the root folder ("0") is implicit.
+    nodes: BTreeMap<String, Node>,
+    /// By OSS object name.
+    pending: HashMap<String, PendingUpload>,
+}
+
+impl Tree {
+    fn add(&mut self, parent_id: &str, name: &str, is_dir: bool, data: Bytes) -> String {
+        self.next_id += 1;
+        let id = (FIRST_ID + self.next_id).to_string();
+        let sha1 = if is_dir {
+            String::new()
+        } else {
+            hex::encode_upper(Sha1::digest(&data))
+        };
+        self.nodes.insert(
+            id.clone(),
+            Node {
+                parent_id: parent_id.to_string(),
+                name: name.to_string(),
+                is_dir,
+                data,
+                pick_code: format!("pc{id}"),
+                sha1,
+            },
+        );
+        id
+    }
+
+    fn child(&self, parent_id: &str, name: &str) -> Option<(&String, &Node)> {
+        self.nodes
+            .iter()
+            .filter(|(_, n)| n.parent_id == parent_id && n.name == name)
+            .max_by_key(|(id, _)| id.parse::<u64>().ok())
+    }
+
+    fn by_pick_code(&self, pick_code: &str) -> Option<(&String, &Node)> {
+        self.nodes.iter().find(|(_, n)| n.pick_code == pick_code)
+    }
+
+    /// Id of the newest file or folder at `path`.
+    fn lookup(&self, path: &str) -> Option<String> {
+        let mut id = "0".to_string();
+        for part in path.split('/').filter(|p| !p.is_empty()) {
+            id = self.child(&id, part)?.0.clone();
+        }
+        Some(id)
+    }
+
+    /// Id of the folder at `path`, creating missing folders.
+    fn mkdir_p(&mut self, path: &str) -> String {
+        let mut id = "0".to_string();
+        for part in path.split('/').filter(|p| !p.is_empty()) {
+            id = match self.child(&id, part) {
+                Some((child, node)) if node.is_dir => child.clone(),
+                _ => self.add(&id.clone(), part, true, Bytes::new()),
+            };
+        }
+        id
+    }
+
+    fn remove(&mut self, id: &str) {
+        if self.nodes.remove(id).is_none() {
+            return;
+        }
+        let children: Vec<String> = self
+            .nodes
+            .iter()
+            .filter(|(_, n)| n.parent_id == id)
+            .map(|(child, _)| child.clone())
+            .collect();
+        for child in children {
+            self.remove(&child);
+        }
+    }
+
+    fn used(&self) -> u64 {
+        self.nodes.values().map(|n| n.data.len() as u64).sum()
+    }
+}
+
+struct Shared {
+    addr: SocketAddr,
+    tree: parking_lot::Mutex<Tree>,
+    /// Calls by path (`/oss` and `/download` for all objects).
+    calls: parking_lot::Mutex<HashMap<String, usize>>,
+    /// Downloads still to cut short, and after how many bytes.
+    cuts: parking_lot::Mutex<(usize, usize)>,
+    /// Renames still to fail, and how many to let through first.
+    rename_faults: parking_lot::Mutex<(usize, usize)>,
+}
+
+/// A running mock; the server stops when it is dropped.
+pub struct Mock115 {
+    shared: Arc<Shared>,
+    server: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for Mock115 {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+impl Mock115 {
+    /// Serve an empty account on a free local port.
+    pub async fn start() -> std::io::Result<Self> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let shared = Arc::new(Shared {
+            addr: listener.local_addr()?,
+            tree: Default::default(),
+            calls: Default::default(),
+            cuts: Default::default(),
+            rename_faults: Default::default(),
+        });
+        let app = Router::new()
+            .route("/open/ufile/files", get(list))
+            .route("/open/ufile/search", get(search))
+            .route("/open/folder/add", post(add_folder))
+            .route("/open/ufile/update", post(rename))
+            .route("/open/ufile/delete", post(delete))
+            .route("/open/ufile/downurl", post(download_url))
+            .route("/open/upload/init", post(upload_init))
+            .route("/open/upload/get_token", get(upload_token))
+            .route("/open/user/info", get(user_info))
+            .route("/oss/:object", put(oss_put))
+            .route("/download/:pick_code", get(download))
+            .fallback(|| async { (StatusCode::NOT_FOUND, fail(404, "not mocked")) })
+            .layer(middleware::from_fn_with_state(shared.clone(), count_call))
+            .with_state(shared.clone());
+        let server = tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+        Ok(Self { shared, server })
+    }
+
+    /// Base URL of the mock API (`--api-base`).
+    pub fn api_base(&self) -> String {
+        format!("http://{}", self.shared.addr)
+    }
+
+    /// Configuration of a server using the mock for the repository at
+    /// `repo_path`, with an in-memory cache DB and strict response parsing.
+    pub fn config(&self, repo_path: &str) -> Config {
+        let resolve = format!("{BUCKET}.{OSS_HOST}:{}", self.shared.addr.ip());
+        Config::try_parse_from([
+            "restic-115",
+            "--access-token",
+            "mock-access-token",
+            "--refresh-token",
+            "mock-refresh-token",
+            "--api-base",
+            &self.api_base(),
+            "--resolve",
+            &resolve,
+            "--db-path",
+            ":memory:",
+            "--repo-path",
+            repo_path,
+            "--response-parsing",
+            "strict",
+        ])
+        .expect("mock configuration parses")
+    }
+
+    /// Number of calls to `path`, e.g. `/open/ufile/downurl`; `/oss` counts
+    /// OSS uploads and `/download` downloads.
+    pub fn calls(&self, path: &str) -> usize {
+        self.shared.calls.lock().get(path).copied().unwrap_or(0)
+    }
+
+    /// Store `data` at `path`, creating missing folders, as if uploaded by
+    /// another client.
+    pub fn put(&self, path: &str, data: impl Into<Bytes>) {
+        let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let mut tree = self.shared.tree.lock();
+        let parent_id = tree.mkdir_p(dir);
+        tree.add(&parent_id, name, false, data.into());
+    }
+
+    /// Replace the content of the newest file at `path` in place, keeping
+    /// its id and pick code, so a client's cached size goes stale.
+    pub fn rewrite(&self, path: &str, data: impl Into<Bytes>) {
+        let mut tree = self.shared.tree.lock();
+        let id = tree.lookup(path).expect("file exists");
+        let node = tree.nodes.get_mut(&id).expect("file exists");
+        node.data = data.into();
+        node.sha1 = hex::encode_upper(Sha1::digest(&node.data));
+    }
+
+    /// Content of the newest file at `path`.
+    pub fn read(&self, path: &str) -> Option<Bytes> {
+        let tree = self.shared.tree.lock();
+        let node = tree.nodes.get(&tree.lookup(path)?)?;
+        (!node.is_dir).then(|| node.data.clone())
+    }
+
+    /// Drop the connection of the next `times` downloads after `after` bytes
+    /// of the body, as a flaky link to OSS would.
+    pub fn cut_downloads(&self, times: usize, after: usize) {
+        *self.shared.cuts.lock() = (times, after);
+    }
+
+    /// Fail the next `times` renames after letting `after` of them through.
+    pub fn fail_renames(&self, times: usize, after: usize) {
+        *self.shared.rename_faults.lock() = (times, after);
+    }
+
+    /// Number of files and folders named `name` in the folder at `dir`.
+    pub fn count(&self, dir: &str, name: &str) -> usize {
+        let tree = self.shared.tree.lock();
+        let Some(parent_id) = tree.lookup(dir) else {
+            return 0;
+        };
+        tree.nodes
+            .values()
+            .filter(|n| n.parent_id == parent_id && n.name == name)
+            .count()
+    }
+}
+
+async fn count_call(State(shared): State<Arc<Shared>>, req: Request, next: Next) -> Response {
+    let path = req.uri().path();
+    let key = ["/oss/", "/download/"]
+        .into_iter()
+        .find(|prefix| path.starts_with(prefix))
+        .map_or(path, |prefix| prefix.trim_end_matches('/'));
+    *shared.calls.lock().entry(key.to_string()).or_default() += 1;
+    if path.starts_with("/open/")
+        && !req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.len() > "Bearer ".len() && v.starts_with("Bearer "))
+    {
+        return fail(40140123, "access_token missing").into_response();
+    }
+    next.run(req).await
+}
+
+fn ok(data: Value) -> Json<Value> {
+    Json(json!({"state": true, "code": 0, "message": "", "data": data}))
+}
+
+fn fail(code: i64, message: &str) -> Json<Value> {
+    Json(json!({"state": false, "code": code, "message": message, "data": []}))
+}
+
+/// Text fields of a `multipart/form-data` body, as the client sends forms.
+fn form_fields(headers: &HeaderMap, body: &[u8]) -> HashMap<String, String> {
+    let Some(boundary) = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split("boundary=").nth(1))
+    else {
+        return HashMap::new();
+    };
+    let delimiter = format!("--{}", boundary.trim_matches('"'));
+    String::from_utf8_lossy(body)
+        .split(&delimiter)
+        .filter_map(|part| {
+            let (head, value) = part.split_once("\r\n\r\n")?;
+            let name = head.split("name=\"").nth(1)?.split('"').next()?;
+            let value = value.strip_suffix("\r\n").unwrap_or(value);
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+fn param<'a>(params: &'a HashMap<String, String>, name: &str) -> &'a str {
+    params.get(name).map_or("", String::as_str)
+}
+
+async fn list(
+    State(shared): State<Arc<Shared>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    let cid = param(&query, "cid");
+    let offset: usize = param(&query, "offset").parse().unwrap_or(0);
+    let limit: usize = param(&query, "limit").parse().unwrap_or(20);
+    let tree = shared.tree.lock();
+    let children: Vec<Value> = tree
+        .nodes
+        .iter()
+        .filter(|(_, n)| n.parent_id == cid)
+        .map(|(id, n)| {
+            json!({
+                "fid": id,
+                "pid": n.parent_id,
+                "fc": if n.is_dir { "0" } else { "1" },
+                "fn": n.name,
+                "fs": n.data.len(),
+                "pc": n.pick_code,
+                "sha1": n.sha1,
+            })
+        })
+        .collect();
+    let count = children.len();
+    let page: Vec<Value> = children.into_iter().skip(offset).take(limit).collect();
+    Json(json!({
+        "state": true,
+        "code": 0,
+        "message": "",
+        "count": count,
+        "offset": offset,
+        "limit": limit,
+        "cid": cid,
+        "data": page,
+    }))
+}
+
+async fn search(
+    State(shared): State<Arc<Shared>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    let cid = param(&query, "cid");
+    let keyword = param(&query, "search_value");
+    let tree = shared.tree.lock();
+    let results: Vec<Value> = tree
+        .nodes
+        .iter()
+        .filter(|(_, n)| !n.is_dir && n.parent_id == cid && n.name.contains(keyword))
+        .map(|(id, n)| {
+            json!({
+                "file_id": id,
+                "file_name": n.name,
+                "parent_id": n.parent_id,
+                "file_size": n.data.len().to_string(),
+                "pick_code": n.pick_code,
+                "sha1": n.sha1,
+                "file_category": "1",
+                "area_id": "1",
+            })
+        })
+        .collect();
+    let mut response = ok(json!(results));
+    response.0["count"] = results.len().into();
+    response
+}
+
+async fn add_folder(
+    State(shared): State<Arc<Shared>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Json<Value> {
+    let form = form_fields(&headers, &body);
+    let (pid, name) = (param(&form, "pid"), param(&form, "file_name"));
+    let mut tree = shared.tree.lock();
+    if tree.child(pid, name).is_some_and(|(_, n)| n.is_dir) {
+        return fail(20004, "folder already exists");
+    }
+    let id = tree.add(pid, name, true, Bytes::new());
+    ok(json!({"file_id": id, "file_name": name}))
+}
+
+async fn rename(State(shared): State<Arc<Shared>>, headers: HeaderMap, body: Bytes) -> Json<Value> {
+    {
+        let mut faults = shared.rename_faults.lock();
+        if faults.1 > 0 {
+            faults.1 -= 1;
+        } else if faults.0 > 0 {
+            faults.0 -= 1;
+            return fail(50002, "rename failed");
+        }
+    }
+    let form = form_fields(&headers, &body);
+    let mut tree = shared.tree.lock();
+    match tree.nodes.get_mut(param(&form, "file_id")) {
+        Some(node) => {
+            node.name = param(&form, "file_name").to_string();
+            ok(json!({"file_name": node.name}))
+        }
+        None => fail(50001, "file not found"),
+    }
+}
+
+async fn delete(State(shared): State<Arc<Shared>>, headers: HeaderMap, body: Bytes) -> Json<Value> {
+    let form = form_fields(&headers, &body);
+    let mut tree = shared.tree.lock();
+    for id in param(&form, "file_ids").split(',') {
+        tree.remove(id);
+    }
+    ok(json!([]))
+}
+
+async fn download_url(
+    State(shared): State<Arc<Shared>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Json<Value> {
+    let form = form_fields(&headers, &body);
+    let tree = shared.tree.lock();
+    let Some((id, node)) = tree.by_pick_code(param(&form, "pick_code")) else {
+        return fail(50003, "file not found");
+    };
+    ok(json!({
+        id.as_str(): {
+            "file_name": node.name,
+            "file_size": node.data.len(),
+            "pick_code": node.pick_code,
+            "sha1": node.sha1,
+            "url": {"url": format!("http://{}/download/{}", shared.addr, node.pick_code)},
+        }
+    }))
+}
+
+async fn download(
+    State(shared): State<Arc<Shared>>,
+    Path(pick_code): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(data) = shared
+        .tree
+        .lock()
+        .by_pick_code(&pick_code)
+        .map(|(_, n)| n.data.clone())
+    else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let cut = {
+        let mut cuts = shared.cuts.lock();
+        (cuts.0 > 0).then(|| {
+            cuts.0 -= 1;
+            cuts.1
+        })
+    };
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("bytes="))
+        .and_then(|v| v.split_once('-'))
+        .and_then(|(start, end)| Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?)));
+    let (status, content_range, body) = match range {
+        Some((start, end)) if start <= end && start < data.len() => {
+            let end = end.min(data.len() - 1);
+            let content_range = format!("bytes {start}-{end}/{}", data.len());
+            (
+                StatusCode::PARTIAL_CONTENT,
+                Some(content_range),
+                data.slice(start..=end),
+            )
+        }
+        Some(_) => return StatusCode::RANGE_NOT_SATISFIABLE.into_response(),
+        None => (StatusCode::OK, None, data),
+    };
+    let mut response = match cut.filter(|&after| after < body.len()) {
+        // The full Content-Length, then an error after `after` bytes.
+        Some(after) => {
+            let len = body.len();
+            use futures::StreamExt;
+
+            // The pause lets the head and the first bytes go out first.
+            let cut = futures::stream::once(async {
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                Err(std::io::Error::other("connection cut by mock"))
+            });
+            let chunks = futures::stream::iter([Ok(body.slice(..after))]).chain(cut);
+            let mut response = axum::body::Body::from_stream(chunks).into_response();
+            response
+                .headers_mut()
+                .insert(header::CONTENT_LENGTH, len.into());
+            response
+        }
+        None => body.into_response(),
+    };
+    *response.status_mut() = status;
+    if let Some(content_range) = content_range {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_RANGE, content_range.parse().unwrap());
+    }
+    response
+}
+
+async fn upload_init(
+    State(shared): State<Arc<Shared>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Json<Value> {
+    let form = form_fields(&headers, &body);
+    let Some(parent_id) = param(&form, "target").strip_prefix("U_1_") else {
+        return fail(10002, "invalid target");
+    };
+    let name = param(&form, "file_name");
+    let size: usize = param(&form, "file_size").parse().unwrap_or(0);
+    let sha1 = param(&form, "fileid").to_ascii_uppercase();
+    let mut tree = shared.tree.lock();
+    // Content the account already holds is linked without an upload.
+    if let Some(data) = tree
+        .nodes
+        .values()
+        .find(|n| !n.is_dir && n.sha1 == sha1 && n.data.len() == size)
+        .map(|n| n.data.clone())
+    {
+        let id = tree.add(parent_id, name, false, data);
+        let pick_code = tree.nodes[&id].pick_code.clone();
+        return ok(json!({
+            "status": 2,
+            "statuscode": 0,
+            "statusmsg": "",
+            "file_id": id,
+            "pick_code": pick_code,
+            "target": format!("U_1_{parent_id}"),
+        }));
+    }
+    tree.next_id += 1;
+    let object = format!("oss/upload-{}", tree.next_id);
+    let pick_code = format!("pcu{}", tree.next_id);
+    tree.pending.insert(
+        object.clone(),
+        PendingUpload {
+            parent_id: parent_id.to_string(),
+            name: name.to_string(),
+            size,
+            sha1,
+            pick_code: pick_code.clone(),
+        },
+    );
+    ok(json!({
+        "status": 1,
+        "statuscode": 0,
+        "statusmsg": "",
+        "pick_code": pick_code,
+        "target": format!("U_1_{parent_id}"),
+        "bucket": BUCKET,
+        "object": object,
+        "callback": {
+            "callback": json!({
+                "callbackUrl": format!("http://{}/callback", shared.addr),
+                "callbackBody": "bucket=${bucket}&object=${object}",
+            }).to_string(),
+            "callback_var": json!({"x:pick_code": pick_code}).to_string(),
+        },
+    }))
+}
+
+async fn upload_token(State(shared): State<Arc<Shared>>) -> Json<Value> {
+    ok(json!({
+        "endpoint": format!("http://{OSS_HOST}:{}", shared.addr.port()),
+        "AccessKeyId": ACCESS_KEY_ID,
+        "AccessKeySecret": ACCESS_KEY_SECRET,
+        "SecurityToken": SECURITY_TOKEN,
+    }))
+}
+
+async fn user_info(State(shared): State<Arc<Shared>>) -> Json<Value> {
+    let used = shared.tree.lock().used();
+    ok(json!({
+        "user_id": 1,
+        "user_name": "mock",
+        "rt_space_info": {
+            "all_total": {"size": TOTAL_SPACE},
+            "all_remain": {"size": TOTAL_SPACE.saturating_sub(used)},
+            "all_use": {"size": used},
+        },
+    }))
+}
+
+fn oss_error(status: StatusCode, code: &str) -> Response {
+    let body = format!("<?xml version=\"1.0\"?><Error><Code>{code}</Code></Error>");
+    (status, [(header::CONTENT_TYPE, "application/xml")], body).into_response()
+}
+
+async fn oss_put(
+    State(shared): State<Arc<Shared>>,
+    Path(object): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let object = format!("oss/{object}");
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+    };
+    let bucket = header("host").split('.').next().unwrap_or_default();
+    let mut oss_headers: Vec<(&str, &str)> = headers
+        .keys()
+        .filter(|name| name.as_str().starts_with("x-oss-"))
+        .map(|name| (name.as_str(), header(name.as_str())))
+        .collect();
+    oss_headers.sort_unstable();
+    let canonical_headers: String = oss_headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect();
+    let string_to_sign = format!(
+        "PUT\n{}\n{}\n{}\n{canonical_headers}/{bucket}/{object}",
+        header("content-md5"),
+        header("content-type"),
+        header("date"),
+    );
+    let mut mac = Hmac::<Sha1>::new_from_slice(ACCESS_KEY_SECRET.as_bytes()).unwrap();
+    mac.update(string_to_sign.as_bytes());
+    let signature = base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes());
+    if bucket != BUCKET {
+        return oss_error(StatusCode::NOT_FOUND, "NoSuchBucket");
+    }
+    if header("authorization") != format!("OSS {ACCESS_KEY_ID}:{signature}")
+        || header("x-oss-security-token") != SECURITY_TOKEN
+    {
+        return oss_error(StatusCode::FORBIDDEN, "SignatureDoesNotMatch");
+    }
+    let md5 = base64::engine::general_purpose::STANDARD.encode(md5::Md5::digest(&body));
+    if header("content-md5") != md5 {
+        return oss_error(StatusCode::BAD_REQUEST, "InvalidDigest");
+    }
+
+    let mut tree = shared.tree.lock();
+    let Some(upload) = tree.pending.remove(&object) else {
+        return oss_error(StatusCode::BAD_REQUEST, "CallbackFailed");
+    };
+    if upload.size != body.len() || upload.sha1 != hex::encode_upper(Sha1::digest(&body)) {
+        return oss_error(StatusCode::BAD_REQUEST, "CallbackFailed");
+    }
+    let id = tree.add(&upload.parent_id, &upload.name, false, body);
+    let node = tree.nodes.get_mut(&id).unwrap();
+    node.pick_code = upload.pick_code;
+    Json(json!({
+        "state": true,
+        "code": 0,
+        "message": "",
+        "data": {
+            "file_id": id,
+            "file_name": node.name,
+            "file_size": node.data.len(),
+            "pick_code": node.pick_code,
+            "sha1": node.sha1,
+            "cid": node.parent_id,
+        },
+    }))
+    .into_response()
+}
@@ -0,0 +1,221 @@
+//! Adaptive pacing of 115 API calls (`OPEN115_ADAPTIVE_RATE_LIMIT`).
+//!
+//! 115 answers too many calls with HTTP 429 or code 406, and keeps doing so
+//! for a while if the pressure does not let up. Retries with backoff handle
+//! single refusals; this handles a sustained overload. The outcome of every
+//! API call is kept for a sliding window, and once the share of refusals in
+//! it crosses a threshold, calls are paced at half the rate the window saw.
+//! Every further overload halves the rate again; after a quiet spell it grows
+//! back by a quarter per step, and pacing stops when it is back above the
+//! rate that caused the first refusal.
+
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Gauge of the current API call rate limit in calls per second (0: none).
+pub const API_RATE_LIMIT: &str = "restic115_api_rate_limit";
+/// Gauge of the share of API calls refused in the sliding window.
+pub const API_REFUSAL_RATIO: &str = "restic115_api_refusal_ratio";
+
+/// Span of the refusal-rate estimate.
+const WINDOW: Duration = Duration::from_secs(60);
+/// Share of refused calls in the window that counts as overload.
+const REFUSAL_THRESHOLD: f64 = 0.05;
+/// Refusals needed in the window before acting on their share, so one
+/// refusal among few calls does not halve the rate.
+const MIN_REFUSALS: usize = 2;
+/// Minimum time between two decreases, so a burst of refusals from calls
+/// sent before the first one counts once.
+const DECREASE_INTERVAL: Duration = Duration::from_secs(5);
+/// Time without refusals between two increases.
+const RECOVERY_INTERVAL: Duration = Duration::from_secs(30);
+const RECOVERY_FACTOR: f64 = 1.25;
+/// Floor of the limit in calls per second.
+const MIN_RATE: f64 = 0.2;
+
+#[derive(Debug)]
+pub struct AdaptiveRateLimiter {
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    /// Time and refusal flag of each call in the window.
+    outcomes: VecDeque<(Instant, bool)>,
+    /// Calls per second, when pacing.
+    limit: Option<f64>,
+    /// Rate at which pacing began; it ends when the limit recovers past it.
+    ceiling: f64,
+    /// Earliest start of the next paced call.
+    next_slot: Instant,
+    /// Last decrease or increase of the limit.
+    changed: Instant,
+    last_refusal: Option<Instant>,
+}
+
+impl Default for AdaptiveRateLimiter {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            state: Mutex::new(State {
+                outcomes: VecDeque::new(),
+                limit: None,
+                ceiling: 0.0,
+                next_slot: now,
+                changed: now,
+                last_refusal: None,
+            }),
+        }
+    }
+}
+
+impl AdaptiveRateLimiter {
+    /// Wait for the next call slot.
+    pub async fn acquire(&self) {
+        let wait = self.reserve(Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Record the outcome of a call; `refused` for HTTP 429 and code 406.
+    pub fn record(&self, refused: bool) {
+        self.observe(Instant::now(), refused);
+    }
+
+    fn reserve(&self, now: Instant) -> Duration {
+        let mut state = self.state.lock();
+        state.recover(now);
+        let Some(rate) = state.limit else {
+            return Duration::ZERO;
+        };
+        let slot = state.next_slot.max(now);
+        state.next_slot = slot + Duration::from_secs_f64(1.0 / rate);
+        slot - now
+    }
+
+    fn observe(&self, now: Instant, refused: bool) {
+        let mut state = self.state.lock();
+        state.outcomes.push_back((now, refused));
+        while state
+            .outcomes
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > WINDOW)
+        {
+            state.outcomes.pop_front();
+        }
+        let refusals = state.outcomes.iter().filter(|(_, r)| *r).count();
+        let ratio = refusals as f64 / state.outcomes.len() as f64;
+        metrics::gauge!(API_REFUSAL_RATIO).set(ratio);
+        if !refused {
+            return;
+        }
+        state.last_refusal = Some(now);
+        if refusals < MIN_REFUSALS
+            || ratio < REFUSAL_THRESHOLD
+            || (state.limit.is_some() && now.duration_since(state.changed) < DECREASE_INTERVAL)
+        {
+            return;
+        }
+        // Rate over the part of the window that has calls, so a burst right
+        // after startup or a quiet period is not averaged down.
+        let span = state
+            .outcomes
+            .front()
+            .map_or(WINDOW, |(at, _)| now.duration_since(*at));
+        let observed =
+            state.outcomes.len() as f64 / span.clamp(Duration::from_secs(1), WINDOW).as_secs_f64();
+        let current = match state.limit {
+            Some(limit) => limit,
+            None => {
+                state.ceiling = observed;
+                observed
+            }
+        };
+        let limit = (current / 2.0).max(MIN_RATE);
+        state.set_limit(Some(limit), now);
+        tracing::warn!(
+            "115 refused {:.0}% of API calls in the last {}s; limiting to {:.2} calls/s",
+            ratio * 100.0,
+            WINDOW.as_secs(),
+            limit
+        );
+    }
+}
+
+impl State {
+    /// Raise the limit after a quiet spell, and stop pacing once it is back
+    /// above the ceiling.
+    fn recover(&mut self, now: Instant) {
+        let Some(limit) = self.limit else {
+            return;
+        };
+        let quiet_since = self
+            .last_refusal
+            .map_or(self.changed, |r| r.max(self.changed));
+        if now.duration_since(quiet_since) < RECOVERY_INTERVAL {
+            return;
+        }
+        let raised = limit * RECOVERY_FACTOR;
+        if raised > self.ceiling {
+            self.set_limit(None, now);
+            tracing::info!("115 API calls no longer limited");
+        } else {
+            self.set_limit(Some(raised), now);
+            tracing::info!("Raised the 115 API call limit to {:.2} calls/s", raised);
+        }
+    }
+
+    fn set_limit(&mut self, limit: Option<f64>, now: Instant) {
+        self.limit = limit;
+        self.changed = now;
+        metrics::gauge!(API_RATE_LIMIT).set(limit.unwrap_or(0.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adaptive_rate_limit() {
+        let limiter = AdaptiveRateLimiter::default();
+        let limit = |l: &AdaptiveRateLimiter| l.state.lock().limit;
+        let start = Instant::now();
+        // 10 calls/s for 12s.
+        for i in 0..118 {
+            limiter.observe(start + Duration::from_millis(i * 100), false);
+        }
+        let t = start + Duration::from_secs(12);
+        limiter.observe(t, true);
+        assert_eq!(limit(&limiter), None, "one refusal is not an overload");
+        limiter.observe(t, true);
+        assert_eq!(limit(&limiter), None, "2 of 120 is below the threshold");
+        for _ in 0..8 {
+            limiter.observe(t, true);
+        }
+        let halved = limit(&limiter).unwrap();
+        assert!((halved - 125.0 / 12.0 / 2.0).abs() < 0.01, "{halved}");
+
+        // Paced calls are spaced by 1/limit.
+        assert_eq!(limiter.reserve(t), Duration::ZERO);
+        let wait = limiter.reserve(t);
+        assert!((wait.as_secs_f64() - 1.0 / halved).abs() < 0.01, "{wait:?}");
+
+        // Refusals right after a decrease do not compound it.
+        limiter.observe(t + Duration::from_secs(1), true);
+        assert_eq!(limit(&limiter), Some(halved));
+
+        // Quiet spells raise the limit step by step, then lift it.
+        let mut now = t + Duration::from_secs(1);
+        let mut steps = 0;
+        while limit(&limiter).is_some() {
+            now += RECOVERY_INTERVAL;
+            limiter.reserve(now);
+            steps += 1;
+        }
+        assert_eq!(steps, 4);
+    }
+}
@@ -5,33 +5,50 @@ use parking_lot::RwLock;
 use reqwest::Client;
 use std::sync::Arc;
 
-use super::database::entities::tokens;
+use super::database::{CacheDb, current_tokens, save_tokens, try_lock_refresh, unlock_refresh};
+use super::retry::{self, Retrier};
+use super::shape::{Shape, ShapeChecker};
 use super::types::RefreshTokenResponse;
 use crate::error::{AppError, Result};
-use sea_orm::{ActiveModelTrait, DatabaseConnection, EntityTrait, Set};
+use serde_json::Value;
 
 const REFRESH_URL: &str = "https://passportapi.115.com/open/refreshToken";
+/// How long a refresh may hold the cross-instance refresh lock before other
+/// instances take it over.
+const REFRESH_LOCK_TTL: std::time::Duration = std::time::Duration::from_secs(60);
+/// How often a refresh waiting for another instance's checks the DB.
+const REFRESH_LOCK_POLL: std::time::Duration = std::time::Duration::from_millis(250);
 
-const MAX_REFRESH_TOKEN_RETRIES: usize = 1;
+/// Time spent refreshing the access token, retries included.
+pub const TOKEN_REFRESH_DURATION_SECONDS: &str = "restic115_token_refresh_duration_seconds";
+/// Token refreshes, by `result` (`ok` or `failed`).
+pub const TOKEN_REFRESHES_TOTAL: &str = "restic115_token_refreshes_total";
 
 fn is_refresh_rate_limited(code: i64) -> bool {
     // See docs/115/接入指南/授权错误码.md
     code == 40140117
 }
 
-async fn backoff_sleep(attempt: usize) {
-    // Exponential backoff with a cap.
-    // attempt starts at 1.
-    // Keep the cap small so refresh can't stall the process for minutes.
-    let secs = (1u64 << (attempt - 1)).min(16);
-    tokio::time::sleep(std::time::Duration::from_secs(secs)).await;
-}
-
 #[derive(Debug, Clone)]
 struct TokenInfo {
     access_token: String,
     refresh_token: String,
     expires_at: Option<DateTime<Utc>>,
+    /// When the tokens were last stored, if known.
+    updated_at: Option<DateTime<Utc>>,
+}
+
+/// State of the current access token, without the token values.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TokenStatus {
+    pub has_token: bool,
+    /// Unix time in seconds; only known after a refresh reported the lifetime.
+    pub expires_at: Option<i64>,
+    pub expires_in_secs: Option<i64>,
+    /// Expired or expiring within 5 minutes; the next API call refreshes it.
+    pub expired: bool,
+    /// Unix time in seconds when the tokens were last refreshed or replaced.
+    pub updated_at: Option<i64>,
 }
 
 impl TokenInfo {
@@ -47,47 +64,53 @@ impl TokenInfo {
 #[derive(Clone)]
 pub struct TokenManager {
     http_client: Client,
-    db: DatabaseConnection,
+    /// Used only for the refresh endpoint, so a pool busy with (or stuck on)
+    /// OSS and download transfers can never hold up a refresh.
+    refresh_client: Client,
+    db: CacheDb,
     token: Arc<RwLock<Option<TokenInfo>>>,
+    retry: Retrier,
+    shapes: Arc<ShapeChecker>,
+    /// Held while refreshing, so concurrent callers wait for one refresh.
+    refreshing: Arc<tokio::sync::Mutex<()>>,
+    /// Identifies this process in the DB's refresh lock.
+    lock_holder: Arc<str>,
 }
 
 impl TokenManager {
     pub async fn new(
-        db: DatabaseConnection,
+        http_client: Client,
+        refresh_client: Client,
+        db: CacheDb,
         access_token: Option<String>,
         refresh_token: Option<String>,
+        retry: Retrier,
+        shapes: Arc<ShapeChecker>,
     ) -> Result<Self> {
-        let http_client = Client::builder()
-            .timeout(std::time::Duration::from_secs(30))
-            .build()
-            .expect("Failed to create HTTP client");
-
         let this = Self {
             http_client,
+            refresh_client,
             db,
             token: Arc::new(RwLock::new(None)),
+            retry,
+            shapes,
+            refreshing: Arc::new(tokio::sync::Mutex::new(())),
+            lock_holder: format!("{}-{}", std::process::id(), uuid::Uuid::new_v4()).into(),
         };
 
         // Try load from DB
-        let db_token = tokens::Entity::find_by_id(1)
-            .one(&this.db)
+        let db_token = current_tokens(&this.db.conn())
             .await
             .map_err(|e| AppError::Internal(format!("DB error loading tokens: {e}")))?;
 
-        let (a, r) = if let Some(t) = db_token {
-            (t.access_token, t.refresh_token)
+        let (a, r, expires_at, updated_at) = if let Some(t) = db_token {
+            (t.access_token, t.refresh_token, t.expires_at, t.updated_at)
         } else if let (Some(a), Some(r)) = (access_token, refresh_token) {
             // No DB token, but have env tokens; store them
-            let am = tokens::ActiveModel {
-                id: Set(1),
-                access_token: Set(a.clone()),
-                refresh_token: Set(r.clone()),
-                updated_at: Set(Utc::now()),
-            };
-            am.insert(&this.db)
+            save_tokens(&this.db.conn(), &a, &r, None)
                 .await
                 .map_err(|e| AppError::Internal(format!("DB error saving tokens: {e}")))?;
-            (a, r)
+            (a, r, None, Utc::now())
         } else {
             return Ok(this);
         };
@@ -97,7 +120,8 @@ impl TokenManager {
             *guard = Some(TokenInfo {
                 access_token: a,
                 refresh_token: r,
-                expires_at: None,
+                expires_at,
+                updated_at: Some(updated_at),
             });
         }
 
@@ -116,6 +140,26 @@ impl TokenManager {
         self.token.read().as_ref().map(|t| t.access_token.clone())
     }
 
+    pub fn status(&self) -> TokenStatus {
+        let guard = self.token.read();
+        let Some(t) = guard.as_ref() else {
+            return TokenStatus {
+                has_token: false,
+                expires_at: None,
+                expires_in_secs: None,
+                expired: true,
+                updated_at: None,
+            };
+        };
+        TokenStatus {
+            has_token: true,
+            expires_at: t.expires_at.map(|at| at.timestamp()),
+            expires_in_secs: t.expires_at.map(|at| (at - Utc::now()).num_seconds()),
+            expired: t.is_expired(),
+            updated_at: t.updated_at.map(|at| at.timestamp()),
+        }
+    }
+
     pub async fn get_token(&self) -> Result<String> {
         {
             let guard = self.token.read();
@@ -128,7 +172,97 @@ impl TokenManager {
         self.refresh_token().await
     }
 
+    /// Refresh now unless the current token is known to stay valid for at
+    /// least `min`. Returns whether a refresh happened.
+    pub async fn ensure_valid_for(&self, min: Duration) -> Result<bool> {
+        let fresh = self
+            .token
+            .read()
+            .as_ref()
+            .and_then(|t| t.expires_at)
+            .is_some_and(|expires_at| Utc::now() + min < expires_at);
+        if fresh {
+            return Ok(false);
+        }
+        self.refresh_token().await?;
+        Ok(true)
+    }
+
     pub async fn refresh_token(&self) -> Result<String> {
+        let start = std::time::Instant::now();
+        let result = self.refresh_token_inner().await;
+        metrics::histogram!(TOKEN_REFRESH_DURATION_SECONDS).record(start.elapsed().as_secs_f64());
+        let outcome = if result.is_ok() { "ok" } else { "failed" };
+        metrics::counter!(TOKEN_REFRESHES_TOTAL, "result" => outcome).increment(1);
+        result
+    }
+
+    /// Refresh the tokens, unless another task or another instance sharing
+    /// the DB rotated them since this call started; their result is used
+    /// then. Refreshing twice with the same refresh token would invalidate
+    /// the pair the other refresh obtained.
+    async fn refresh_token_inner(&self) -> Result<String> {
+        let stale = self.refresh_token_value();
+        let _refreshing = self.refreshing.lock().await;
+        let deadline = tokio::time::Instant::now() + REFRESH_LOCK_TTL;
+        loop {
+            if let Some(access_token) = self.adopt_rotated(stale.as_deref()).await {
+                return Ok(access_token);
+            }
+            match try_lock_refresh(&self.db.conn(), &self.lock_holder, REFRESH_LOCK_TTL).await {
+                Ok(true) => break,
+                Ok(false) if tokio::time::Instant::now() < deadline => {
+                    tokio::time::sleep(REFRESH_LOCK_POLL).await;
+                }
+                Ok(false) => {
+                    tracing::warn!(
+                        "Token refresh lock still held by another instance; refreshing anyway"
+                    );
+                    return self.refresh_unlocked().await;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to take the token refresh lock: {}", e);
+                    return self.refresh_unlocked().await;
+                }
+            }
+        }
+        // The other holder may have finished between the check and the lock.
+        let result = match self.adopt_rotated(stale.as_deref()).await {
+            Some(access_token) => Ok(access_token),
+            None => self.refresh_unlocked().await,
+        };
+        if let Err(e) = unlock_refresh(&self.db.conn(), &self.lock_holder).await {
+            tracing::warn!("Failed to release the token refresh lock: {}", e);
+        }
+        result
+    }
+
+    /// Switch to the DB's current tokens if their refresh token is no longer
+    /// `stale`, returning the new access token.
+    async fn adopt_rotated(&self, stale: Option<&str>) -> Option<String> {
+        let current = match current_tokens(&self.db.conn()).await {
+            Ok(current) => current?,
+            Err(e) => {
+                tracing::warn!("DB error loading tokens: {}", e);
+                return None;
+            }
+        };
+        if Some(current.refresh_token.as_str()) == stale {
+            return None;
+        }
+        tracing::info!("Using tokens refreshed by another task or instance");
+        *self.token.write() = Some(TokenInfo {
+            access_token: current.access_token.clone(),
+            refresh_token: current.refresh_token,
+            expires_at: current.expires_at,
+            updated_at: Some(current.updated_at),
+        });
+        Some(current.access_token)
+    }
+
+    /// Call 115's refresh endpoint with the current refresh token and store
+    /// the result.
+    async fn refresh_unlocked(&self) -> Result<String> {
         let refresh = {
             let guard = self.token.read();
             guard
@@ -146,9 +280,12 @@ impl TokenManager {
         let body: RefreshTokenResponse = {
             let mut last_err: Option<AppError> = None;
             let mut ok_body: Option<RefreshTokenResponse> = None;
-            for attempt in 1..=MAX_REFRESH_TOKEN_RETRIES {
+            let max_attempts = self.retry.policy.max_attempts;
+            for attempt in 1..=max_attempts {
+                // Honor backoff from rate limits hit by API calls, too.
+                self.retry.gate.wait().await;
                 let response = self
-                    .http_client
+                    .refresh_client
                     .post(REFRESH_URL)
                     .header("Content-Type", "application/x-www-form-urlencoded")
                     .form(&[("refresh_token", refresh.as_str())])
@@ -159,39 +296,43 @@ impl TokenManager {
                     Ok(r) => r,
                     Err(e) => {
                         // Network/timeout: treat as retryable with backoff.
-                        if attempt < MAX_REFRESH_TOKEN_RETRIES {
+                        if self.retry.policy.can_retry(attempt) {
                             tracing::warn!(
                                 "refreshToken network error, backing off attempt {}/{}: {}",
                                 attempt,
-                                MAX_REFRESH_TOKEN_RETRIES,
+                                max_attempts,
                                 e
                             );
                             last_err = Some(AppError::HttpClient(e));
-                            backoff_sleep(attempt).await;
+                            self.retry.backoff(attempt).await;
                             continue;
                         }
                         return Err(AppError::HttpClient(e));
                     }
                 };
 
-                let parsed = response.json::<RefreshTokenResponse>().await;
+                let requested = retry::retry_after(response.headers());
+                let parsed = response.json::<Value>().await;
                 let body = match parsed {
                     Ok(b) => b,
                     Err(e) => {
-                        if attempt < MAX_REFRESH_TOKEN_RETRIES {
+                        if self.retry.policy.can_retry(attempt) {
                             tracing::warn!(
                                 "refreshToken JSON parse error, backing off attempt {}/{}: {}",
                                 attempt,
-                                MAX_REFRESH_TOKEN_RETRIES,
+                                max_attempts,
                                 e
                             );
                             last_err = Some(AppError::HttpClient(e));
-                            backoff_sleep(attempt).await;
+                            self.retry.backoff(attempt).await;
                             continue;
                         }
                         return Err(AppError::HttpClient(e));
                     }
                 };
+                self.shapes.check(Shape::Token, &body)?;
+                let requested = requested.or_else(|| retry::json_retry_after(&body));
+                let body: RefreshTokenResponse = serde_json::from_value(body)?;
 
                 let ok = body.state.unwrap_or(false);
                 let code = body.code.unwrap_or(-1);
@@ -200,19 +341,19 @@ impl TokenManager {
                     break;
                 }
 
-                if is_refresh_rate_limited(code) && attempt < MAX_REFRESH_TOKEN_RETRIES {
+                if is_refresh_rate_limited(code) && self.retry.policy.can_retry(attempt) {
                     tracing::warn!(
                         "refreshToken rate limited (code={}), backing off attempt {}/{}",
                         code,
                         attempt,
-                        MAX_REFRESH_TOKEN_RETRIES
+                        max_attempts
                     );
                     last_err = Some(AppError::Auth(format!(
                         "Failed to refresh token: code={}, message={}",
                         code,
                         body.message.clone().unwrap_or_default()
                     )));
-                    backoff_sleep(attempt).await;
+                    self.retry.backoff_after(attempt, requested).await;
                     continue;
                 }
 
@@ -241,38 +382,45 @@ impl TokenManager {
         })?;
 
         let expires_at = data.expires_in.map(|s| Utc::now() + Duration::seconds(s));
+        self.store(access_token.clone(), refresh_token, expires_at)
+            .await?;
+        Ok(access_token)
+    }
 
+    /// Replace both tokens, e.g. with ones an operator obtained after the old
+    /// refresh token was invalidated. The caller validates them first.
+    pub async fn set_tokens(&self, access_token: String, refresh_token: String) -> Result<()> {
+        self.store(access_token, refresh_token, None).await
+    }
+
+    /// Swap in new tokens and persist them.
+    async fn store(
+        &self,
+        access_token: String,
+        refresh_token: String,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<()> {
         {
             let mut guard = self.token.write();
             *guard = Some(TokenInfo {
                 access_token: access_token.clone(),
                 refresh_token: refresh_token.clone(),
                 expires_at,
+                updated_at: Some(Utc::now()),
             });
         }
 
-        // Persist refreshed tokens to DB
-        let am = tokens::ActiveModel {
-            id: Set(1),
-            access_token: Set(access_token.clone()),
-            refresh_token: Set(refresh_token.clone()),
-            updated_at: Set(Utc::now()),
-        };
-        tokens::Entity::insert(am)
-            .on_conflict(
-                sea_orm::sea_query::OnConflict::column(tokens::Column::Id)
-                    .update_columns([
-                        tokens::Column::AccessToken,
-                        tokens::Column::RefreshToken,
-                        tokens::Column::UpdatedAt,
-                    ])
-                    .to_owned(),
-            )
-            .exec(&self.db)
+        // Persist tokens to DB, keeping the previous pair as history
+        save_tokens(&self.db.conn(), &access_token, &refresh_token, expires_at)
             .await
             .map_err(|e| AppError::Internal(format!("DB error updating tokens: {e}")))?;
-
-        Ok(access_token)
+        if self.db.is_degraded() {
+            tracing::warn!(
+                "Cache DB is read-only; the new tokens are kept in memory only and will be \
+                 lost on restart"
+            );
+        }
+        Ok(())
     }
 }
 
@@ -286,6 +434,51 @@ impl std::fmt::Debug for TokenManager {
 
 #[cfg(test)]
 mod tests {
-    // Tests for persist_tokens_to_file were removed as the function is removed.
-    // Database logic is better tested in integration tests.
+    use super::*;
+    use crate::config::ResponseParsing;
+    use crate::open115::database::{init_db, sqlite_url};
+    use crate::open115::retry::{RateLimitGate, RetryPolicy};
+
+    #[tokio::test]
+    async fn test_refresh_uses_other_instances_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = sqlite_url(&dir.path().join("c.db").to_string_lossy(), "rwc");
+        let manager = |db| {
+            TokenManager::new(
+                Client::new(),
+                Client::new(),
+                CacheDb::new(db),
+                Some("a0".to_string()),
+                Some("r0".to_string()),
+                Retrier::new(RetryPolicy::refresh(), RateLimitGate::default()),
+                Arc::new(ShapeChecker::new(ResponseParsing::Lenient)),
+            )
+        };
+        let a = manager(init_db(&url, "/r").await.unwrap()).await.unwrap();
+        let b = manager(init_db(&url, "/r").await.unwrap()).await.unwrap();
+
+        // Neither refresh reaches 115: each finds the other's result.
+        a.set_tokens("a1".to_string(), "r1".to_string())
+            .await
+            .unwrap();
+        assert_eq!(b.refresh_token().await.unwrap(), "a1");
+        assert_eq!(b.refresh_token_value().as_deref(), Some("r1"));
+
+        // While another instance holds the lock, wait for its result.
+        let db = init_db(&url, "/r").await.unwrap();
+        assert!(
+            try_lock_refresh(&db, "other", REFRESH_LOCK_TTL)
+                .await
+                .unwrap()
+        );
+        let waiting = tokio::spawn({
+            let b = b.clone();
+            async move { b.refresh_token().await }
+        });
+        tokio::time::sleep(REFRESH_LOCK_POLL * 2).await;
+        assert!(!waiting.is_finished());
+        save_tokens(&db, "a2", "r2", None).await.unwrap();
+        unlock_refresh(&db, "other").await.unwrap();
+        assert_eq!(waiting.await.unwrap().unwrap(), "a2");
+    }
 }
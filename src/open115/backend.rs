@@ -0,0 +1,170 @@
+//! [`Backend`] implementation for 115.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt};
+
+use super::ResticFileType;
+use super::client::{DOWNLOAD_SIZE_MISMATCHES_TOTAL, FileInfo, Open115Client};
+use crate::backend::{Backend, ObjectInfo, ObjectPages};
+use crate::config::DuplicatePolicy;
+use crate::error::{AppError, Result};
+
+impl From<FileInfo> for ObjectInfo {
+    fn from(file: FileInfo) -> Self {
+        Self {
+            name: file.filename,
+            size: file.size as u64,
+            id: file.pick_code,
+        }
+    }
+}
+
+impl Open115Client {
+    async fn download_object(
+        &self,
+        object: &ObjectInfo,
+        range: Option<(u64, u64)>,
+    ) -> Result<Bytes> {
+        match range {
+            Some(range) => self.download_file(&object.id, Some(range)).await,
+            None => self.download_whole_file(&object.id, object.size).await,
+        }
+    }
+
+    /// Folder holding `name` of `file_type`, without creating it.
+    async fn object_dir_id(&self, file_type: ResticFileType, name: &str) -> Result<Option<String>> {
+        if file_type == ResticFileType::Data {
+            self.find_data_file_dir_id(name).await
+        } else {
+            self.find_type_dir_id(file_type).await
+        }
+    }
+}
+
+#[async_trait]
+impl Backend for Open115Client {
+    fn repo_id(&self) -> &str {
+        Open115Client::repo_id(self)
+    }
+
+    fn repo_path(&self) -> &str {
+        Open115Client::repo_path(self)
+    }
+
+    fn duplicate_policy(&self) -> DuplicatePolicy {
+        Open115Client::duplicate_policy(self)
+    }
+
+    async fn init(&self) -> Result<()> {
+        self.init_repository().await
+    }
+
+    async fn destroy(&self) -> Result<bool> {
+        self.delete_repository().await
+    }
+
+    async fn list(&self, file_type: ResticFileType) -> Result<Vec<ObjectInfo>> {
+        let files = if file_type == ResticFileType::Data {
+            self.list_all_data_files().await?
+        } else {
+            match self.find_type_dir_id(file_type).await? {
+                Some(dir_id) => self.list_files(&dir_id).await?,
+                None => Vec::new(),
+            }
+        };
+        Ok(files
+            .into_iter()
+            .filter(|f| !f.is_dir)
+            .map(ObjectInfo::from)
+            .collect())
+    }
+
+    async fn list_pages(&self, file_type: ResticFileType) -> Result<ObjectPages> {
+        if file_type != ResticFileType::Data {
+            let objects = self.list(file_type).await?;
+            return Ok(futures::stream::once(async { Ok(objects) }).boxed());
+        }
+        Ok(self
+            .stream_data_files()
+            .await?
+            .map_ok(|files| files.into_iter().map(ObjectInfo::from).collect())
+            .boxed())
+    }
+
+    async fn stat(&self, file_type: ResticFileType, name: &str) -> Result<Option<ObjectInfo>> {
+        let Some(dir_id) = self.object_dir_id(file_type, name).await? else {
+            return Ok(None);
+        };
+        // Avoid listing inside data hash subdirs; allow listing fallback for
+        // the other (small) folders, whose search index can lag an upload.
+        Ok(self
+            .get_file_info_with_fallback(&dir_id, name, file_type != ResticFileType::Data)
+            .await?
+            .map(ObjectInfo::from))
+    }
+
+    async fn exists(&self, file_type: ResticFileType, name: &str) -> Result<bool> {
+        Ok(match self.object_dir_id(file_type, name).await? {
+            Some(dir_id) => self.find_file(&dir_id, name).await?.is_some(),
+            None => false,
+        })
+    }
+
+    /// Download `object`; if 115 sends a different length than its cached
+    /// size, re-read its directory entry and try once more with that.
+    async fn get(&self, object: &ObjectInfo, range: Option<(u64, u64)>) -> Result<Bytes> {
+        let err = match self.download_object(object, range).await {
+            Err(e @ AppError::SizeMismatch { .. }) => e,
+            result => return result,
+        };
+        tracing::warn!("{}; refreshing the cached entry of {}", err, object.name);
+        let Some(fresh) = self.refresh_file_entry(&object.id).await? else {
+            metrics::counter!(DOWNLOAD_SIZE_MISMATCHES_TOTAL, "outcome" => "failed").increment(1);
+            return Err(err);
+        };
+        let result = self.download_object(&ObjectInfo::from(fresh), range).await;
+        if let Err(e) = &result {
+            tracing::error!(
+                "Download of {} still failing after a refresh: {}",
+                object.name,
+                e
+            );
+        }
+        let outcome = if result.is_ok() {
+            "refreshed"
+        } else {
+            "failed"
+        };
+        metrics::counter!(DOWNLOAD_SIZE_MISMATCHES_TOTAL, "outcome" => outcome).increment(1);
+        result
+    }
+
+    async fn reported_size(&self, object: &ObjectInfo) -> Result<Option<u64>> {
+        Open115Client::reported_size(self, &object.id).await
+    }
+
+    async fn known_sha1(&self, object: &ObjectInfo) -> Option<String> {
+        Open115Client::known_sha1(self, &object.id).await
+    }
+
+    async fn put(&self, file_type: ResticFileType, name: &str, data: Bytes) -> Result<()> {
+        let dir_id = if file_type == ResticFileType::Data {
+            self.get_data_file_dir_id(name).await?
+        } else {
+            self.get_type_dir_id(file_type).await?
+        };
+        self.upload_file(&dir_id, name, data).await
+    }
+
+    async fn delete(&self, file_type: ResticFileType, name: &str) -> Result<()> {
+        let Some(dir_id) = self.object_dir_id(file_type, name).await? else {
+            return Ok(());
+        };
+        if let Some(file) = self.find_file(&dir_id, name).await? {
+            // delete_file handles the API call and local cache removal.
+            self.delete_file(&dir_id, &file.file_id).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+//! Per-request cap on 115 API calls (`--request-call-budget`).
+//!
+//! A restic request normally costs a handful of API calls, but a damaged
+//! cache can turn one lookup into a long chain of searches, listings and
+//! retries. Requests run inside [`with_call_budget`], and every API call
+//! (retries included) made from the request's task is charged to it; once
+//! the budget is spent the next call fails fast with
+//! [`AppError::CallBudgetExceeded`]. Work done on background tasks, such as
+//! batched deletes and token refreshes, is not charged.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::error::{AppError, Result};
+
+/// Counter of requests that ran out of their API call budget.
+pub const CALL_BUDGET_EXCEEDED_TOTAL: &str = "restic115_call_budget_exceeded_total";
+
+struct CallBudget {
+    limit: u32,
+    used: AtomicU32,
+}
+
+tokio::task_local! {
+    static CALL_BUDGET: Arc<CallBudget>;
+}
+
+/// Run `fut` with at most `limit` 115 API calls.
+pub async fn with_call_budget<F: Future>(limit: u32, fut: F) -> F::Output {
+    let budget = Arc::new(CallBudget {
+        limit,
+        used: AtomicU32::new(0),
+    });
+    CALL_BUDGET.scope(budget, fut).await
+}
+
+/// Charge one API call, `call` (e.g. `GET https://...`), to the enclosing
+/// budget, if any.
+pub(super) fn charge(call: &str) -> Result<()> {
+    let Ok(budget) = CALL_BUDGET.try_with(Arc::clone) else {
+        return Ok(());
+    };
+    let used = budget.used.fetch_add(1, Ordering::Relaxed);
+    if used < budget.limit {
+        return Ok(());
+    }
+    // Only count the first refused call of a request.
+    if used == budget.limit {
+        metrics::counter!(CALL_BUDGET_EXCEEDED_TOTAL).increment(1);
+    }
+    Err(AppError::CallBudgetExceeded {
+        budget: budget.limit,
+        call: call.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_call_budget() {
+        // Outside a budget every call is allowed.
+        assert!(charge("GET /a").is_ok());
+
+        with_call_budget(2, async {
+            assert!(charge("GET /a").is_ok());
+            assert!(charge("GET /b").is_ok());
+            let err = charge("GET /c").unwrap_err();
+            assert!(err.to_string().contains("GET /c"), "{err}");
+            assert!(err.to_string().contains("budget of 2"), "{err}");
+        })
+        .await;
+    }
+}
@@ -1,18 +1,45 @@
 use log::LevelFilter;
+use parking_lot::RwLock;
 use sea_orm::{ConnectOptions, ConnectionTrait, Database, DatabaseConnection, DbErr, Schema};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Duration;
+
+use crate::config::Config;
+
+/// Consecutive "database is locked" probes before giving up on the file.
+const LOCKED_PROBES_BEFORE_DEGRADING: u32 = 3;
+/// Rows copied per INSERT when moving the cache into memory.
+const COPY_BATCH_ROWS: usize = 500;
+/// How long a connection waits for another writer's lock before failing
+/// with "database is locked".
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Attempts of a write that keeps finding the DB locked (see [`retry_busy`]).
+const BUSY_ATTEMPTS: u32 = 4;
+/// Schema version this release migrates a cache DB to (see [`migrate`]).
+pub const SCHEMA_VERSION: i32 = 4;
+/// Newest `tokens` rows kept as rotation history.
+const TOKEN_HISTORY_ROWS: u64 = 500;
 
 pub mod entities {
     pub mod tokens {
         use sea_orm::entity::prelude::*;
 
-        #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+        #[derive(
+            Clone, Debug, PartialEq, Eq, DeriveEntityModel, serde::Serialize, serde::Deserialize,
+        )]
+        /// Token pairs, one row per rotation; the highest `id` is current.
         #[sea_orm(table_name = "tokens")]
         pub struct Model {
             #[sea_orm(primary_key, auto_increment = false)]
             pub id: i32,
             pub access_token: String,
             pub refresh_token: String,
+            /// When the pair was stored (the rotation time).
             pub updated_at: DateTimeUtc,
+            /// Access token expiry, when the refresh reported its lifetime.
+            #[serde(default)]
+            pub expires_at: Option<DateTimeUtc>,
         }
 
         #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -24,9 +51,14 @@ pub mod entities {
     pub mod file_nodes {
         use sea_orm::entity::prelude::*;
 
-        #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+        #[derive(
+            Clone, Debug, PartialEq, Eq, DeriveEntityModel, serde::Serialize, serde::Deserialize,
+        )]
         #[sea_orm(table_name = "file_nodes")]
         pub struct Model {
+            /// Repository namespace the row belongs to (see `Open115Client::repo_id`).
+            #[sea_orm(primary_key, auto_increment = false)]
+            pub repo: String,
             #[sea_orm(primary_key, auto_increment = false)]
             pub file_id: String,
             #[sea_orm(indexed)]
@@ -36,6 +68,150 @@ pub mod entities {
             pub is_dir: bool,
             pub size: i64,
             pub pick_code: String,
+            /// SHA-1 (lowercase hex) of the content, when 115 or an upload
+            /// reported it.
+            pub sha1: Option<String>,
+        }
+
+        #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+        pub enum Relation {}
+
+        impl ActiveModelBehavior for ActiveModel {}
+    }
+
+    pub mod repo_access {
+        use sea_orm::entity::prelude::*;
+
+        /// Last time a server used each repository namespace in `file_nodes`.
+        #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+        #[sea_orm(table_name = "repo_access")]
+        pub struct Model {
+            #[sea_orm(primary_key, auto_increment = false)]
+            pub repo: String,
+            pub last_seen: DateTimeUtc,
+        }
+
+        #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+        pub enum Relation {}
+
+        impl ActiveModelBehavior for ActiveModel {}
+    }
+
+    pub mod delete_journal {
+        use sea_orm::entity::prelude::*;
+
+        /// Objects deleted through the server, kept so `undelete` can bring
+        /// them back from the 115 recycle bin.
+        #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, serde::Serialize)]
+        #[sea_orm(table_name = "delete_journal")]
+        pub struct Model {
+            #[sea_orm(primary_key, auto_increment = false)]
+            pub repo: String,
+            #[sea_orm(primary_key, auto_increment = false)]
+            pub file_id: String,
+            pub parent_id: String,
+            pub name: String,
+            pub size: i64,
+            pub pick_code: String,
+            pub sha1: Option<String>,
+            #[sea_orm(indexed)]
+            pub deleted_at: DateTimeUtc,
+        }
+
+        #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+        pub enum Relation {}
+
+        impl ActiveModelBehavior for ActiveModel {}
+    }
+
+    pub mod import_checkpoints {
+        use sea_orm::entity::prelude::*;
+
+        /// Progress of `import` per object, so an interrupted import resumes
+        /// without re-uploading or re-verifying what already made it.
+        #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+        #[sea_orm(table_name = "import_checkpoints")]
+        pub struct Model {
+            #[sea_orm(primary_key, auto_increment = false)]
+            pub repo: String,
+            /// Restic directory of the object (`data`, `index`, ...).
+            #[sea_orm(primary_key, auto_increment = false)]
+            pub file_type: String,
+            #[sea_orm(primary_key, auto_increment = false)]
+            pub name: String,
+            /// Size of the local object.
+            pub size: i64,
+            /// Size of the copy last seen on 115.
+            pub bytes_confirmed: i64,
+            /// SHA-1 (lowercase hex) of the local object.
+            pub sha1: String,
+            /// Whether 115 reported the same SHA-1 for its copy.
+            pub verified: bool,
+            pub updated_at: DateTimeUtc,
+        }
+
+        #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+        pub enum Relation {}
+
+        impl ActiveModelBehavior for ActiveModel {}
+    }
+
+    pub mod download_urls {
+        use sea_orm::entity::prelude::*;
+
+        /// Resolved download URLs, so a restart does not resolve every
+        /// object again while its URL is still valid.
+        #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+        #[sea_orm(table_name = "download_urls")]
+        pub struct Model {
+            #[sea_orm(primary_key, auto_increment = false)]
+            pub repo: String,
+            #[sea_orm(primary_key, auto_increment = false)]
+            pub pick_code: String,
+            pub url: String,
+            pub sha1: Option<String>,
+            pub size: Option<i64>,
+            #[sea_orm(indexed)]
+            pub expires_at: DateTimeUtc,
+        }
+
+        #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+        pub enum Relation {}
+
+        impl ActiveModelBehavior for ActiveModel {}
+    }
+
+    pub mod refresh_lock {
+        use sea_orm::entity::prelude::*;
+
+        /// The process refreshing the tokens right now, if any; a single
+        /// row with `id` 1, shared by every instance using this DB.
+        #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+        #[sea_orm(table_name = "refresh_lock")]
+        pub struct Model {
+            #[sea_orm(primary_key, auto_increment = false)]
+            pub id: i32,
+            pub holder: String,
+            /// When the lock lapses if the holder never releases it.
+            pub expires_at: DateTimeUtc,
+        }
+
+        #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+        pub enum Relation {}
+
+        impl ActiveModelBehavior for ActiveModel {}
+    }
+
+    pub mod schema_version {
+        use sea_orm::entity::prelude::*;
+
+        /// Schema migrations applied to this cache DB, one row each.
+        #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+        #[sea_orm(table_name = "schema_version")]
+        pub struct Model {
+            #[sea_orm(primary_key, auto_increment = false)]
+            pub version: i32,
+            pub applied_at: DateTimeUtc,
         }
 
         #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -49,10 +225,205 @@ pub mod entities {
 // Database initialization
 // =========================================================================
 
-pub async fn init_db(db_url: &str) -> Result<DatabaseConnection, DbErr> {
-    let mut opt = ConnectOptions::new(db_url);
-    opt.sqlx_logging_level(LevelFilter::Debug);
-    let db = Database::connect(opt).await?;
+/// `sqlite:` URL opening the DB file at `path` with `mode` (`rwc`, `ro`).
+///
+/// sqlx splits the URL at the first `?` and percent-decodes the rest, so
+/// those two characters are escaped; everything else, including Windows
+/// drive letters and backslashes, is passed through as is.
+pub fn sqlite_url(path: &str, mode: &str) -> String {
+    let mut url = String::with_capacity(path.len() + 16);
+    url.push_str("sqlite:");
+    for c in path.chars() {
+        match c {
+            '%' => url.push_str("%25"),
+            '?' => url.push_str("%3F"),
+            c => url.push(c),
+        }
+    }
+    url.push_str("?mode=");
+    url.push_str(mode);
+    url
+}
+
+/// Create the directory the DB file at `path` goes in, which for the default
+/// `DB_PATH` under the per-user data directory may not exist yet.
+pub fn create_db_dir(path: &str) -> std::io::Result<()> {
+    match std::path::Path::new(path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => std::fs::create_dir_all(dir),
+        _ => Ok(()),
+    }
+}
+
+/// Connection settings of the cache DB: pool limits and the SQLCipher key.
+#[derive(Clone)]
+pub struct DbPool {
+    /// SQLx's default when unset.
+    pub max_connections: Option<u32>,
+    pub acquire_timeout: Duration,
+    /// Level of the statement log.
+    pub log_level: LevelFilter,
+    /// SQLCipher passphrase; the file is plain SQLite when unset.
+    pub key: Option<String>,
+}
+
+impl std::fmt::Debug for DbPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DbPool")
+            .field("max_connections", &self.max_connections)
+            .field("acquire_timeout", &self.acquire_timeout)
+            .field("log_level", &self.log_level)
+            .field("key", &self.key.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl Default for DbPool {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            acquire_timeout: Duration::from_secs(30),
+            log_level: LevelFilter::Debug,
+            key: None,
+        }
+    }
+}
+
+impl DbPool {
+    /// Settings from `cfg`, reading the key from `--db-key-file` if given.
+    pub fn from_config(cfg: &Config) -> std::io::Result<Self> {
+        let key = match &cfg.db_key_file {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)?;
+                let key = text.lines().next().unwrap_or_default().trim_end();
+                if key.is_empty() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("{}: no key on the first line", path.display()),
+                    ));
+                }
+                Some(key.to_string())
+            }
+            None => cfg.db_key.clone(),
+        };
+        Ok(Self {
+            max_connections: cfg.db_max_connections,
+            acquire_timeout: Duration::from_secs(cfg.db_acquire_timeout.max(1)),
+            log_level: cfg.db_log_statements,
+            key,
+        })
+    }
+
+    /// Open a pool on `db_url`, unlocking it with the key if there is one.
+    ///
+    /// With a key, fails unless SQLite is SQLCipher and the key decrypts the
+    /// file. A wrong key is reported as such rather than as corruption, so
+    /// the file is never moved aside for it.
+    pub async fn connect(&self, db_url: &str) -> Result<DatabaseConnection, DbErr> {
+        use sea_orm::Statement;
+
+        let mut opt = ConnectOptions::new(db_url);
+        let key = self
+            .key
+            .as_ref()
+            .map(|k| format!("'{}'", k.replace('\'', "''")));
+        opt.sqlx_logging_level(self.log_level)
+            .acquire_timeout(self.acquire_timeout)
+            .map_sqlx_sqlite_opts(move |o| {
+                let o = o.busy_timeout(BUSY_TIMEOUT);
+                match &key {
+                    Some(key) => o.pragma("key", key.clone()),
+                    None => o,
+                }
+            });
+        if let Some(max) = self.max_connections {
+            opt.max_connections(max.max(1));
+        }
+        if self.key.is_none() {
+            check_plain_sqlite(db_url)?;
+            return Database::connect(opt).await;
+        }
+
+        let wrong_key = |e: DbErr| {
+            if is_corrupt(&e) {
+                DbErr::Custom(format!(
+                    "{CANNOT_DECRYPT}: wrong DB_KEY, or the file is not encrypted"
+                ))
+            } else {
+                e
+            }
+        };
+        let db = Database::connect(opt).await.map_err(wrong_key)?;
+        let backend = db.get_database_backend();
+        let cipher = db
+            .query_one(Statement::from_string(backend, "PRAGMA cipher_version;"))
+            .await?;
+        if cipher.is_none() {
+            return Err(DbErr::Custom(format!(
+                "DB_KEY is set, but this build uses plain SQLite; {NEEDS_SQLCIPHER}"
+            )));
+        }
+        db.query_one(Statement::from_string(
+            backend,
+            "SELECT count(*) FROM sqlite_master;",
+        ))
+        .await
+        .map_err(wrong_key)?;
+        Ok(db)
+    }
+}
+
+/// Part of the error for a DB file that does not start with the SQLite header.
+const NOT_PLAIN_SQLITE: &str = "is not a plain SQLite file";
+/// Prefix of the error for a key that does not decrypt the DB.
+const CANNOT_DECRYPT: &str = "cannot decrypt the cache DB";
+/// End of the error for a key given to a build without SQLCipher.
+const NEEDS_SQLCIPHER: &str = "build with --features sqlcipher";
+/// Part of the error for a DB written by a newer release.
+const NEWER_SCHEMA: &str = "is newer than this release supports";
+
+/// Refuse a DB file at `db_url` whose header is not SQLite's, before SQLite
+/// reports it as "not a database": it is most likely encrypted and opened
+/// without its key, and must not be moved aside as corrupt.
+fn check_plain_sqlite(db_url: &str) -> Result<(), DbErr> {
+    use std::io::Read;
+
+    let Some(path) = db_url
+        .strip_prefix("sqlite:")
+        .and_then(|rest| rest.split('?').next())
+        .filter(|path| !path.is_empty() && *path != ":memory:")
+    else {
+        return Ok(());
+    };
+    let path = path.replace("%3F", "?").replace("%25", "%");
+    let mut header = [0u8; 16];
+    let read = match std::fs::File::open(&path) {
+        Ok(mut file) => file.read(&mut header).unwrap_or(0),
+        Err(_) => return Ok(()),
+    };
+    if read == 0 || header[..read] == b"SQLite format 3\0"[..read] {
+        return Ok(());
+    }
+    Err(DbErr::Custom(format!(
+        "cache DB {path} {NOT_PLAIN_SQLITE}; if it is encrypted, set DB_KEY or DB_KEY_FILE"
+    )))
+}
+
+/// Open the cache DB with the default pool settings and create/upgrade the
+/// schema; see [`init_db_with`].
+pub async fn init_db(db_url: &str, repo_id: &str) -> Result<DatabaseConnection, DbErr> {
+    init_db_with(db_url, repo_id, &DbPool::default()).await
+}
+
+/// Open the cache DB and create/upgrade the schema.
+///
+/// `repo_id` is the namespace assigned to `file_nodes` rows written before
+/// rows were scoped by repository.
+pub async fn init_db_with(
+    db_url: &str,
+    repo_id: &str,
+    pool: &DbPool,
+) -> Result<DatabaseConnection, DbErr> {
+    let db = pool.connect(db_url).await?;
 
     // Enable SQLite performance optimizations
     db.execute(sea_orm::Statement::from_string(
@@ -64,11 +435,18 @@ pub async fn init_db(db_url: &str) -> Result<DatabaseConnection, DbErr> {
          PRAGMA mmap_size=30000000000;",
     ))
     .await?;
+    check_integrity(&db).await?;
 
-    // Create tables if they don't exist
-    let builder = db.get_database_backend();
-    let schema = Schema::new(builder);
+    let schema = Schema::new(db.get_database_backend());
+    create_tables(&db, &schema).await?;
+    migrate(&db, &schema, repo_id).await?;
+    create_indexes(&db, &schema).await?;
+    Ok(db)
+}
 
+/// Create the tables of every entity that don't exist yet.
+async fn create_tables(db: &DatabaseConnection, schema: &Schema) -> Result<(), DbErr> {
+    let builder = db.get_database_backend();
     let tables = [
         builder.build(
             schema
@@ -80,16 +458,56 @@ pub async fn init_db(db_url: &str) -> Result<DatabaseConnection, DbErr> {
                 .create_table_from_entity(entities::file_nodes::Entity)
                 .if_not_exists(),
         ),
+        builder.build(
+            schema
+                .create_table_from_entity(entities::repo_access::Entity)
+                .if_not_exists(),
+        ),
+        builder.build(
+            schema
+                .create_table_from_entity(entities::import_checkpoints::Entity)
+                .if_not_exists(),
+        ),
+        builder.build(
+            schema
+                .create_table_from_entity(entities::delete_journal::Entity)
+                .if_not_exists(),
+        ),
+        builder.build(
+            schema
+                .create_table_from_entity(entities::download_urls::Entity)
+                .if_not_exists(),
+        ),
+        builder.build(
+            schema
+                .create_table_from_entity(entities::refresh_lock::Entity)
+                .if_not_exists(),
+        ),
+        builder.build(
+            schema
+                .create_table_from_entity(entities::schema_version::Entity)
+                .if_not_exists(),
+        ),
     ];
 
     for stmt in tables {
         db.execute(stmt).await?;
     }
+    Ok(())
+}
 
-    // Create indexes from entity definitions (#[sea_orm(indexed)] attributes)
+/// Create the indexes from entity definitions (`#[sea_orm(indexed)]`
+/// attributes), after [`migrate`] has brought the tables up to date.
+async fn create_indexes(db: &DatabaseConnection, schema: &Schema) -> Result<(), DbErr> {
     // create_index_from_entity generates CREATE INDEX statements, but doesn't support IF NOT EXISTS,
     // so we ignore "already exists" errors.
-    for index_stmt in schema.create_index_from_entity(entities::file_nodes::Entity) {
+    let builder = db.get_database_backend();
+    let indexes = schema
+        .create_index_from_entity(entities::file_nodes::Entity)
+        .into_iter()
+        .chain(schema.create_index_from_entity(entities::delete_journal::Entity))
+        .chain(schema.create_index_from_entity(entities::download_urls::Entity));
+    for index_stmt in indexes {
         let sql = builder.build(&index_stmt);
         if let Err(e) = db.execute(sql).await {
             // Ignore "index already exists" errors (SQLite error code for this)
@@ -99,6 +517,1542 @@ pub async fn init_db(db_url: &str) -> Result<DatabaseConnection, DbErr> {
             }
         }
     }
+    Ok(())
+}
+
+/// Prefix of the error [`check_integrity`] returns.
+const INTEGRITY_CHECK_FAILED: &str = "integrity check failed";
+
+/// Fail with a corruption error unless `PRAGMA quick_check` passes.
+///
+/// Quick mode skips the index-versus-table cross checks, so it stays fast on
+/// a large cache while still catching damaged pages.
+async fn check_integrity(db: &DatabaseConnection) -> Result<(), DbErr> {
+    let problems: Vec<String> = db
+        .query_all(sea_orm::Statement::from_string(
+            db.get_database_backend(),
+            "PRAGMA quick_check;",
+        ))
+        .await?
+        .into_iter()
+        .map(|row| row.try_get_by_index::<String>(0))
+        .collect::<Result<_, _>>()?;
+    if problems.iter().all(|p| p == "ok") {
+        return Ok(());
+    }
+    Err(DbErr::Custom(format!(
+        "{INTEGRITY_CHECK_FAILED}: {}",
+        problems.join("; ")
+    )))
+}
+
+/// Whether `e` says the DB file is damaged (or not a DB at all), as
+/// opposed to unreachable.
+pub fn is_corrupt(e: &DbErr) -> bool {
+    let msg = e.to_string().to_lowercase();
+    msg.contains(INTEGRITY_CHECK_FAILED)
+        || msg.contains("malformed")
+        || msg.contains("file is not a database")
+}
+
+/// Whether `e` means the DB must not be used without the operator stepping
+/// in: it is encrypted and the key is missing or wrong, or it was written by
+/// a newer release. Such a DB is neither recovered as corrupt nor copied
+/// into memory.
+pub fn is_fatal(e: &DbErr) -> bool {
+    let msg = e.to_string();
+    msg.contains(NOT_PLAIN_SQLITE)
+        || msg.contains(CANNOT_DECRYPT)
+        || msg.contains(NEEDS_SQLCIPHER)
+        || msg.contains(NEWER_SCHEMA)
+}
+
+/// Move the damaged cache DB at `db_path` (and its WAL) aside and start a
+/// new one, keeping what can still be read of the tokens. The cache itself
+/// is rebuilt by the next warm-up.
+pub async fn recover_corrupt(
+    db_path: &str,
+    repo_id: &str,
+    pool: &DbPool,
+) -> Result<DatabaseConnection, DbErr> {
+    let stamp = chrono::Utc::now().format("%Y%m%d%H%M%S");
+    let moved = format!("{db_path}.corrupt-{stamp}");
+    for suffix in ["", "-wal", "-shm"] {
+        let from = format!("{db_path}{suffix}");
+        if std::path::Path::new(&from).exists() {
+            std::fs::rename(&from, format!("{moved}{suffix}"))
+                .map_err(|e| DbErr::Custom(format!("move {from} aside: {e}")))?;
+        }
+    }
+    let db = init_db_with(&sqlite_url(db_path, "rwc"), repo_id, pool).await?;
+    let salvaged = match pool.connect(&sqlite_url(&moved, "ro")).await {
+        Ok(damaged) => copy_tokens(&damaged, &db).await.unwrap_or_else(|e| {
+            tracing::warn!("Could not read the tokens from the damaged cache DB: {}", e);
+            0
+        }),
+        Err(_) => 0,
+    };
+    tracing::error!(
+        "CACHE DB WAS CORRUPT and has been moved to {moved}; started a new one \
+         ({salvaged} token rows salvaged). The cache is rebuilt from 115 on warm-up."
+    );
+    Ok(db)
+}
 
+/// An in-memory DB with the same schema, kept alive for the process lifetime.
+async fn init_memory_db() -> Result<DatabaseConnection, DbErr> {
+    let mut opt = ConnectOptions::new("sqlite::memory:");
+    opt.sqlx_logging_level(LevelFilter::Debug)
+        .max_connections(1)
+        .min_connections(1)
+        // The data lives only as long as a connection, so never recycle it.
+        .max_lifetime(Duration::from_secs(100 * 365 * 24 * 60 * 60));
+    let db = Database::connect(opt).await?;
+    let schema = Schema::new(db.get_database_backend());
+    create_tables(&db, &schema).await?;
+    create_indexes(&db, &schema).await?;
     Ok(db)
 }
+
+/// Why the cache DB file cannot take writes, if that is what `e` means.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Unwritable {
+    ReadOnly,
+    Locked,
+}
+
+fn unwritable(e: &DbErr) -> Option<Unwritable> {
+    let msg = e.to_string().to_lowercase();
+    if msg.contains("readonly") || msg.contains("read-only") || msg.contains("read only") {
+        Some(Unwritable::ReadOnly)
+    } else if msg.contains("database is locked") || msg.contains("database is busy") {
+        Some(Unwritable::Locked)
+    } else {
+        None
+    }
+}
+
+/// Run the write `f`, running it again with a growing pause while SQLite
+/// reports the DB locked or busy, up to [`BUSY_ATTEMPTS`] times in all.
+///
+/// The busy timeout already waits out most lock contention between
+/// concurrent uploads; this covers what it cannot, such as a transaction
+/// whose read lock could not be upgraded, which SQLite fails at once.
+pub async fn retry_busy<T, F, Fut>(what: &str, mut f: F) -> Result<T, DbErr>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, DbErr>>,
+{
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Err(e)
+                if attempt < BUSY_ATTEMPTS
+                    && matches!(unwritable(&e), Some(Unwritable::Locked)) =>
+            {
+                tracing::warn!("Cache DB busy during {} (attempt {}): {}", what, attempt, e);
+                tokio::time::sleep(Duration::from_millis(50 << attempt)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Shared handle to the cache DB.
+///
+/// If the file stops accepting writes (filesystem remounted read-only, or a
+/// lock that never clears), the handle switches every user to an in-memory
+/// copy so requests keep working against 115; the cache is then lost on
+/// restart.
+#[derive(Clone)]
+pub struct CacheDb {
+    conn: Arc<RwLock<DatabaseConnection>>,
+    degraded: Arc<AtomicBool>,
+    locked_probes: Arc<AtomicU32>,
+}
+
+impl CacheDb {
+    pub fn new(conn: DatabaseConnection) -> Self {
+        Self {
+            conn: Arc::new(RwLock::new(conn)),
+            degraded: Arc::new(AtomicBool::new(false)),
+            locked_probes: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    /// Open the cache at `db_path` when it cannot be opened for writing: copy
+    /// whatever can still be read into memory, or start empty.
+    pub async fn open_degraded(
+        db_path: &str,
+        repo_id: &str,
+        pool: &DbPool,
+        reason: &str,
+    ) -> Result<Self, DbErr> {
+        let source = pool.connect(&sqlite_url(db_path, "ro")).await.ok();
+        let this = Self::new(init_memory_db().await?);
+        this.degrade(source.as_ref(), repo_id, reason).await?;
+        Ok(this)
+    }
+
+    /// Connection to use for the next query.
+    pub fn conn(&self) -> DatabaseConnection {
+        self.conn.read().clone()
+    }
+
+    /// Whether the cache now lives in memory only.
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    /// Probe the file with a real write (recording `repo_id` as in use) and
+    /// switch to memory if it is read-only, or still locked after several
+    /// probes.
+    pub async fn check_writable(&self, repo_id: &str) -> Result<(), DbErr> {
+        if self.is_degraded() {
+            return Ok(());
+        }
+        let conn = self.conn();
+        let err = match touch_repo(&conn, repo_id).await {
+            Ok(()) => {
+                self.locked_probes.store(0, Ordering::Relaxed);
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+        match unwritable(&err) {
+            Some(Unwritable::ReadOnly) => {
+                self.degrade(Some(&conn), repo_id, &err.to_string()).await
+            }
+            Some(Unwritable::Locked)
+                if self.locked_probes.fetch_add(1, Ordering::Relaxed) + 1
+                    >= LOCKED_PROBES_BEFORE_DEGRADING =>
+            {
+                self.degrade(Some(&conn), repo_id, &err.to_string()).await
+            }
+            Some(Unwritable::Locked) => {
+                tracing::warn!("Cache DB is locked: {}", err);
+                Ok(())
+            }
+            None => Err(err),
+        }
+    }
+
+    async fn degrade(
+        &self,
+        source: Option<&DatabaseConnection>,
+        repo_id: &str,
+        reason: &str,
+    ) -> Result<(), DbErr> {
+        let memory = if self.is_degraded() {
+            self.conn()
+        } else {
+            init_memory_db().await?
+        };
+        let copied = match source {
+            Some(source) => copy_cache(source, &memory, repo_id)
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::warn!("Could not copy the cache DB into memory: {}", e);
+                    0
+                }),
+            None => 0,
+        };
+        *self.conn.write() = memory;
+        self.degraded.store(true, Ordering::Relaxed);
+        tracing::error!(
+            "CACHE DB IS NOT WRITABLE ({reason}); continuing with an in-memory cache \
+             ({copied} rows copied). Cache updates and refreshed tokens will be LOST on \
+             restart; fix the database file and restart the server."
+        );
+        Ok(())
+    }
+}
+
+/// Copy the tokens and the `repo_id` namespace of `file_nodes` from `src`
+/// into `dst`, returning the number of rows copied.
+async fn copy_cache(
+    src: &DatabaseConnection,
+    dst: &DatabaseConnection,
+    repo_id: &str,
+) -> Result<usize, DbErr> {
+    use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, Set};
+
+    let mut copied = copy_tokens(src, dst).await?;
+    let nodes = entities::file_nodes::Entity::find()
+        .filter(entities::file_nodes::Column::Repo.eq(repo_id))
+        .all(src)
+        .await?;
+    for chunk in nodes.chunks(COPY_BATCH_ROWS) {
+        entities::file_nodes::Entity::insert_many(chunk.iter().map(|n| {
+            entities::file_nodes::ActiveModel {
+                repo: Set(n.repo.clone()),
+                file_id: Set(n.file_id.clone()),
+                parent_id: Set(n.parent_id.clone()),
+                name: Set(n.name.clone()),
+                is_dir: Set(n.is_dir),
+                size: Set(n.size),
+                pick_code: Set(n.pick_code.clone()),
+                sha1: Set(n.sha1.clone()),
+            }
+        }))
+        .exec(dst)
+        .await?;
+        copied += chunk.len();
+    }
+    Ok(copied)
+}
+
+/// One row of a portable cache snapshot (`cache export`).
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheRow {
+    Tokens(entities::tokens::Model),
+    FileNodes(entities::file_nodes::Model),
+}
+
+/// Pass every `tokens` and `file_nodes` row to `write`, reading the nodes a
+/// page at a time; returns the number of rows.
+pub async fn export_cache<E: From<DbErr>>(
+    db: &DatabaseConnection,
+    mut write: impl FnMut(&CacheRow) -> Result<(), E>,
+) -> Result<usize, E> {
+    use sea_orm::{EntityTrait, PaginatorTrait, QueryOrder};
+
+    let mut exported = 0;
+    for t in entities::tokens::Entity::find().all(db).await? {
+        write(&CacheRow::Tokens(t))?;
+        exported += 1;
+    }
+    let mut pages = entities::file_nodes::Entity::find()
+        .order_by_asc(entities::file_nodes::Column::Repo)
+        .order_by_asc(entities::file_nodes::Column::FileId)
+        .paginate(db, COPY_BATCH_ROWS as u64);
+    while let Some(page) = pages.fetch_and_next().await? {
+        for n in page {
+            write(&CacheRow::FileNodes(n))?;
+            exported += 1;
+        }
+    }
+    Ok(exported)
+}
+
+/// Load exported rows in one transaction. The `file_nodes` rows of each
+/// repository namespace in `rows` replace the ones cached before; other
+/// namespaces are kept. Tokens in `rows` replace the whole token history,
+/// so the newest imported pair becomes the current one.
+pub async fn import_cache<E: From<DbErr>>(
+    db: &DatabaseConnection,
+    rows: impl Iterator<Item = Result<CacheRow, E>>,
+) -> Result<usize, E> {
+    use sea_orm::{ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter, TransactionTrait};
+
+    let txn = db.begin().await?;
+    let mut cleared = std::collections::HashSet::new();
+    let mut tokens_cleared = false;
+    let mut nodes = Vec::new();
+    let mut imported = 0;
+    for row in rows {
+        match row? {
+            CacheRow::Tokens(t) => {
+                use entities::tokens::Entity;
+                if !tokens_cleared {
+                    Entity::delete_many().exec(&txn).await?;
+                    tokens_cleared = true;
+                }
+                Entity::insert(t.into_active_model()).exec(&txn).await?;
+            }
+            CacheRow::FileNodes(n) => {
+                if cleared.insert(n.repo.clone()) {
+                    entities::file_nodes::Entity::delete_many()
+                        .filter(entities::file_nodes::Column::Repo.eq(&n.repo))
+                        .exec(&txn)
+                        .await?;
+                }
+                nodes.push(n.into_active_model());
+                if nodes.len() == COPY_BATCH_ROWS {
+                    entities::file_nodes::Entity::insert_many(std::mem::take(&mut nodes))
+                        .exec(&txn)
+                        .await?;
+                }
+            }
+        }
+        imported += 1;
+    }
+    if !nodes.is_empty() {
+        entities::file_nodes::Entity::insert_many(nodes)
+            .exec(&txn)
+            .await?;
+    }
+    txn.commit().await?;
+    Ok(imported)
+}
+
+/// Copy the `tokens` rows from `src` into `dst`, returning how many.
+async fn copy_tokens(src: &DatabaseConnection, dst: &DatabaseConnection) -> Result<usize, DbErr> {
+    use sea_orm::{EntityTrait, Set};
+
+    let mut copied = 0;
+    for t in entities::tokens::Entity::find().all(src).await? {
+        entities::tokens::Entity::insert(entities::tokens::ActiveModel {
+            id: Set(t.id),
+            access_token: Set(t.access_token),
+            refresh_token: Set(t.refresh_token),
+            updated_at: Set(t.updated_at),
+            expires_at: Set(t.expires_at),
+        })
+        .exec(dst)
+        .await?;
+        copied += 1;
+    }
+    Ok(copied)
+}
+
+/// Bring the cache DB up to [`SCHEMA_VERSION`], running each migration it
+/// has not recorded in `schema_version` yet.
+///
+/// DBs from before the table existed record nothing, so every migration
+/// checks the layout it changes and is a no-op where that is already
+/// current. A DB from a newer release is refused rather than guessed at.
+async fn migrate(db: &DatabaseConnection, schema: &Schema, repo_id: &str) -> Result<(), DbErr> {
+    use sea_orm::{EntityTrait, QueryOrder, Set};
+
+    let current = entities::schema_version::Entity::find()
+        .order_by_desc(entities::schema_version::Column::Version)
+        .one(db)
+        .await?
+        .map_or(0, |row| row.version);
+    if current > SCHEMA_VERSION {
+        return Err(DbErr::Custom(format!(
+            "cache DB schema version {current} {NEWER_SCHEMA} ({SCHEMA_VERSION}); \
+             upgrade restic-115 or point DB_PATH elsewhere"
+        )));
+    }
+    for version in current + 1..=SCHEMA_VERSION {
+        match version {
+            1 => scope_legacy_file_nodes(db, schema, repo_id).await?,
+            2 => add_file_nodes_sha1(db).await?,
+            3 => convert_legacy_cache_tables(db, repo_id).await?,
+            4 => add_tokens_expires_at(db).await?,
+            _ => unreachable!("no migration to schema version {version}"),
+        }
+        entities::schema_version::Entity::insert(entities::schema_version::ActiveModel {
+            version: Set(version),
+            applied_at: Set(chrono::Utc::now()),
+        })
+        .exec(db)
+        .await?;
+    }
+    if current < SCHEMA_VERSION {
+        tracing::debug!("Cache DB schema migrated from version {current} to {SCHEMA_VERSION}");
+    }
+    Ok(())
+}
+
+/// Column names of `table`, empty when it does not exist.
+async fn table_columns(db: &DatabaseConnection, table: &str) -> Result<Vec<String>, DbErr> {
+    use sea_orm::Statement;
+
+    db.query_all(Statement::from_string(
+        db.get_database_backend(),
+        format!("PRAGMA table_info({table});"),
+    ))
+    .await?
+    .into_iter()
+    .map(|row| row.try_get::<String>("", "name"))
+    .collect()
+}
+
+/// Move rows of the `cached_dirs` / `cached_files` tables early releases
+/// kept next to `file_nodes` into it, under `repo_id`, and drop them.
+///
+/// Rows are taken from whatever of `file_id`, `parent_id`, `name`, `size`
+/// and `pick_code` the table has; a table without the first three cannot be
+/// converted and is dropped anyway, as nothing reads it and the cache warm-up
+/// lists those folders again.
+async fn convert_legacy_cache_tables(db: &DatabaseConnection, repo_id: &str) -> Result<(), DbErr> {
+    use sea_orm::{Statement, TransactionTrait};
+
+    let backend = db.get_database_backend();
+    for (table, is_dir) in [("cached_dirs", true), ("cached_files", false)] {
+        let columns = table_columns(db, table).await?;
+        if columns.is_empty() {
+            continue;
+        }
+        let has = |c: &str| columns.iter().any(|name| name == c);
+        let txn = db.begin().await?;
+        if has("file_id") && has("parent_id") && has("name") {
+            let size = if has("size") { "size" } else { "0" };
+            let pick_code = if has("pick_code") { "pick_code" } else { "''" };
+            let moved = txn
+                .execute(Statement::from_sql_and_values(
+                    backend,
+                    format!(
+                        "INSERT OR IGNORE INTO file_nodes (repo, file_id, parent_id, name, is_dir, size, pick_code)
+                         SELECT ?, file_id, parent_id, name, ?, {size}, {pick_code} FROM {table};"
+                    ),
+                    [repo_id.into(), is_dir.into()],
+                ))
+                .await?
+                .rows_affected();
+            tracing::info!("Migrated {moved} rows of legacy {table} into file_nodes for {repo_id}");
+        } else {
+            tracing::warn!(
+                "Dropping legacy {table}: columns {columns:?} do not map onto file_nodes; \
+                 the cache warm-up lists those folders again"
+            );
+        }
+        txn.execute(Statement::from_string(
+            backend,
+            format!("DROP TABLE {table};"),
+        ))
+        .await?;
+        txn.commit().await?;
+    }
+    Ok(())
+}
+
+/// Rebuild a pre-namespacing `file_nodes` table (primary key `file_id` only)
+/// into the repo-scoped layout, assigning existing rows to `repo_id` so the
+/// warmed cache survives the upgrade.
+async fn scope_legacy_file_nodes(
+    db: &DatabaseConnection,
+    schema: &Schema,
+    repo_id: &str,
+) -> Result<(), DbErr> {
+    use sea_orm::{Statement, TransactionTrait};
+
+    let backend = db.get_database_backend();
+    let columns = table_columns(db, "file_nodes").await?;
+    if columns.iter().any(|c| c == "repo") {
+        return Ok(());
+    }
+
+    tracing::info!("Migrating file_nodes cache rows into repository namespace {repo_id}");
+    let txn = db.begin().await?;
+    txn.execute(Statement::from_string(
+        backend,
+        "ALTER TABLE file_nodes RENAME TO file_nodes_unscoped;",
+    ))
+    .await?;
+    txn.execute(backend.build(&schema.create_table_from_entity(entities::file_nodes::Entity)))
+        .await?;
+    txn.execute(Statement::from_sql_and_values(
+        backend,
+        "INSERT INTO file_nodes (repo, file_id, parent_id, name, is_dir, size, pick_code)
+         SELECT ?, file_id, parent_id, name, is_dir, size, pick_code FROM file_nodes_unscoped;",
+        [repo_id.into()],
+    ))
+    .await?;
+    txn.execute(Statement::from_string(
+        backend,
+        "DROP TABLE file_nodes_unscoped;",
+    ))
+    .await?;
+    txn.commit().await
+}
+
+/// Add the `sha1` column to a `file_nodes` table created before it existed.
+async fn add_file_nodes_sha1(db: &DatabaseConnection) -> Result<(), DbErr> {
+    use sea_orm::Statement;
+
+    let has_sha1 = table_columns(db, "file_nodes")
+        .await?
+        .iter()
+        .any(|c| c == "sha1");
+    if !has_sha1 {
+        db.execute(Statement::from_string(
+            db.get_database_backend(),
+            "ALTER TABLE file_nodes ADD COLUMN sha1 TEXT;",
+        ))
+        .await?;
+    }
+    Ok(())
+}
+
+/// Add the `expires_at` column to a `tokens` table created before token
+/// history was kept.
+async fn add_tokens_expires_at(db: &DatabaseConnection) -> Result<(), DbErr> {
+    use sea_orm::Statement;
+
+    if !table_columns(db, "tokens")
+        .await?
+        .iter()
+        .any(|c| c == "expires_at")
+    {
+        db.execute(Statement::from_string(
+            db.get_database_backend(),
+            "ALTER TABLE tokens ADD COLUMN expires_at TEXT;",
+        ))
+        .await?;
+    }
+    Ok(())
+}
+
+/// The current token pair: the most recently stored one.
+pub async fn current_tokens(
+    db: &DatabaseConnection,
+) -> Result<Option<entities::tokens::Model>, DbErr> {
+    use sea_orm::{EntityTrait, QueryOrder};
+
+    entities::tokens::Entity::find()
+        .order_by_desc(entities::tokens::Column::Id)
+        .one(db)
+        .await
+}
+
+/// Store a token pair as the current one, keeping the pairs before it as
+/// history (the newest [`TOKEN_HISTORY_ROWS`]).
+pub async fn save_tokens(
+    db: &DatabaseConnection,
+    access_token: &str,
+    refresh_token: &str,
+    expires_at: Option<chrono::DateTime<chrono::Utc>>,
+) -> Result<(), DbErr> {
+    use entities::tokens::{ActiveModel, Column, Entity};
+    use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, Set};
+
+    let id = current_tokens(db).await?.map_or(1, |t| t.id + 1);
+    Entity::insert(ActiveModel {
+        id: Set(id),
+        access_token: Set(access_token.to_string()),
+        refresh_token: Set(refresh_token.to_string()),
+        updated_at: Set(chrono::Utc::now()),
+        expires_at: Set(expires_at),
+    })
+    .exec(db)
+    .await?;
+    Entity::delete_many()
+        .filter(Column::Id.lte(i64::from(id) - TOKEN_HISTORY_ROWS as i64))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Stored token pairs, newest first, at most `limit`.
+pub async fn token_history(
+    db: &DatabaseConnection,
+    limit: u64,
+) -> Result<Vec<entities::tokens::Model>, DbErr> {
+    use sea_orm::{EntityTrait, QueryOrder, QuerySelect};
+
+    entities::tokens::Entity::find()
+        .order_by_desc(entities::tokens::Column::Id)
+        .limit(limit)
+        .all(db)
+        .await
+}
+
+/// Take the token refresh lock for `holder` for `ttl`, unless another
+/// holder has it and it has not lapsed. Returns whether `holder` has it now.
+///
+/// A single conditional upsert, so two processes sharing the DB file cannot
+/// both win.
+pub async fn try_lock_refresh(
+    db: &DatabaseConnection,
+    holder: &str,
+    ttl: Duration,
+) -> Result<bool, DbErr> {
+    use sea_orm::Statement;
+
+    let now = chrono::Utc::now();
+    let expires_at = now + chrono::Duration::from_std(ttl).unwrap_or(chrono::Duration::MAX);
+    let result = db
+        .execute(Statement::from_sql_and_values(
+            db.get_database_backend(),
+            "INSERT INTO refresh_lock (id, holder, expires_at) VALUES (1, ?, ?)
+             ON CONFLICT (id) DO UPDATE SET holder = excluded.holder, expires_at = excluded.expires_at
+             WHERE refresh_lock.holder = excluded.holder OR refresh_lock.expires_at < ?;",
+            [holder.into(), expires_at.into(), now.into()],
+        ))
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Release the token refresh lock if `holder` has it.
+pub async fn unlock_refresh(db: &DatabaseConnection, holder: &str) -> Result<(), DbErr> {
+    use entities::refresh_lock::{Column, Entity};
+    use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+    Entity::delete_many()
+        .filter(Column::Holder.eq(holder))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Record that `repo_id` is in use now.
+pub async fn touch_repo(db: &DatabaseConnection, repo_id: &str) -> Result<(), DbErr> {
+    use sea_orm::{EntityTrait, Set, sea_query::OnConflict};
+
+    let am = entities::repo_access::ActiveModel {
+        repo: Set(repo_id.to_string()),
+        last_seen: Set(chrono::Utc::now()),
+    };
+    entities::repo_access::Entity::insert(am)
+        .on_conflict(
+            OnConflict::column(entities::repo_access::Column::Repo)
+                .update_column(entities::repo_access::Column::LastSeen)
+                .to_owned(),
+        )
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Delete the `file_nodes` rows of every repository namespace not used since
+/// `cutoff`, except `keep`, and return the evicted namespaces.
+///
+/// Namespaces that have rows but were never recorded in `repo_access` (e.g.
+/// written before it existed) start their clock now.
+pub async fn evict_stale_repos(
+    db: &DatabaseConnection,
+    keep: &str,
+    cutoff: chrono::DateTime<chrono::Utc>,
+) -> Result<Vec<String>, DbErr> {
+    use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, Set, Statement, sea_query::OnConflict};
+
+    let backend = db.get_database_backend();
+    let known = db
+        .query_all(Statement::from_string(
+            backend,
+            "SELECT DISTINCT repo FROM file_nodes;",
+        ))
+        .await?
+        .into_iter()
+        .map(|row| row.try_get::<String>("", "repo"))
+        .collect::<Result<Vec<_>, _>>()?;
+    let now = chrono::Utc::now();
+    for repo in known {
+        let am = entities::repo_access::ActiveModel {
+            repo: Set(repo),
+            last_seen: Set(now),
+        };
+        entities::repo_access::Entity::insert(am)
+            .on_conflict(
+                OnConflict::column(entities::repo_access::Column::Repo)
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .do_nothing()
+            .exec(db)
+            .await?;
+    }
+
+    let stale: Vec<String> = entities::repo_access::Entity::find()
+        .filter(entities::repo_access::Column::LastSeen.lt(cutoff))
+        .filter(entities::repo_access::Column::Repo.ne(keep))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|r| r.repo)
+        .collect();
+    for repo in &stale {
+        let removed = entities::file_nodes::Entity::delete_many()
+            .filter(entities::file_nodes::Column::Repo.eq(repo.as_str()))
+            .exec(db)
+            .await?;
+        entities::repo_access::Entity::delete_by_id(repo.clone())
+            .exec(db)
+            .await?;
+        tracing::info!(
+            "Evicted {} cached rows of unused repository namespace {}",
+            removed.rows_affected,
+            repo
+        );
+    }
+    if !stale.is_empty() {
+        // Give the freed pages back to the filesystem.
+        db.execute(Statement::from_string(backend, "VACUUM;"))
+            .await?;
+    }
+    Ok(stale)
+}
+
+/// Sizes of the cache DB before and after [`maintain`], in pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct MaintenanceReport {
+    pub page_size: i64,
+    pub pages_before: i64,
+    pub free_pages_before: i64,
+    pub pages_after: i64,
+    /// WAL frames not yet copied into the DB before the first checkpoint.
+    pub wal_frames: i64,
+}
+
+/// Checkpoint and truncate the WAL, refresh the query planner's statistics
+/// and rewrite the file without its free pages.
+///
+/// `VACUUM` rewrites the whole file and blocks writers while it runs; it is
+/// skipped when there are no free pages to give back.
+pub async fn maintain(db: &DatabaseConnection) -> Result<MaintenanceReport, DbErr> {
+    use sea_orm::Statement;
+
+    let backend = db.get_database_backend();
+    let pragma = |sql: &'static str, column: &'static str| async move {
+        let row = db
+            .query_one(Statement::from_string(backend, sql))
+            .await?
+            .ok_or_else(|| DbErr::Custom(format!("{sql} returned no row")))?;
+        row.try_get::<i64>("", column)
+    };
+    let page_size = pragma("PRAGMA page_size;", "page_size").await?;
+    let pages_before = pragma("PRAGMA page_count;", "page_count").await?;
+    let free_pages_before = pragma("PRAGMA freelist_count;", "freelist_count").await?;
+    // `log` is -1 when the DB is not in WAL mode (e.g. in memory).
+    let wal_frames = pragma("PRAGMA wal_checkpoint(TRUNCATE);", "log")
+        .await?
+        .max(0);
+    db.execute(Statement::from_string(backend, "ANALYZE;"))
+        .await?;
+    if free_pages_before > 0 {
+        db.execute(Statement::from_string(backend, "VACUUM;"))
+            .await?;
+        pragma("PRAGMA wal_checkpoint(TRUNCATE);", "log").await?;
+    }
+    Ok(MaintenanceReport {
+        page_size,
+        pages_before,
+        free_pages_before,
+        pages_after: pragma("PRAGMA page_count;", "page_count").await?,
+        wal_frames,
+    })
+}
+
+/// The `import` checkpoint of one object, if any.
+pub async fn import_checkpoint(
+    db: &DatabaseConnection,
+    repo_id: &str,
+    file_type: &str,
+    name: &str,
+) -> Result<Option<entities::import_checkpoints::Model>, DbErr> {
+    use sea_orm::EntityTrait;
+
+    entities::import_checkpoints::Entity::find_by_id((
+        repo_id.to_string(),
+        file_type.to_string(),
+        name.to_string(),
+    ))
+    .one(db)
+    .await
+}
+
+/// Insert or replace an `import` checkpoint.
+pub async fn save_import_checkpoint(
+    db: &DatabaseConnection,
+    checkpoint: entities::import_checkpoints::Model,
+) -> Result<(), DbErr> {
+    use entities::import_checkpoints::{ActiveModel, Column, Entity};
+    use sea_orm::{EntityTrait, IntoActiveModel, sea_query::OnConflict};
+
+    let am: ActiveModel = checkpoint.into_active_model();
+    Entity::insert(am)
+        .on_conflict(
+            OnConflict::columns([Column::Repo, Column::FileType, Column::Name])
+                .update_columns([
+                    Column::Size,
+                    Column::BytesConfirmed,
+                    Column::Sha1,
+                    Column::Verified,
+                    Column::UpdatedAt,
+                ])
+                .to_owned(),
+        )
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Record objects about to be deleted in the delete journal, replacing any
+/// earlier entry for the same file, and drop entries older than `cutoff`.
+pub async fn journal_deletes(
+    db: &DatabaseConnection,
+    entries: Vec<entities::delete_journal::Model>,
+    cutoff: chrono::DateTime<chrono::Utc>,
+) -> Result<(), DbErr> {
+    use entities::delete_journal::{ActiveModel, Column, Entity};
+    use sea_orm::{ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter, sea_query::OnConflict};
+
+    Entity::delete_many()
+        .filter(Column::DeletedAt.lt(cutoff))
+        .exec(db)
+        .await?;
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let models: Vec<ActiveModel> = entries
+        .into_iter()
+        .map(IntoActiveModel::into_active_model)
+        .collect();
+    Entity::insert_many(models)
+        .on_conflict(
+            OnConflict::columns([Column::Repo, Column::FileId])
+                .update_columns([
+                    Column::ParentId,
+                    Column::Name,
+                    Column::Size,
+                    Column::PickCode,
+                    Column::Sha1,
+                    Column::DeletedAt,
+                ])
+                .to_owned(),
+        )
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Journal entries of `repo_id` deleted at or after `since`, newest first.
+pub async fn deleted_objects(
+    db: &DatabaseConnection,
+    repo_id: &str,
+    since: chrono::DateTime<chrono::Utc>,
+) -> Result<Vec<entities::delete_journal::Model>, DbErr> {
+    use entities::delete_journal::{Column, Entity};
+    use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder};
+
+    Entity::find()
+        .filter(Column::Repo.eq(repo_id))
+        .filter(Column::DeletedAt.gte(since))
+        .order_by_desc(Column::DeletedAt)
+        .all(db)
+        .await
+}
+
+/// Remove journal entries once their objects have been restored.
+pub async fn forget_deleted(
+    db: &DatabaseConnection,
+    repo_id: &str,
+    file_ids: &[String],
+) -> Result<(), DbErr> {
+    use entities::delete_journal::{Column, Entity};
+    use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+    Entity::delete_many()
+        .filter(Column::Repo.eq(repo_id))
+        .filter(Column::FileId.is_in(file_ids.iter().cloned()))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Up to `limit` files of `repo_id` exactly `depth` folder levels below
+/// `data_id` with file ids after `after`, in file id order.
+///
+/// One query joins each row to its ancestor folders. `CROSS JOIN` keeps
+/// SQLite walking `file_nodes` in primary key order from `after`, so a page
+/// stops after `limit` matches instead of sorting every file of the
+/// repository.
+pub async fn data_files_page(
+    db: &DatabaseConnection,
+    repo_id: &str,
+    data_id: &str,
+    depth: usize,
+    after: &str,
+    limit: u64,
+) -> Result<Vec<entities::file_nodes::Model>, DbErr> {
+    use sea_orm::{EntityTrait, Statement};
+
+    let mut sql = String::from("SELECT f.* FROM file_nodes f");
+    let mut child = "f".to_string();
+    for level in 1..=depth {
+        sql.push_str(&format!(
+            " CROSS JOIN file_nodes s{level} ON s{level}.repo = f.repo \
+             AND s{level}.file_id = {child}.parent_id AND s{level}.is_dir = 1"
+        ));
+        child = format!("s{level}");
+    }
+    sql.push_str(&format!(
+        " WHERE f.repo = ? AND f.is_dir = 0 AND f.file_id > ? AND {child}.parent_id = ? \
+         ORDER BY f.file_id LIMIT ?"
+    ));
+    let backend = db.get_database_backend();
+    entities::file_nodes::Entity::find()
+        .from_raw_sql(Statement::from_sql_and_values(
+            backend,
+            sql,
+            [
+                repo_id.into(),
+                after.into(),
+                data_id.into(),
+                (limit as i64).into(),
+            ],
+        ))
+        .all(db)
+        .await
+}
+
+/// Insert or replace a resolved download URL.
+pub async fn save_download_url(
+    db: &DatabaseConnection,
+    entry: entities::download_urls::Model,
+) -> Result<(), DbErr> {
+    use entities::download_urls::{ActiveModel, Column, Entity};
+    use sea_orm::{EntityTrait, IntoActiveModel, sea_query::OnConflict};
+
+    let am: ActiveModel = entry.into_active_model();
+    Entity::insert(am)
+        .on_conflict(
+            OnConflict::columns([Column::Repo, Column::PickCode])
+                .update_columns([Column::Url, Column::Sha1, Column::Size, Column::ExpiresAt])
+                .to_owned(),
+        )
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Drop download URLs expired by `now`, then return those of `repo_id`.
+pub async fn live_download_urls(
+    db: &DatabaseConnection,
+    repo_id: &str,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<Vec<entities::download_urls::Model>, DbErr> {
+    use entities::download_urls::{Column, Entity};
+    use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+    Entity::delete_many()
+        .filter(Column::ExpiresAt.lte(now))
+        .exec(db)
+        .await?;
+    Entity::find()
+        .filter(Column::Repo.eq(repo_id))
+        .all(db)
+        .await
+}
+
+/// Forget the download URL of `pick_code` in `repo_id`.
+pub async fn forget_download_url(
+    db: &DatabaseConnection,
+    repo_id: &str,
+    pick_code: &str,
+) -> Result<(), DbErr> {
+    use entities::download_urls::{Column, Entity};
+    use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+    Entity::delete_many()
+        .filter(Column::Repo.eq(repo_id))
+        .filter(Column::PickCode.eq(pick_code))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Forget the download URLs of `repo_id`.
+pub async fn forget_download_urls(db: &DatabaseConnection, repo_id: &str) -> Result<(), DbErr> {
+    use entities::download_urls::{Column, Entity};
+    use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+    Entity::delete_many()
+        .filter(Column::Repo.eq(repo_id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sea_orm::{EntityTrait, Statement};
+
+    #[test]
+    fn test_sqlite_url() {
+        assert_eq!(
+            sqlite_url("cache-115.db", "rwc"),
+            "sqlite:cache-115.db?mode=rwc"
+        );
+        assert_eq!(
+            sqlite_url(r"C:\ProgramData\restic-115\cache.db", "ro"),
+            r"sqlite:C:\ProgramData\restic-115\cache.db?mode=ro"
+        );
+        assert_eq!(sqlite_url("a?b%c.db", "ro"), "sqlite:a%3Fb%25c.db?mode=ro");
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_db_is_moved_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("c.db");
+        let path_str = path.to_string_lossy().into_owned();
+        let url = sqlite_url(&path_str, "rwc");
+        let damage = |range: std::ops::Range<usize>| {
+            let mut bytes = std::fs::read(&path).unwrap();
+            bytes[range].fill(0x5a);
+            std::fs::write(&path, bytes).unwrap();
+        };
+
+        // A damaged table page fails the quick check.
+        let db = init_db(&url, "/r").await.unwrap();
+        db.execute(Statement::from_string(
+            sea_orm::DatabaseBackend::Sqlite,
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 2000)
+             INSERT INTO file_nodes (repo, file_id, parent_id, name, is_dir, size, pick_code)
+             SELECT '/r', i, '0', printf('%064d', i), 0, 1, 'p' || i FROM n;
+             PRAGMA wal_checkpoint(TRUNCATE);",
+        ))
+        .await
+        .unwrap();
+        db.close().await.unwrap();
+        let len = std::fs::metadata(&path).unwrap().len() as usize;
+        damage(len / 2..len / 2 + 4096);
+        let err = init_db(&url, "/r").await.unwrap_err();
+        assert!(is_corrupt(&err), "{err}");
+
+        // A file without the SQLite header may be encrypted: it is refused,
+        // not recovered.
+        damage(0..100);
+        let err = init_db(&url, "/r").await.unwrap_err();
+        assert!(!is_corrupt(&err) && is_fatal(&err), "{err}");
+        assert!(err.to_string().contains("DB_KEY"), "{err}");
+        assert!(!is_corrupt(&DbErr::Custom(
+            "database is locked".to_string()
+        )));
+
+        let db = recover_corrupt(&path_str, "/r", &DbPool::default())
+            .await
+            .unwrap();
+        let tokens = entities::tokens::Entity::find().all(&db).await.unwrap();
+        assert!(tokens.is_empty());
+        let moved: Vec<String> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with("c.db.corrupt-") && !name.ends_with("-wal"))
+            .filter(|name| !name.ends_with("-shm"))
+            .collect();
+        assert_eq!(moved.len(), 1, "{moved:?}");
+    }
+
+    #[tokio::test]
+    async fn test_retry_busy() {
+        let calls = AtomicU32::new(0);
+        let flaky = |fail_first: u32, err: &'static str| {
+            calls.store(0, Ordering::Relaxed);
+            let calls = &calls;
+            move || async move {
+                if calls.fetch_add(1, Ordering::Relaxed) < fail_first {
+                    Err(DbErr::Custom(err.to_string()))
+                } else {
+                    Ok(())
+                }
+            }
+        };
+        retry_busy("test", flaky(2, "database is locked"))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+        assert!(
+            retry_busy("test", flaky(9, "database is busy"))
+                .await
+                .is_err()
+        );
+        assert_eq!(calls.load(Ordering::Relaxed), BUSY_ATTEMPTS);
+        assert!(
+            retry_busy("test", flaky(9, "disk I/O error"))
+                .await
+                .is_err()
+        );
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_maintain() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("c.db");
+        let db = init_db(&sqlite_url(&path.to_string_lossy(), "rwc"), "/r")
+            .await
+            .unwrap();
+        db.execute(Statement::from_string(
+            sea_orm::DatabaseBackend::Sqlite,
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 5000)
+             INSERT INTO file_nodes (repo, file_id, parent_id, name, is_dir, size, pick_code)
+             SELECT '/old', i, '0', printf('%064d', i), 0, 1, 'p' || i FROM n;
+             DELETE FROM file_nodes WHERE repo = '/old';",
+        ))
+        .await
+        .unwrap();
+
+        let report = maintain(&db).await.unwrap();
+        assert!(report.free_pages_before > 0);
+        assert!(report.pages_after < report.pages_before);
+        let again = maintain(&db).await.unwrap();
+        assert_eq!((again.free_pages_before, again.wal_frames), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_legacy_file_nodes_are_scoped() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_url = sqlite_url(&dir.path().join("c.db").to_string_lossy(), "rwc");
+
+        {
+            let db = Database::connect(&db_url).await.unwrap();
+            db.execute(Statement::from_string(
+                sea_orm::DatabaseBackend::Sqlite,
+                "CREATE TABLE file_nodes (file_id TEXT PRIMARY KEY NOT NULL, parent_id TEXT NOT NULL,
+                 name TEXT NOT NULL, is_dir BOOLEAN NOT NULL, size BIGINT NOT NULL, pick_code TEXT NOT NULL);
+                 INSERT INTO file_nodes VALUES ('1', '0', 'restic-backup', 1, 0, '');",
+            ))
+            .await
+            .unwrap();
+        }
+
+        let db = init_db(&db_url, "/restic-backup").await.unwrap();
+        let rows = entities::file_nodes::Entity::find().all(&db).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].repo, "/restic-backup");
+        assert_eq!(rows[0].name, "restic-backup");
+
+        // Re-opening an already scoped DB is a no-op.
+        drop(db);
+        let db = init_db(&db_url, "/other").await.unwrap();
+        let rows = entities::file_nodes::Entity::find().all(&db).await.unwrap();
+        assert_eq!(rows[0].repo, "/restic-backup");
+    }
+
+    #[tokio::test]
+    async fn test_db_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("c.db");
+        let url = sqlite_url(&path.to_string_lossy(), "rwc");
+        let keyed = |key: &str| DbPool {
+            key: Some(key.to_string()),
+            ..DbPool::default()
+        };
+
+        if !cfg!(feature = "sqlcipher") {
+            let err = init_db_with(&url, "/r", &keyed("k")).await.unwrap_err();
+            assert!(is_fatal(&err), "{err}");
+            assert!(err.to_string().contains("--features sqlcipher"), "{err}");
+            return;
+        }
+
+        let db = init_db_with(&url, "/r", &keyed("it's secret"))
+            .await
+            .unwrap();
+        touch_repo(&db, "/r").await.unwrap();
+        db.close().await.unwrap();
+        let header = std::fs::read(&path).unwrap();
+        assert!(!header.starts_with(b"SQLite format 3"));
+
+        let db = init_db_with(&url, "/r", &keyed("it's secret"))
+            .await
+            .unwrap();
+        let repos = entities::repo_access::Entity::find()
+            .all(&db)
+            .await
+            .unwrap();
+        assert_eq!(repos.len(), 1);
+        db.close().await.unwrap();
+
+        // A wrong key, or none, is not mistaken for corruption.
+        let err = init_db_with(&url, "/r", &keyed("wrong")).await.unwrap_err();
+        assert!(
+            !is_corrupt(&err) && is_fatal(&err) && err.to_string().contains("wrong DB_KEY"),
+            "{err}"
+        );
+        let err = init_db(&url, "/r").await.unwrap_err();
+        assert!(!is_corrupt(&err) && is_fatal(&err), "{err}");
+        assert!(std::fs::read(&path).unwrap() == header, "left in place");
+    }
+
+    #[tokio::test]
+    async fn test_refresh_lock() {
+        let db = init_memory_db().await.unwrap();
+        let minute = Duration::from_secs(60);
+        assert!(try_lock_refresh(&db, "a", minute).await.unwrap());
+        assert!(!try_lock_refresh(&db, "b", minute).await.unwrap());
+        assert!(
+            try_lock_refresh(&db, "a", minute).await.unwrap(),
+            "re-entrant"
+        );
+
+        unlock_refresh(&db, "b").await.unwrap();
+        assert!(!try_lock_refresh(&db, "b", minute).await.unwrap());
+        unlock_refresh(&db, "a").await.unwrap();
+        assert!(try_lock_refresh(&db, "b", Duration::ZERO).await.unwrap());
+
+        // A lapsed lock is taken over.
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(try_lock_refresh(&db, "a", minute).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_token_history() {
+        let db = init_memory_db().await.unwrap();
+        assert!(current_tokens(&db).await.unwrap().is_none());
+        for n in 0..TOKEN_HISTORY_ROWS + 2 {
+            save_tokens(&db, &format!("a{n}"), &format!("r{n}"), None)
+                .await
+                .unwrap();
+        }
+        let current = current_tokens(&db).await.unwrap().unwrap();
+        assert_eq!(
+            current.refresh_token,
+            format!("r{}", TOKEN_HISTORY_ROWS + 1)
+        );
+
+        let history = token_history(&db, 1000).await.unwrap();
+        assert_eq!(history.len() as u64, TOKEN_HISTORY_ROWS, "oldest pruned");
+        assert_eq!(history[0], current);
+        assert_eq!(history[1].refresh_token, format!("r{TOKEN_HISTORY_ROWS}"));
+        assert_eq!(token_history(&db, 3).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_schema_migrations() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_url = sqlite_url(&dir.path().join("c.db").to_string_lossy(), "rwc");
+
+        {
+            let db = Database::connect(&db_url).await.unwrap();
+            db.execute(Statement::from_string(
+                sea_orm::DatabaseBackend::Sqlite,
+                "CREATE TABLE cached_dirs (file_id TEXT PRIMARY KEY, parent_id TEXT, name TEXT);
+                 INSERT INTO cached_dirs VALUES ('1', '0', 'repo');
+                 CREATE TABLE cached_files (file_id TEXT PRIMARY KEY, parent_id TEXT, name TEXT,
+                     size BIGINT, pick_code TEXT);
+                 INSERT INTO cached_files VALUES ('2', '1', 'config', 155, 'pc');",
+            ))
+            .await
+            .unwrap();
+        }
+
+        let db = init_db(&db_url, "/repo").await.unwrap();
+        let mut rows = entities::file_nodes::Entity::find().all(&db).await.unwrap();
+        rows.sort_by(|a, b| a.file_id.cmp(&b.file_id));
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].is_dir && !rows[1].is_dir);
+        assert_eq!((rows[1].size, rows[1].pick_code.as_str()), (155, "pc"));
+        assert_eq!(rows[1].repo, "/repo");
+        assert!(table_columns(&db, "cached_files").await.unwrap().is_empty());
+        let applied = entities::schema_version::Entity::find()
+            .all(&db)
+            .await
+            .unwrap();
+        assert_eq!(applied.len(), SCHEMA_VERSION as usize);
+
+        // A DB from a newer release is refused.
+        db.execute(Statement::from_string(
+            sea_orm::DatabaseBackend::Sqlite,
+            "INSERT INTO schema_version VALUES (99, '2026-01-01T00:00:00Z');",
+        ))
+        .await
+        .unwrap();
+        drop(db);
+        let err = init_db(&db_url, "/repo").await.unwrap_err();
+        assert!(err.to_string().contains("schema version 99"), "{err}");
+        assert!(is_fatal(&err) && !is_corrupt(&err), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_read_only_db_degrades_to_memory() {
+        use sea_orm::Set;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("c.db");
+        let db = init_db(&sqlite_url(&path.to_string_lossy(), "rwc"), "/r")
+            .await
+            .unwrap();
+        entities::file_nodes::Entity::insert(entities::file_nodes::ActiveModel {
+            repo: Set("/r".to_string()),
+            file_id: Set("1".to_string()),
+            parent_id: Set("0".to_string()),
+            name: Set("r".to_string()),
+            is_dir: Set(true),
+            size: Set(0),
+            pick_code: Set(String::new()),
+            sha1: Set(None),
+        })
+        .exec(&db)
+        .await
+        .unwrap();
+        drop(db);
+
+        let ro = Database::connect(sqlite_url(&path.to_string_lossy(), "ro"))
+            .await
+            .unwrap();
+        let cache = CacheDb::new(ro);
+        cache.check_writable("/r").await.unwrap();
+        assert!(cache.is_degraded());
+
+        // Reads see the copied rows and writes succeed in memory.
+        let rows = entities::file_nodes::Entity::find()
+            .all(&cache.conn())
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        touch_repo(&cache.conn(), "/r").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_evict_stale_repos() {
+        use sea_orm::Set;
+
+        let db = init_db("sqlite::memory:", "/live").await.unwrap();
+        for repo in ["/live", "/abandoned", "/fresh"] {
+            entities::file_nodes::Entity::insert(entities::file_nodes::ActiveModel {
+                repo: Set(repo.to_string()),
+                file_id: Set("1".to_string()),
+                parent_id: Set("0".to_string()),
+                name: Set("root".to_string()),
+                is_dir: Set(true),
+                size: Set(0),
+                pick_code: Set(String::new()),
+                sha1: Set(None),
+            })
+            .exec(&db)
+            .await
+            .unwrap();
+        }
+        entities::repo_access::Entity::insert(entities::repo_access::ActiveModel {
+            repo: Set("/abandoned".to_string()),
+            last_seen: Set(chrono::Utc::now() - chrono::Duration::days(60)),
+        })
+        .exec(&db)
+        .await
+        .unwrap();
+
+        let cutoff = chrono::Utc::now() - chrono::Duration::days(30);
+        let evicted = evict_stale_repos(&db, "/live", cutoff).await.unwrap();
+        assert_eq!(evicted, vec!["/abandoned".to_string()]);
+        let mut left: Vec<String> = entities::file_nodes::Entity::find()
+            .all(&db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.repo)
+            .collect();
+        left.sort();
+        assert_eq!(left, vec!["/fresh".to_string(), "/live".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_import_checkpoints() {
+        let db = init_db("sqlite::memory:", "/r").await.unwrap();
+        assert!(
+            import_checkpoint(&db, "/r", "data", "ab12")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let mut checkpoint = entities::import_checkpoints::Model {
+            repo: "/r".to_string(),
+            file_type: "data".to_string(),
+            name: "ab12".to_string(),
+            size: 4,
+            bytes_confirmed: 0,
+            sha1: "a".repeat(40),
+            verified: false,
+            updated_at: chrono::Utc::now(),
+        };
+        save_import_checkpoint(&db, checkpoint.clone())
+            .await
+            .unwrap();
+        checkpoint.bytes_confirmed = 4;
+        checkpoint.verified = true;
+        save_import_checkpoint(&db, checkpoint.clone())
+            .await
+            .unwrap();
+
+        let loaded = import_checkpoint(&db, "/r", "data", "ab12").await.unwrap();
+        assert_eq!(loaded, Some(checkpoint));
+        assert!(
+            import_checkpoint(&db, "/other", "data", "ab12")
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_journal() {
+        let db = init_db("sqlite::memory:", "/r").await.unwrap();
+        let now = chrono::Utc::now();
+        let entry = |file_id: &str, age_days: i64| entities::delete_journal::Model {
+            repo: "/r".to_string(),
+            file_id: file_id.to_string(),
+            parent_id: "9".to_string(),
+            name: format!("name-{file_id}"),
+            size: 4,
+            pick_code: format!("pc{file_id}"),
+            sha1: None,
+            deleted_at: now - chrono::Duration::days(age_days),
+        };
+        let cutoff = now - chrono::Duration::days(30);
+        journal_deletes(
+            &db,
+            vec![entry("1", 40), entry("2", 2)],
+            cutoff - chrono::Duration::days(30),
+        )
+        .await
+        .unwrap();
+        // The next write prunes entries past the retention.
+        journal_deletes(&db, vec![entry("3", 0)], cutoff)
+            .await
+            .unwrap();
+
+        let since = now - chrono::Duration::days(365);
+        let ids = |rows: Vec<entities::delete_journal::Model>| {
+            rows.into_iter().map(|r| r.file_id).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(deleted_objects(&db, "/r", since).await.unwrap()),
+            ["3", "2"]
+        );
+        assert_eq!(
+            ids(deleted_objects(&db, "/r", now - chrono::Duration::days(1))
+                .await
+                .unwrap()),
+            ["3"]
+        );
+        assert!(
+            deleted_objects(&db, "/other", since)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        forget_deleted(&db, "/r", &["3".to_string()]).await.unwrap();
+        assert_eq!(ids(deleted_objects(&db, "/r", since).await.unwrap()), ["2"]);
+    }
+
+    #[tokio::test]
+    async fn test_download_urls() {
+        let db = init_db("sqlite::memory:", "/r").await.unwrap();
+        let now = chrono::Utc::now();
+        let entry = |repo: &str, pick_code: &str, ttl_secs: i64| entities::download_urls::Model {
+            repo: repo.to_string(),
+            pick_code: pick_code.to_string(),
+            url: format!("https://cdn.example/{pick_code}"),
+            sha1: None,
+            size: Some(3),
+            expires_at: now + chrono::Duration::seconds(ttl_secs),
+        };
+        for e in [
+            entry("/r", "a", 60),
+            entry("/r", "b", -1),
+            entry("/o", "c", 60),
+        ] {
+            save_download_url(&db, e).await.unwrap();
+        }
+        // Saving again replaces the entry.
+        let mut renewed = entry("/r", "a", 120);
+        renewed.url = "https://cdn.example/a2".to_string();
+        save_download_url(&db, renewed.clone()).await.unwrap();
+
+        assert_eq!(live_download_urls(&db, "/r", now).await.unwrap(), [renewed]);
+        save_download_url(&db, entry("/r", "d", 60)).await.unwrap();
+        forget_download_url(&db, "/r", "d").await.unwrap();
+        assert_eq!(
+            entities::download_urls::Entity::find()
+                .all(&db)
+                .await
+                .unwrap()
+                .len(),
+            2,
+            "expired entries are dropped"
+        );
+        forget_download_urls(&db, "/r").await.unwrap();
+        assert!(live_download_urls(&db, "/r", now).await.unwrap().is_empty());
+        assert_eq!(live_download_urls(&db, "/o", now).await.unwrap().len(), 1);
+    }
+}
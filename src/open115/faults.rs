@@ -0,0 +1,117 @@
+//! Fault injection for chaos testing (`--inject-faults`).
+//!
+//! Makes the upstream path fail the way 115 does on a bad day, so a restic
+//! run against a real or mock account shows whether the retry, backoff and
+//! token refresh logic keep it alive. API calls are still sent; an injected
+//! failure replaces the response that came back, so the token refresh that
+//! follows an injected invalid-token answer is a real one. Downloads are cut
+//! short after the body has arrived.
+
+use bytes::Bytes;
+use reqwest::StatusCode;
+use reqwest::header::HeaderMap;
+
+use super::retry::unit_random;
+use crate::config::FaultSpec;
+
+/// Counter of injected failures, labelled by `kind`.
+pub const FAULTS_INJECTED_TOTAL: &str = "restic115_faults_injected_total";
+
+#[derive(Debug)]
+pub struct FaultInjector {
+    spec: FaultSpec,
+}
+
+impl FaultInjector {
+    pub fn new(spec: FaultSpec) -> Self {
+        Self { spec }
+    }
+
+    fn roll(&self, kind: &'static str, probability: f64) -> bool {
+        let hit = probability > 0.0 && unit_random() < probability;
+        if hit {
+            metrics::counter!(FAULTS_INJECTED_TOTAL, "kind" => kind).increment(1);
+        }
+        hit
+    }
+
+    /// Sometimes hold back an API response or download.
+    pub async fn delay(&self) {
+        if self.roll("slow", self.spec.slow) {
+            tokio::time::sleep(self.spec.delay).await;
+        }
+    }
+
+    /// Sometimes replace an API response with a refusal or an invalid-token
+    /// answer.
+    pub async fn api_response(
+        &self,
+        method: &str,
+        url: &str,
+        response: (StatusCode, HeaderMap, Bytes),
+    ) -> (StatusCode, HeaderMap, Bytes) {
+        self.delay().await;
+        let injected = if self.roll("429", self.spec.rate_limit) {
+            (StatusCode::TOO_MANY_REQUESTS, Bytes::new())
+        } else if self.roll("406", self.spec.quota) {
+            let body = r#"{"state":false,"code":406,"message":"injected: quota reached"}"#;
+            (StatusCode::OK, Bytes::from_static(body.as_bytes()))
+        } else if self.roll("token", self.spec.token) {
+            let body =
+                r#"{"state":false,"code":40140125,"message":"injected: access_token invalid"}"#;
+            (StatusCode::OK, Bytes::from_static(body.as_bytes()))
+        } else {
+            return response;
+        };
+        tracing::warn!("Injected HTTP {} on {} {}", injected.0, method, url);
+        (injected.0, HeaderMap::new(), injected.1)
+    }
+
+    /// Sometimes cut a downloaded body short.
+    pub fn download_body(&self, body: Bytes) -> Bytes {
+        if body.is_empty() || !self.roll("truncate", self.spec.truncate) {
+            return body;
+        }
+        let keep = (unit_random() * body.len() as f64) as usize;
+        tracing::warn!(
+            "Injected truncation of a download to {} of {} bytes",
+            keep,
+            body.len()
+        );
+        body.slice(..keep)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fault_injection() {
+        let ok = || (StatusCode::OK, HeaderMap::new(), Bytes::from_static(b"{}"));
+        let none = FaultInjector::new(FaultSpec::default());
+        for _ in 0..100 {
+            assert_eq!(none.api_response("GET", "/x", ok()).await.0, StatusCode::OK);
+            assert_eq!(none.download_body(Bytes::from_static(b"data")).len(), 4);
+        }
+
+        let always: FaultSpec = "429=1,truncate=1".parse().unwrap();
+        let all = FaultInjector::new(always);
+        let (status, _, _) = all.api_response("GET", "/x", ok()).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert!(all.download_body(Bytes::from_static(b"data")).len() < 4);
+
+        let token = FaultInjector::new("token=1".parse().unwrap());
+        let (_, _, body) = token.api_response("GET", "/x", ok()).await;
+        let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(v["code"], 40140125);
+
+        let half = FaultInjector::new("406=0.5".parse().unwrap());
+        let mut refused = 0;
+        for _ in 0..1000 {
+            let (_, _, body) = half.api_response("GET", "/x", ok()).await;
+            refused += usize::from(body.len() > 2);
+        }
+        assert!((350..650).contains(&refused), "{refused}");
+    }
+}
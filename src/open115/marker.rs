@@ -0,0 +1,137 @@
+//! Repository marker written by `--repo-template`.
+//!
+//! `init_repository` with a template stores `.restic-115/repo.json` in the
+//! repository folder, recording which instance created the repository, when,
+//! and how it lays out objects on 115. Startup checks the marker against the
+//! configuration, so a server pointed at a repository with a different
+//! layout refuses to run instead of failing to find its packs.
+
+use serde::{Deserialize, Serialize};
+
+/// Folder of server metadata inside the repository folder.
+pub const MARKER_DIR: &str = ".restic-115";
+/// Name of the marker file in [`MARKER_DIR`].
+pub const MARKER_FILE: &str = "repo.json";
+/// Version of the on-115 layout described by the marker.
+pub const LAYOUT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepoMarker {
+    /// Random id of the repository, stable across servers.
+    pub instance_id: String,
+    /// Unix time in seconds.
+    pub created_at: i64,
+    /// restic-115 version that created the repository.
+    pub created_by: String,
+    pub layout_version: u32,
+    /// 0 for the flat layout.
+    pub data_shard_depth: usize,
+    pub data_shard_width: usize,
+}
+
+impl RepoMarker {
+    pub fn new(data_shard_depth: usize, data_shard_width: usize) -> Self {
+        Self {
+            instance_id: uuid::Uuid::new_v4().to_string(),
+            created_at: chrono::Utc::now().timestamp(),
+            created_by: env!("CARGO_PKG_VERSION").to_string(),
+            layout_version: LAYOUT_VERSION,
+            data_shard_depth,
+            data_shard_width,
+        }
+    }
+
+    /// Why a server laying out packs as `data_shard_depth` levels of
+    /// `data_shard_width` characters cannot serve this repository, if so.
+    pub fn mismatch(&self, data_shard_depth: usize, data_shard_width: usize) -> Option<String> {
+        if self.layout_version > LAYOUT_VERSION {
+            return Some(format!(
+                "layout version {} is newer than this server supports ({}); upgrade restic-115",
+                self.layout_version, LAYOUT_VERSION
+            ));
+        }
+        // Without subdirectories the shard width is unused.
+        if self.data_shard_depth == 0 && data_shard_depth == 0 {
+            return None;
+        }
+        if self.data_shard_depth == 0 {
+            return Some(
+                "packs are stored flat in data/, but the server is configured for \
+                 subdirectories; set OPEN115_DATA_LAYOUT=flat"
+                    .to_string(),
+            );
+        }
+        if data_shard_depth == 0 {
+            return Some(format!(
+                "packs are sharded {} levels of {} characters deep, but the server is configured \
+                 for the flat layout; set OPEN115_DATA_LAYOUT=sharded",
+                self.data_shard_depth, self.data_shard_width
+            ));
+        }
+        if (self.data_shard_depth, self.data_shard_width) != (data_shard_depth, data_shard_width) {
+            return Some(format!(
+                "packs are sharded {} levels of {} characters deep, but the server is configured \
+                 for {} of {}; set OPEN115_DATA_SHARD_DEPTH={} and OPEN115_DATA_SHARD_WIDTH={}",
+                self.data_shard_depth,
+                self.data_shard_width,
+                data_shard_depth,
+                data_shard_width,
+                self.data_shard_depth,
+                self.data_shard_width
+            ));
+        }
+        None
+    }
+}
+
+/// Names of the first-level `data/` subdirectories for pack names of
+/// lowercase hex digits, `width` characters each.
+pub fn shard_names(width: usize) -> Vec<String> {
+    let count = 16usize.pow(width as u32);
+    (0..count).map(|i| format!("{i:0width$x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marker_mismatch() {
+        let marker = RepoMarker::new(1, 2);
+        let json = serde_json::to_string(&marker).unwrap();
+        let parsed: RepoMarker = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, marker);
+        assert_eq!(marker.mismatch(1, 2), None);
+        assert!(
+            marker
+                .mismatch(2, 2)
+                .unwrap()
+                .contains("OPEN115_DATA_SHARD_DEPTH=1")
+        );
+
+        let future = RepoMarker {
+            layout_version: LAYOUT_VERSION + 1,
+            ..marker.clone()
+        };
+        assert!(future.mismatch(1, 2).unwrap().contains("upgrade"));
+
+        let flat = RepoMarker::new(0, 2);
+        assert_eq!(flat.mismatch(0, 2), None);
+        assert_eq!(flat.mismatch(0, 3), None);
+        assert!(
+            flat.mismatch(1, 2)
+                .unwrap()
+                .contains("OPEN115_DATA_LAYOUT=flat")
+        );
+        assert!(
+            marker
+                .mismatch(0, 2)
+                .unwrap()
+                .contains("OPEN115_DATA_LAYOUT=sharded")
+        );
+
+        let shards = shard_names(2);
+        assert_eq!(shards.len(), 256);
+        assert_eq!((shards[0].as_str(), shards[255].as_str()), ("00", "ff"));
+    }
+}
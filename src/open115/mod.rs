@@ -1,11 +1,21 @@
 //! 115 Open Platform client module.
 
+mod adaptive;
 mod auth;
+mod backend;
+pub mod budget;
 mod client;
 pub mod database;
+mod faults;
+mod fixtures;
+pub mod marker;
+pub mod retry;
+mod shape;
+mod throttle;
 mod types;
 
-pub use client::{FileInfo, Open115Client};
+pub use auth::TokenStatus;
+pub use client::{FileInfo, Open115Client, SpaceInfo, repo_namespace};
 
 /// Restic backend file types.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -0,0 +1,370 @@
+//! Retry policy shared by the token manager and the API client.
+//!
+//! Both talk to 115 under the same rate limits, so they also share one
+//! [`RateLimitGate`]: once any call is rate limited, every caller waits out
+//! that backoff before its next request, instead of each backing off on its
+//! own while the others keep adding pressure. When 115 says how long to
+//! wait (a `Retry-After` header, or `retry_after` in the JSON body), that
+//! wait replaces the policy's guess.
+//!
+//! Backoff delays are jittered, and callers held by the gate are released
+//! over a spread rather than all at once, so requests that failed together
+//! do not retry together. A [`RetryBudget`] shared by all API calls bounds
+//! retries to a share of the requests, so under a sustained overload
+//! requests fail fast instead of multiplying the pressure.
+
+use parking_lot::Mutex;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use serde_json::Value;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Counter of retries refused because the retry budget was used up.
+pub const RETRY_BUDGET_EXHAUSTED_TOTAL: &str = "restic115_retry_budget_exhausted_total";
+
+/// Delay before retrying after the `attempt`-th failure (starting at 1).
+pub trait BackoffStrategy: Send + Sync {
+    fn delay(&self, attempt: usize) -> Duration;
+}
+
+/// `base * 2^(attempt - 1)`, capped at `cap`.
+#[derive(Debug, Clone, Copy)]
+pub struct Exponential {
+    pub base: Duration,
+    pub cap: Duration,
+}
+
+impl BackoffStrategy for Exponential {
+    fn delay(&self, attempt: usize) -> Duration {
+        let shift = attempt.saturating_sub(1).min(16) as u32;
+        self.base.saturating_mul(1 << shift).min(self.cap)
+    }
+}
+
+/// Uniformly distributed in `0..1`.
+pub(crate) fn unit_random() -> f64 {
+    static KEY: LazyLock<RandomState> = LazyLock::new(RandomState::new);
+    static DRAWS: AtomicU64 = AtomicU64::new(0);
+    let n = DRAWS.fetch_add(1, Ordering::Relaxed);
+    (KEY.hash_one(n) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Another strategy's delay, randomized to between half and all of it.
+#[derive(Debug, Clone, Copy)]
+pub struct Jittered<B>(pub B);
+
+impl<B: BackoffStrategy> BackoffStrategy for Jittered<B> {
+    fn delay(&self, attempt: usize) -> Duration {
+        let delay = self.0.delay(attempt);
+        delay.mul_f64(0.5 + unit_random() / 2.0)
+    }
+}
+
+/// Short exponential backoff used for API calls and token refreshes; the cap
+/// keeps a single request from blocking for minutes.
+const API_BACKOFF: Exponential = Exponential {
+    base: Duration::from_secs(1),
+    cap: Duration::from_secs(16),
+};
+
+/// Longest wait requested by 115 that is honored as is; a request should
+/// not block for longer on a single backoff.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
+
+/// How often one kind of request is attempted and how long to wait between.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: usize,
+    pub backoff: Arc<dyn BackoffStrategy>,
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: usize, backoff: impl BackoffStrategy + 'static) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            backoff: Arc::new(backoff),
+        }
+    }
+
+    /// 115 API calls.
+    pub fn api() -> Self {
+        Self::new(6, Jittered(API_BACKOFF))
+    }
+
+    /// Token refreshes. A single attempt: refreshing too often is itself
+    /// rate limited (40140117), so a failed refresh surfaces immediately.
+    pub fn refresh() -> Self {
+        Self::new(1, Jittered(API_BACKOFF))
+    }
+
+    /// OSS PutObject of one upload body. Not gated: OSS is not subject to
+    /// 115's API rate limits.
+    pub fn oss() -> Self {
+        Self::new(4, Jittered(API_BACKOFF))
+    }
+
+    /// Whether another attempt may follow the `attempt`-th.
+    pub fn can_retry(&self, attempt: usize) -> bool {
+        attempt < self.max_attempts
+    }
+}
+
+/// Instant before which no request should be sent to 115.
+#[derive(Clone, Default)]
+pub struct RateLimitGate {
+    until: Arc<Mutex<Option<Instant>>>,
+}
+
+impl RateLimitGate {
+    /// Hold back every caller for at least `delay` from now.
+    pub fn back_off(&self, delay: Duration) {
+        let until = Instant::now() + delay;
+        let mut guard = self.until.lock();
+        if guard.is_none_or(|current| current < until) {
+            *guard = Some(until);
+        }
+    }
+
+    /// Wait until any backoff in progress has passed, and then up to a
+    /// quarter of the wait longer, so the callers it held are not all
+    /// released at once.
+    pub async fn wait(&self) {
+        let until = *self.until.lock();
+        let now = Instant::now();
+        if let Some(until) = until
+            && until > now
+        {
+            let stagger = (until - now).mul_f64(unit_random() / 4.0);
+            tokio::time::sleep_until(until + stagger).await;
+        }
+    }
+}
+
+/// Retries left across all requests (`OPEN115_RETRY_BUDGET`).
+///
+/// Every request adds `ratio` of a retry and every retry takes one, on top
+/// of a floor refilling one retry per second so a quiet server can still
+/// retry. The balance is capped, so a long calm spell does not bank a burst.
+#[derive(Clone)]
+pub struct RetryBudget {
+    ratio: f64,
+    state: Arc<Mutex<BudgetState>>,
+}
+
+struct BudgetState {
+    balance: f64,
+    refilled: Instant,
+    exhausted: bool,
+}
+
+/// Most retries the budget holds.
+const BUDGET_CAP: f64 = 50.0;
+/// Retries available at startup.
+const BUDGET_INITIAL: f64 = 10.0;
+/// Retries per second added regardless of requests.
+const BUDGET_FLOOR_PER_SEC: f64 = 1.0;
+
+impl RetryBudget {
+    pub fn new(ratio: f64) -> Self {
+        Self {
+            ratio: ratio.max(0.0),
+            state: Arc::new(Mutex::new(BudgetState {
+                balance: BUDGET_INITIAL,
+                refilled: Instant::now(),
+                exhausted: false,
+            })),
+        }
+    }
+
+    /// Count a request.
+    pub fn deposit(&self) {
+        let mut state = self.state.lock();
+        state.balance = (state.balance + self.ratio).min(BUDGET_CAP);
+    }
+
+    /// Take one retry, if any is left.
+    pub fn withdraw(&self) -> bool {
+        self.withdraw_at(Instant::now())
+    }
+
+    fn withdraw_at(&self, now: Instant) -> bool {
+        let mut state = self.state.lock();
+        let elapsed = now.saturating_duration_since(state.refilled);
+        state.refilled = now;
+        state.balance =
+            (state.balance + elapsed.as_secs_f64() * BUDGET_FLOOR_PER_SEC).min(BUDGET_CAP);
+        if state.balance >= 1.0 {
+            state.balance -= 1.0;
+            if std::mem::take(&mut state.exhausted) {
+                tracing::info!("Retry budget available again");
+            }
+            return true;
+        }
+        metrics::counter!(RETRY_BUDGET_EXHAUSTED_TOTAL).increment(1);
+        if !std::mem::replace(&mut state.exhausted, true) {
+            tracing::warn!("Retry budget used up; failing rate-limited 115 calls without retrying");
+        }
+        false
+    }
+}
+
+/// A retry policy together with the gate shared by all 115 callers.
+#[derive(Clone)]
+pub struct Retrier {
+    pub policy: RetryPolicy,
+    pub gate: RateLimitGate,
+    /// Shared bound on retries, if any.
+    pub budget: Option<RetryBudget>,
+}
+
+impl Retrier {
+    pub fn new(policy: RetryPolicy, gate: RateLimitGate) -> Self {
+        Self {
+            policy,
+            gate,
+            budget: None,
+        }
+    }
+
+    pub fn with_budget(mut self, budget: RetryBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Whether another attempt may follow the `attempt`-th, under both the
+    /// policy and the budget.
+    pub fn may_retry(&self, attempt: usize) -> bool {
+        self.policy.can_retry(attempt) && self.budget.as_ref().is_none_or(RetryBudget::withdraw)
+    }
+
+    /// Back off after the `attempt`-th failure: extend the shared gate by the
+    /// policy's delay and wait it out.
+    pub async fn backoff(&self, attempt: usize) {
+        self.backoff_after(attempt, None).await;
+    }
+
+    /// Like [`Self::backoff`], but wait `requested` (capped at two minutes)
+    /// instead when 115 said how long to wait.
+    pub async fn backoff_after(&self, attempt: usize, requested: Option<Duration>) {
+        let delay = match requested {
+            Some(requested) => requested.min(MAX_RETRY_AFTER),
+            None => self.policy.backoff.delay(attempt),
+        };
+        self.gate.back_off(delay);
+        self.gate.wait().await;
+    }
+}
+
+/// Wait requested by a `Retry-After` header, in seconds or as an HTTP date.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (at.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Wait requested in a 115 JSON body: `retry_after` seconds, at the top
+/// level or in `data`.
+pub fn json_retry_after(body: &Value) -> Option<Duration> {
+    let field = body
+        .get("retry_after")
+        .or_else(|| body.get("data")?.get("retry_after"))?;
+    let secs = match field {
+        Value::String(s) => s.trim().parse::<f64>().ok()?,
+        other => other.as_f64()?,
+    };
+    (secs.is_finite() && secs >= 0.0).then(|| Duration::from_secs_f64(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exponential_backoff_is_capped() {
+        let delays: Vec<u64> = (1..=7).map(|a| API_BACKOFF.delay(a).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 16, 16]);
+        assert!(RetryPolicy::api().can_retry(5));
+        assert!(!RetryPolicy::api().can_retry(6));
+        assert!(!RetryPolicy::refresh().can_retry(1));
+        assert!(RetryPolicy::oss().can_retry(3));
+        assert!(!RetryPolicy::oss().can_retry(4));
+    }
+
+    #[test]
+    fn test_jitter_and_budget() {
+        for attempt in 1..=6 {
+            let full = API_BACKOFF.delay(attempt);
+            let jittered = Jittered(API_BACKOFF).delay(attempt);
+            assert!(jittered >= full / 2 && jittered <= full, "{jittered:?}");
+        }
+        let delays: std::collections::HashSet<Duration> =
+            (0..10).map(|_| Jittered(API_BACKOFF).delay(3)).collect();
+        assert!(delays.len() > 1, "retries are spread out");
+
+        let budget = RetryBudget::new(0.5);
+        let start = Instant::now();
+        let allowed = (0..20).filter(|_| budget.withdraw_at(start)).count();
+        assert_eq!(allowed, BUDGET_INITIAL as usize);
+        // Two requests earn a retry.
+        budget.deposit();
+        assert!(!budget.withdraw_at(start));
+        budget.deposit();
+        assert!(budget.withdraw_at(start));
+        assert!(!budget.withdraw_at(start));
+        // As does a second without any.
+        assert!(budget.withdraw_at(start + Duration::from_secs(1)));
+
+        let retrier = Retrier::new(RetryPolicy::api(), RateLimitGate::default())
+            .with_budget(RetryBudget::new(0.0));
+        assert_eq!((0..20).filter(|_| retrier.may_retry(1)).count(), 10);
+        assert!(!retrier.may_retry(6));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(RETRY_AFTER, "7".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+        let at = chrono::Utc::now() + chrono::Duration::seconds(30);
+        headers.insert(RETRY_AFTER, at.to_rfc2822().parse().unwrap());
+        let wait = retry_after(&headers).unwrap();
+        assert!(wait > Duration::from_secs(25) && wait <= Duration::from_secs(30));
+        headers.insert(
+            RETRY_AFTER,
+            "Thu, 01 Jan 1970 00:00:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+
+        let body = serde_json::json!({"state": false, "data": {"retry_after": "1.5"}});
+        assert_eq!(json_retry_after(&body), Some(Duration::from_millis(1500)));
+        assert_eq!(
+            json_retry_after(&serde_json::json!({"retry_after": 3})),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(json_retry_after(&serde_json::json!({"data": []})), None);
+    }
+
+    #[tokio::test]
+    async fn test_gate_holds_back_other_callers() {
+        let gate = RateLimitGate::default();
+        let other = gate.clone();
+        gate.back_off(Duration::from_millis(50));
+        // A shorter backoff never shortens the one in progress.
+        other.back_off(Duration::from_millis(1));
+        let start = std::time::Instant::now();
+        other.wait().await;
+        assert!(start.elapsed() >= Duration::from_millis(45));
+    }
+}
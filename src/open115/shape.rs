@@ -0,0 +1,272 @@
+//! Detection of 115 API drift (`OPEN115_RESPONSE_PARSING`).
+//!
+//! The responses the server depends on most (token refresh, listings, upload
+//! init and the OSS upload callback) are checked against the fields known to
+//! the parser before they are deserialized. Unknown fields, or a `data` of
+//! the wrong kind, either fail the request (`strict`, for CI and
+//! self-tests) or are logged once per field with a sample of the response
+//! and counted (`lenient`, the default), so a changed API shows up in the
+//! logs before it breaks a backup.
+
+use serde_json::Value;
+use std::collections::HashSet;
+
+use crate::config::ResponseParsing;
+use crate::error::{AppError, Result};
+
+/// Counter of unexpected fields or shapes seen in 115 responses, by
+/// `response`.
+pub const UNEXPECTED_RESPONSE_FIELDS_TOTAL: &str = "restic115_unexpected_response_fields_total";
+
+/// Longest response sample logged.
+const MAX_SAMPLE_BYTES: usize = 1024;
+
+/// Fields of the envelope every 115 response shares.
+const ENVELOPE: &[&str] = &["state", "code", "message", "error", "errno", "data"];
+
+/// The checked responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+    /// `POST /open/refreshToken`.
+    Token,
+    /// `GET /open/ufile/files`.
+    Listing,
+    /// `POST /open/upload/init`.
+    UploadInit,
+    /// The OSS PutObject callback result.
+    Callback,
+}
+
+impl Shape {
+    fn as_str(self) -> &'static str {
+        match self {
+            Shape::Token => "token",
+            Shape::Listing => "listing",
+            Shape::UploadInit => "upload_init",
+            Shape::Callback => "callback",
+        }
+    }
+
+    /// Known top-level fields besides the envelope.
+    fn top_level(self) -> &'static [&'static str] {
+        match self {
+            Shape::Listing => &[
+                "count",
+                "sys_count",
+                "offset",
+                "limit",
+                "aid",
+                "cid",
+                "is_asc",
+                "min_size",
+                "max_size",
+                "sys_dir",
+                "hide_data",
+                "record_open_time",
+                "star",
+                "type",
+                "suffix",
+                "cur",
+                "min_create_time",
+                "max_create_time",
+                "order",
+                "fc_mix",
+                "path",
+            ],
+            Shape::Token | Shape::UploadInit | Shape::Callback => &[],
+        }
+    }
+
+    /// Known fields of `data`, or of each entry when it is a list.
+    fn data(self) -> &'static [&'static str] {
+        match self {
+            Shape::Token => &["access_token", "refresh_token", "expires_in"],
+            Shape::Listing => &[
+                "fid",
+                "aid",
+                "pid",
+                "fc",
+                "fn",
+                "fco",
+                "ism",
+                "isp",
+                "pc",
+                "upt",
+                "uet",
+                "uppt",
+                "cm",
+                "fdesc",
+                "ispl",
+                "fl",
+                "sha1",
+                "fs",
+                "fta",
+                "ico",
+                "fatr",
+                "isv",
+                "def",
+                "def2",
+                "play_long",
+                "v_img",
+                "thumb",
+                "uo",
+            ],
+            Shape::UploadInit => &[
+                "pick_code",
+                "status",
+                "statuscode",
+                "statusmsg",
+                "sign_key",
+                "sign_check",
+                "file_id",
+                "target",
+                "bucket",
+                "object",
+                "callback",
+            ],
+            Shape::Callback => &[
+                "pick_code",
+                "file_name",
+                "file_size",
+                "file_id",
+                "sha1",
+                "cid",
+            ],
+        }
+    }
+
+    /// Whether `data` may be a list of entries rather than one object.
+    fn data_is_list(self) -> bool {
+        self == Shape::Listing
+    }
+}
+
+/// Checks responses against [`Shape`]s; see the module docs.
+pub struct ShapeChecker {
+    mode: ResponseParsing,
+    /// `(response, field)` pairs already logged.
+    logged: parking_lot::Mutex<HashSet<(&'static str, String)>>,
+}
+
+impl ShapeChecker {
+    pub fn new(mode: ResponseParsing) -> Self {
+        Self {
+            mode,
+            logged: parking_lot::Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Check `value` against `shape`. Only fails in strict mode.
+    pub fn check(&self, shape: Shape, value: &Value) -> Result<()> {
+        let problems = unexpected(shape, value);
+        if problems.is_empty() {
+            return Ok(());
+        }
+        metrics::counter!(UNEXPECTED_RESPONSE_FIELDS_TOTAL, "response" => shape.as_str())
+            .increment(problems.len() as u64);
+        if self.mode == ResponseParsing::Strict {
+            return Err(AppError::Internal(format!(
+                "unexpected {} in 115 {} response",
+                problems.join(", "),
+                shape.as_str()
+            )));
+        }
+        let new: Vec<&String> = {
+            let mut logged = self.logged.lock();
+            problems
+                .iter()
+                .filter(|p| logged.insert((shape.as_str(), (*p).clone())))
+                .collect()
+        };
+        if !new.is_empty() {
+            let mut sample = value.to_string();
+            if sample.len() > MAX_SAMPLE_BYTES {
+                sample.truncate(sample.floor_char_boundary(MAX_SAMPLE_BYTES));
+                sample.push_str("...");
+            }
+            tracing::warn!(
+                "115 {} response has unexpected {:?}; the API may have changed: {}",
+                shape.as_str(),
+                new,
+                sample
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Unexpected fields (`field` or `data.field`) and shapes in `value`.
+fn unexpected(shape: Shape, value: &Value) -> Vec<String> {
+    let Value::Object(top) = value else {
+        return vec!["non-object body".to_string()];
+    };
+    let mut problems: Vec<String> = top
+        .keys()
+        .filter(|k| !ENVELOPE.contains(&k.as_str()) && !shape.top_level().contains(&k.as_str()))
+        .cloned()
+        .collect();
+    let entries: Vec<&serde_json::Map<String, Value>> = match top.get("data") {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::Object(data)) if !shape.data_is_list() => vec![data],
+        Some(Value::Array(items)) if shape.data_is_list() => {
+            let mut entries = Vec::new();
+            for item in items {
+                match item {
+                    Value::Object(entry) => entries.push(entry),
+                    _ => problems.push("non-object data entry".to_string()),
+                }
+            }
+            entries
+        }
+        // 115 answers errors with `"data": []` whatever the endpoint.
+        Some(Value::Array(items)) if items.is_empty() => Vec::new(),
+        Some(_) => {
+            problems.push("data type".to_string());
+            Vec::new()
+        }
+    };
+    let mut fields = HashSet::new();
+    for entry in entries {
+        for key in entry.keys() {
+            if !shape.data().contains(&key.as_str()) && fields.insert(key.as_str()) {
+                problems.push(format!("data.{key}"));
+            }
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_unexpected_fields() {
+        let token = json!({"state": 1, "code": 0, "message": "", "data": {
+            "access_token": "a", "refresh_token": "r", "expires_in": 7200,
+        }});
+        assert!(unexpected(Shape::Token, &token).is_empty());
+        assert!(unexpected(Shape::Token, &json!({"state": false, "data": []})).is_empty());
+
+        let listing = json!({"state": true, "count": 2, "path": [], "new_top": 1, "data": [
+            {"fid": "1", "fc": "1", "fn": "a", "fs": 3, "pc": "p", "badge": 1},
+            {"fid": "2", "fc": "1", "fn": "b", "fs": 3, "pc": "q", "badge": 2},
+        ]});
+        assert_eq!(
+            unexpected(Shape::Listing, &listing),
+            ["new_top", "data.badge"]
+        );
+        assert_eq!(
+            unexpected(Shape::UploadInit, &json!({"state": true, "data": "x"})),
+            ["data type"]
+        );
+
+        let strict = ShapeChecker::new(ResponseParsing::Strict);
+        let err = strict.check(Shape::Listing, &listing).unwrap_err();
+        assert!(err.to_string().contains("data.badge"), "{err}");
+        assert!(strict.check(Shape::Token, &token).is_ok());
+        let lenient = ShapeChecker::new(ResponseParsing::Lenient);
+        assert!(lenient.check(Shape::Listing, &listing).is_ok());
+    }
+}
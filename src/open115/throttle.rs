@@ -0,0 +1,81 @@
+//! Bandwidth limiting for upstream transfers.
+
+use parking_lot::Mutex;
+use std::time::{Duration, Instant};
+
+/// Shared token bucket that caps throughput in bytes per second.
+///
+/// Callers reserve bytes up front; when the bucket is overdrawn they sleep
+/// until the reservation is paid back, so concurrent transfers share the
+/// configured rate instead of each getting their own.
+#[derive(Debug)]
+pub struct BandwidthLimiter {
+    bytes_per_sec: f64,
+    state: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    available: f64,
+    last: Instant,
+}
+
+impl BandwidthLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = bytes_per_sec.max(1) as f64;
+        Self {
+            bytes_per_sec,
+            state: Mutex::new(Bucket {
+                // Allow up to one second of burst.
+                available: bytes_per_sec,
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec as u64
+    }
+
+    /// Account for `n` transferred bytes, sleeping if the rate is exceeded.
+    pub async fn consume(&self, n: usize) {
+        let wait = self.reserve(n, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn reserve(&self, n: usize, now: Instant) -> Duration {
+        let mut bucket = self.state.lock();
+        let elapsed = now.saturating_duration_since(bucket.last).as_secs_f64();
+        bucket.last = now;
+        bucket.available =
+            (bucket.available + elapsed * self.bytes_per_sec).min(self.bytes_per_sec);
+        bucket.available -= n as f64;
+        if bucket.available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.available / self.bytes_per_sec)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve_accumulates_debt() {
+        let limiter = BandwidthLimiter::new(1000);
+        let now = Instant::now();
+        // Burst allowance covers the first second.
+        assert_eq!(limiter.reserve(1000, now), Duration::ZERO);
+        // Overdrawn by 500 bytes -> half a second.
+        assert_eq!(limiter.reserve(500, now), Duration::from_millis(500));
+        // A second consumer queues behind the first one's debt.
+        assert_eq!(limiter.reserve(500, now), Duration::from_secs(1));
+        // Time passing pays debt back.
+        let later = now + Duration::from_secs(2);
+        assert_eq!(limiter.reserve(0, later), Duration::ZERO);
+    }
+}
@@ -21,6 +21,29 @@ where
     })
 }
 
+/// OSS endpoints given as one string, possibly comma-separated, or a list.
+fn deserialize_endpoints<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let v = Option::<Value>::deserialize(deserializer)?;
+    let items = match v {
+        Some(Value::String(s)) => vec![s],
+        Some(Value::Array(items)) => items
+            .into_iter()
+            .filter_map(|item| item.as_str().map(String::from))
+            .collect(),
+        _ => Vec::new(),
+    };
+    Ok(items
+        .iter()
+        .flat_map(|s| s.split(','))
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect())
+}
+
 #[derive(Debug, Deserialize)]
 pub struct RefreshTokenResponse {
     #[serde(default, deserialize_with = "deserialize_state")]
@@ -59,6 +82,8 @@ pub struct FileEntry {
     pub fs: i64,
     #[serde(default)]
     pub pc: String,
+    #[serde(default)]
+    pub sha1: Option<String>,
 }
 
 impl FileEntry {
@@ -96,7 +121,11 @@ pub struct UploadTokenResponse {
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct UploadToken {
-    pub endpoint: Option<String>,
+    /// Regular OSS endpoints, in the order given.
+    #[serde(default, deserialize_with = "deserialize_endpoints")]
+    pub endpoint: Vec<String>,
+    #[serde(default, alias = "AccelerateEndpoint")]
+    pub accelerate_endpoint: Option<String>,
     #[serde(rename = "AccessKeyId")]
     pub access_key_id: Option<String>,
     #[serde(rename = "AccessKeySecret")]
@@ -159,3 +188,35 @@ pub struct OssCallbackData {
     #[serde(rename = "file_id", default)]
     pub file_id: String,
 }
+
+/// Byte count that 115 sends either as a number or as a numeric string.
+fn deserialize_lenient_u64<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let v = Value::deserialize(deserializer)?;
+    Ok(match v {
+        Value::Number(n) => n.as_u64().unwrap_or(0),
+        Value::String(s) => s.parse().unwrap_or(0),
+        _ => 0,
+    })
+}
+
+/// `data` of `GET /open/user/info`.
+#[derive(Debug, Deserialize)]
+pub struct UserInfoData {
+    pub rt_space_info: Option<SpaceInfoData>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SpaceInfoData {
+    pub all_total: SpaceSize,
+    pub all_remain: SpaceSize,
+    pub all_use: SpaceSize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SpaceSize {
+    #[serde(deserialize_with = "deserialize_lenient_u64")]
+    pub size: u64,
+}
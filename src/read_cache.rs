@@ -0,0 +1,366 @@
+//! Size-capped on-disk LRU cache of restic objects.
+//!
+//! restic object names are the SHA-256 of their contents, so a cached copy
+//! never goes stale; entries only leave the cache through eviction or a
+//! DELETE from restic. The in-memory index is rebuilt from the directory on
+//! startup.
+//!
+//! With compression enabled, metadata objects (index, snapshots, keys) are
+//! stored zstd-compressed as `<name>.zst` whenever that is smaller, and the
+//! size budget is charged for the bytes on disk.
+
+use bytes::Bytes;
+use moka::future::Cache;
+use moka::notification::RemovalCause;
+use moka::policy::EvictionPolicy;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::error::Result;
+use crate::open115::ResticFileType;
+
+const TMP_SUFFIX: &str = ".tmp";
+const ZSTD_SUFFIX: &str = ".zst";
+const ZSTD_LEVEL: i32 = 3;
+/// Longest zstd frame header (`ZSTD_FRAMEHEADERSIZE_MAX`).
+const ZSTD_FRAME_HEADER_MAX: usize = 18;
+
+/// A cached object as stored on disk.
+#[derive(Debug, Clone, Copy)]
+struct Stored {
+    disk_size: u64,
+    /// Size of the object itself, once decompressed.
+    size: u64,
+    compressed: bool,
+}
+
+/// Handle to the read cache; cheap to clone.
+#[derive(Clone)]
+pub struct ReadCache {
+    dir: Arc<PathBuf>,
+    /// Keyed by `type/name`.
+    index: Cache<String, Stored>,
+    compress: bool,
+}
+
+impl ReadCache {
+    /// Open (or create) the cache at `dir`, holding at most `capacity` bytes
+    /// on disk. `compress` enables zstd for metadata objects.
+    pub async fn open(dir: PathBuf, capacity: u64, compress: bool) -> Result<Self> {
+        tokio::fs::create_dir_all(&dir).await?;
+        let evict_dir = dir.clone();
+        let index = Cache::builder()
+            .max_capacity(capacity)
+            .weigher(|_key: &String, stored: &Stored| {
+                stored.disk_size.try_into().unwrap_or(u32::MAX)
+            })
+            .eviction_policy(EvictionPolicy::lru())
+            .eviction_listener(move |key: Arc<String>, stored: Stored, cause| {
+                // A replacement stored in the same form reuses the path; one
+                // stored in the other form already removed the stale file.
+                if cause != RemovalCause::Replaced {
+                    let _ = std::fs::remove_file(evict_dir.join(file_key(&key, stored.compressed)));
+                }
+            })
+            .build();
+        let cache = Self {
+            dir: Arc::new(dir),
+            index,
+            compress,
+        };
+        cache.load_existing().await?;
+        Ok(cache)
+    }
+
+    async fn load_existing(&self) -> Result<()> {
+        let mut count = 0usize;
+        for file_type in CACHED_TYPES {
+            let type_dir = self.dir.join(file_type.dirname());
+            let mut read_dir = match tokio::fs::read_dir(&type_dir).await {
+                Ok(rd) => rd,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            while let Some(entry) = read_dir.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.ends_with(TMP_SUFFIX) {
+                    let _ = tokio::fs::remove_file(entry.path()).await;
+                    continue;
+                }
+                let (name, compressed) = match name.strip_suffix(ZSTD_SUFFIX) {
+                    Some(stem) => (stem.to_string(), true),
+                    None => (name, false),
+                };
+                let disk_size = entry.metadata().await?.len();
+                let size = if compressed {
+                    match content_size(&entry.path()).await {
+                        Some(size) => size,
+                        None => {
+                            // Written without its size in the frame header.
+                            let _ = tokio::fs::remove_file(entry.path()).await;
+                            continue;
+                        }
+                    }
+                } else {
+                    disk_size
+                };
+                let stored = Stored {
+                    disk_size,
+                    size,
+                    compressed,
+                };
+                self.index.insert(key(*file_type, &name), stored).await;
+                count += 1;
+            }
+        }
+        self.index.run_pending_tasks().await;
+        tracing::info!(
+            "Read cache: {} objects ({} bytes) in {}",
+            count,
+            self.index.weighted_size(),
+            self.dir.display()
+        );
+        Ok(())
+    }
+
+    /// Whether objects of `file_type` are cached at all.
+    pub fn caches(file_type: ResticFileType) -> bool {
+        CACHED_TYPES.contains(&file_type)
+    }
+
+    /// Return the cached object, if present.
+    pub async fn get(&self, file_type: ResticFileType, name: &str) -> Option<Bytes> {
+        if !Self::caches(file_type) || !is_safe_name(name) {
+            return None;
+        }
+        let key = key(file_type, name);
+        let stored = self.index.get(&key).await?;
+        let path = self.dir.join(file_key(&key, stored.compressed));
+        let read = tokio::task::spawn_blocking(move || {
+            let raw = std::fs::read(path)?;
+            if stored.compressed {
+                zstd::decode_all(raw.as_slice())
+            } else {
+                Ok(raw)
+            }
+        })
+        .await;
+        match read {
+            Ok(Ok(data)) => Some(Bytes::from(data)),
+            Ok(Err(e)) => {
+                tracing::warn!("Read cache: dropping unreadable {}: {}", key, e);
+                self.index.invalidate(&key).await;
+                None
+            }
+            Err(e) => {
+                tracing::warn!("Read cache: read task for {} failed: {}", key, e);
+                None
+            }
+        }
+    }
+
+    /// Size of a cached object, without reading it, so a caller can budget
+    /// for it first.
+    pub async fn size(&self, file_type: ResticFileType, name: &str) -> Option<u64> {
+        if !Self::caches(file_type) || !is_safe_name(name) {
+            return None;
+        }
+        Some(self.index.get(&key(file_type, name)).await?.size)
+    }
+
+    /// Store a complete object. Failures are logged and otherwise ignored.
+    pub async fn put(&self, file_type: ResticFileType, name: &str, data: Bytes) {
+        if !Self::caches(file_type) || !is_safe_name(name) {
+            return;
+        }
+        let key = key(file_type, name);
+        let dir = self.dir.clone();
+        let compress = self.compress && COMPRESSED_TYPES.contains(&file_type);
+        let file = key.clone();
+        let written =
+            tokio::task::spawn_blocking(move || store(&dir, &file, &data, compress)).await;
+        match written {
+            Ok(Ok(stored)) => self.index.insert(key, stored).await,
+            Ok(Err(e)) => tracing::warn!("Read cache: failed to store {}: {}", key, e),
+            Err(e) => tracing::warn!("Read cache: store task for {} failed: {}", key, e),
+        }
+    }
+
+    /// Drop an object, e.g. after restic deleted it.
+    pub async fn remove(&self, file_type: ResticFileType, name: &str) {
+        if Self::caches(file_type) && is_safe_name(name) {
+            // The eviction listener removes the file.
+            self.index.invalidate(&key(file_type, name)).await;
+        }
+    }
+}
+
+/// Locks are short-lived and rewritten constantly; caching them would only churn.
+const CACHED_TYPES: &[ResticFileType] = &[
+    ResticFileType::Data,
+    ResticFileType::Index,
+    ResticFileType::Snapshots,
+    ResticFileType::Keys,
+];
+
+/// Metadata objects; pack files are already compressed by restic.
+const COMPRESSED_TYPES: &[ResticFileType] = &[
+    ResticFileType::Index,
+    ResticFileType::Snapshots,
+    ResticFileType::Keys,
+];
+
+fn key(file_type: ResticFileType, name: &str) -> String {
+    format!("{}/{}", file_type.dirname(), name)
+}
+
+/// Path of an entry below the cache directory.
+fn file_key(key: &str, compressed: bool) -> String {
+    if compressed {
+        format!("{key}{ZSTD_SUFFIX}")
+    } else {
+        key.to_string()
+    }
+}
+
+/// Write an object, compressed if requested and worthwhile, and remove the
+/// copy in the other form if there is one.
+fn store(dir: &Path, key: &str, data: &[u8], compress: bool) -> std::io::Result<Stored> {
+    let compressed = if compress {
+        // Unlike the streaming encoder, this records the size in the frame.
+        Some(zstd::bulk::compress(data, ZSTD_LEVEL)?).filter(|c| c.len() < data.len())
+    } else {
+        None
+    };
+    let stored = Stored {
+        disk_size: compressed.as_ref().map_or(data.len(), Vec::len) as u64,
+        size: data.len() as u64,
+        compressed: compressed.is_some(),
+    };
+    write_atomically(
+        &dir.join(file_key(key, stored.compressed)),
+        compressed.as_deref().unwrap_or(data),
+    )?;
+    match std::fs::remove_file(dir.join(file_key(key, !stored.compressed))) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    Ok(stored)
+}
+
+/// Decompressed size recorded in the header of the zstd file at `path`.
+async fn content_size(path: &Path) -> Option<u64> {
+    use tokio::io::AsyncReadExt;
+
+    let mut header = [0u8; ZSTD_FRAME_HEADER_MAX];
+    let mut file = tokio::fs::File::open(path).await.ok()?;
+    let mut len = 0;
+    while len < header.len() {
+        match file.read(&mut header[len..]).await.ok()? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    zstd::zstd_safe::get_frame_content_size(&header[..len])
+        .ok()
+        .flatten()
+}
+
+fn is_safe_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !name.ends_with(TMP_SUFFIX)
+        && !name.ends_with(ZSTD_SUFFIX)
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(TMP_SUFFIX);
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_evicts_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ReadCache::open(dir.path().to_path_buf(), 10, false)
+            .await
+            .unwrap();
+        let data = ResticFileType::Data;
+
+        cache.put(data, "aa", Bytes::from_static(b"1111")).await;
+        cache.put(data, "bb", Bytes::from_static(b"2222")).await;
+        cache.index.run_pending_tasks().await;
+        assert!(cache.get(data, "aa").await.is_some());
+        cache.index.run_pending_tasks().await;
+        cache.put(data, "cc", Bytes::from_static(b"3333")).await;
+        cache.index.run_pending_tasks().await;
+
+        assert!(cache.get(data, "aa").await.is_some());
+        assert!(cache.get(data, "bb").await.is_none());
+        assert!(!dir.path().join("data/bb").exists());
+        assert_eq!(cache.get(data, "cc").await.unwrap(), "3333");
+
+        let reopened = ReadCache::open(dir.path().to_path_buf(), 10, false)
+            .await
+            .unwrap();
+        assert_eq!(reopened.get(data, "aa").await.unwrap(), "1111");
+    }
+
+    #[tokio::test]
+    async fn test_compresses_metadata_when_smaller() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ReadCache::open(dir.path().to_path_buf(), 1 << 20, true)
+            .await
+            .unwrap();
+        let json = Bytes::from(vec![b'a'; 10_000]);
+
+        cache.put(ResticFileType::Index, "ii", json.clone()).await;
+        assert!(dir.path().join("index/ii.zst").exists());
+        assert!(!dir.path().join("index/ii").exists());
+        assert_eq!(cache.get(ResticFileType::Index, "ii").await.unwrap(), json);
+
+        // Packs are never compressed, and incompressible metadata stays raw.
+        cache.put(ResticFileType::Data, "dd", json.clone()).await;
+        assert!(dir.path().join("data/dd").exists());
+        cache
+            .put(ResticFileType::Snapshots, "ss", Bytes::from_static(b"x"))
+            .await;
+        assert!(dir.path().join("snapshots/ss").exists());
+
+        // The size is known without reading the entry, after a restart too.
+        // A compressed entry without it is dropped.
+        std::fs::write(
+            dir.path().join("index/old.zst"),
+            zstd::encode_all(&json[..], ZSTD_LEVEL).unwrap(),
+        )
+        .unwrap();
+        let reopened = ReadCache::open(dir.path().to_path_buf(), 1 << 20, true)
+            .await
+            .unwrap();
+        assert_eq!(
+            reopened.size(ResticFileType::Index, "ii").await,
+            Some(10_000)
+        );
+        assert_eq!(
+            reopened.size(ResticFileType::Snapshots, "ss").await,
+            Some(1)
+        );
+        assert_eq!(reopened.size(ResticFileType::Index, "old").await, None);
+        assert!(!dir.path().join("index/old.zst").exists());
+        assert_eq!(
+            reopened.get(ResticFileType::Index, "ii").await.unwrap(),
+            json
+        );
+    }
+}
@@ -0,0 +1,75 @@
+//! Restic requests being served, so background jobs that lock the cache DB
+//! for a while can wait for a quiet moment.
+
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub struct Activity {
+    /// Requests in flight, and when the last one finished.
+    state: Mutex<(usize, Instant)>,
+}
+
+/// A request counted as in flight; finished on drop.
+pub struct ActivityGuard {
+    activity: Arc<Activity>,
+}
+
+impl Activity {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new((0, Instant::now())),
+        })
+    }
+
+    pub fn begin(self: &Arc<Self>) -> ActivityGuard {
+        self.state.lock().0 += 1;
+        ActivityGuard {
+            activity: self.clone(),
+        }
+    }
+
+    /// Whether no request is in flight and none finished within `quiet`.
+    pub fn is_idle(&self, quiet: Duration) -> bool {
+        let (in_flight, last) = *self.state.lock();
+        in_flight == 0 && last.elapsed() >= quiet
+    }
+
+    /// Wait until [`Activity::is_idle`], checking every `quiet`.
+    pub async fn wait_idle(&self, quiet: Duration) {
+        while !self.is_idle(quiet) {
+            tokio::time::sleep(quiet).await;
+        }
+    }
+}
+
+impl Drop for ActivityGuard {
+    fn drop(&mut self) {
+        let mut state = self.activity.state.lock();
+        state.0 -= 1;
+        state.1 = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_idle_after_quiet_period() {
+        let quiet = Duration::from_millis(50);
+        let activity = Activity::new();
+        let first = activity.begin();
+        let second = activity.begin();
+        drop(first);
+        assert!(!activity.is_idle(Duration::ZERO));
+
+        drop(second);
+        assert!(activity.is_idle(Duration::ZERO));
+        assert!(!activity.is_idle(quiet));
+        tokio::time::timeout(quiet * 4, activity.wait_idle(quiet))
+            .await
+            .unwrap();
+        assert!(activity.is_idle(quiet));
+    }
+}
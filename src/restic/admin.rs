@@ -0,0 +1,441 @@
+//! Operator endpoints under `/admin`, guarded by `--admin-token`.
+
+use axum::{
+    Json, Router,
+    extract::{Query, Request, State},
+    http::{HeaderMap, StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::handler::AppState;
+use super::pagination::{PageParams, paginate};
+use crate::error::{AppError, Result};
+use crate::events::Event;
+
+/// How long the access token must stay valid after the pre-backup hook, so a
+/// backup does not have to refresh it midway.
+const PRE_BACKUP_TOKEN_VALIDITY: Duration = Duration::from_secs(60 * 60);
+
+/// Routes mounted at `/admin`; every request must carry the admin bearer token.
+pub fn router(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/spool/flush", post(flush_spool))
+        .route("/info", get(info))
+        .route("/events", get(list_events))
+        .route("/tokens", post(replace_tokens))
+        .route("/token", get(token_status))
+        .route("/token/refresh", post(refresh_token))
+        .route("/cache/refresh", post(refresh_cache_dir))
+        .route(
+            "/cache/rebuild",
+            get(cache_rebuild_status).post(rebuild_cache),
+        )
+        .route("/cache/dirs", get(list_cached_dirs))
+        .route("/cache/files", get(list_cached_files))
+        .route("/hooks/pre-backup", post(pre_backup))
+        .route("/hooks/post-backup", post(post_backup))
+        .route("/deleted", get(list_deleted))
+        .route("/undelete", post(undelete))
+        .route_layer(middleware::from_fn_with_state(state, require_admin_token))
+}
+
+/// Read-only account routes mounted at `/api`, behind the same token.
+pub fn api_router(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/quota", get(quota))
+        .route_layer(middleware::from_fn_with_state(state, require_admin_token))
+}
+
+async fn require_admin_token(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Result<Response> {
+    if state.admin_token.is_none() {
+        return Err(AppError::NotFound("admin API disabled".to_string()));
+    }
+    if is_admin(&state, req.headers()) {
+        Ok(next.run(req).await)
+    } else {
+        Err(AppError::Unauthorized("invalid admin token".to_string()))
+    }
+}
+
+/// Whether `headers` carry the admin bearer token. Always false without one.
+pub(super) fn is_admin(state: &AppState, headers: &HeaderMap) -> bool {
+    let Some(expected) = state.admin_token.as_deref() else {
+        return false;
+    };
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|token| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+}
+
+pub(super) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Summary of the deployment logged at startup.
+async fn info(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.info.as_ref().clone())
+}
+
+/// Block until every spooled upload has reached 115.
+async fn flush_spool(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse> {
+    let spool = state
+        .spool
+        .as_ref()
+        .ok_or_else(|| AppError::BadRequest("spool is not enabled".to_string()))?;
+    let pending = spool.pending().len();
+    tracing::info!("Admin: flushing spool ({} pending uploads)", pending);
+    spool.flush().await?;
+    Ok(Json(json!({ "flushed": pending })))
+}
+
+#[derive(Deserialize)]
+struct RefreshParams {
+    /// Absolute 115 path of a directory inside the repository.
+    path: String,
+}
+
+/// Re-list one directory from 115, e.g. after fixing it in the web UI.
+async fn refresh_cache_dir(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<RefreshParams>,
+) -> Result<impl IntoResponse> {
+    tracing::info!("Admin: refreshing cached directory {}", params.path);
+    let entries = state.client()?.refresh_dir(&params.path).await?;
+    Ok(Json(json!({ "path": params.path, "entries": entries })))
+}
+
+/// Progress of the last cache rebuild started from `/admin/cache/rebuild`.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct RebuildStatus {
+    running: bool,
+    path: Option<String>,
+    /// Unix times in seconds.
+    started_at: Option<i64>,
+    finished_at: Option<i64>,
+    dirs_done: usize,
+    dirs_queued: usize,
+    entries: usize,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RebuildParams {
+    /// Directory to rebuild; the whole repository when unset.
+    path: Option<String>,
+}
+
+/// Start re-listing the repository (or one subtree) in the background.
+async fn rebuild_cache(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<RebuildParams>,
+) -> Result<impl IntoResponse> {
+    let client = state.client()?.clone();
+    let path = params.path.unwrap_or_else(|| client.repo_id().to_string());
+    let status = {
+        let mut status = state.cache_rebuild.lock();
+        if status.running {
+            return Err(AppError::Conflict(format!(
+                "a rebuild of {} is already running",
+                status.path.as_deref().unwrap_or_default()
+            )));
+        }
+        *status = RebuildStatus {
+            running: true,
+            path: Some(path.clone()),
+            started_at: Some(chrono::Utc::now().timestamp()),
+            ..Default::default()
+        };
+        status.clone()
+    };
+    tracing::info!("Admin: rebuilding cache of {}", path);
+
+    let shared = state.cache_rebuild.clone();
+    tokio::spawn(async move {
+        let result = client
+            .rebuild_tree(&path, |entries, queued| {
+                let mut status = shared.lock();
+                status.dirs_done += 1;
+                status.dirs_queued = queued;
+                status.entries += entries;
+            })
+            .await;
+        let mut status = shared.lock();
+        status.running = false;
+        status.finished_at = Some(chrono::Utc::now().timestamp());
+        if let Err(e) = result {
+            tracing::warn!("Cache rebuild of {} failed: {}", path, e);
+            status.error = Some(e.to_string());
+        }
+    });
+    Ok((StatusCode::ACCEPTED, Json(status)))
+}
+
+/// Progress of the current or last cache rebuild.
+async fn cache_rebuild_status(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.cache_rebuild.lock().clone())
+}
+
+/// Cached directories of the repository, ordered by file id.
+async fn list_cached_dirs(
+    State(state): State<Arc<AppState>>,
+    Query(page): Query<PageParams>,
+) -> Result<impl IntoResponse> {
+    let dirs = state.client()?.cached_dirs().await?;
+    Ok(Json(paginate(by_file_id(dirs, |d| &d.file_id), &page)?))
+}
+
+#[derive(Deserialize)]
+struct CachedFilesParams {
+    /// File id of the directory, or its absolute 115 path.
+    parent: String,
+    /// Only return entries with this name.
+    name: Option<String>,
+}
+
+/// Cached entries of one directory, ordered by file id.
+async fn list_cached_files(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<CachedFilesParams>,
+    Query(page): Query<PageParams>,
+) -> Result<impl IntoResponse> {
+    let parent_id = if params.parent.starts_with('/') {
+        state
+            .client()?
+            .find_path_id(&params.parent)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("{} is not cached", params.parent)))?
+    } else {
+        params.parent
+    };
+    let mut files = state.client()?.cached_children(&parent_id).await?;
+    if let Some(name) = &params.name {
+        files.retain(|f| &f.name == name);
+    }
+    Ok(Json(paginate(by_file_id(files, |f| &f.file_id), &page)?))
+}
+
+/// Key rows by their numeric 115 file id for pagination.
+fn by_file_id<T>(rows: Vec<T>, file_id: impl Fn(&T) -> &str) -> Vec<(u64, T)> {
+    let mut keyed: Vec<_> = rows
+        .into_iter()
+        .filter_map(|row| Some((file_id(&row).parse().ok()?, row)))
+        .collect();
+    keyed.sort_by_key(|(id, _)| *id);
+    keyed
+}
+
+#[derive(Deserialize)]
+struct NewTokens {
+    access_token: String,
+    refresh_token: String,
+}
+
+/// Validate pasted tokens against 115 and switch to them without a restart.
+async fn replace_tokens(
+    State(state): State<Arc<AppState>>,
+    Json(tokens): Json<NewTokens>,
+) -> Result<impl IntoResponse> {
+    let access_token = tokens.access_token.trim();
+    let refresh_token = tokens.refresh_token.trim();
+    if access_token.is_empty() || refresh_token.is_empty() {
+        return Err(AppError::BadRequest(
+            "access_token and refresh_token are required".to_string(),
+        ));
+    }
+    state
+        .client()?
+        .replace_tokens(access_token, refresh_token)
+        .await?;
+    Ok(Json(json!({ "replaced": true })))
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenStatusParams {
+    /// Also check the token with a live 115 call.
+    #[serde(default)]
+    check: bool,
+}
+
+/// Expiry and age of the access token, optionally verified against 115.
+async fn token_status(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<TokenStatusParams>,
+) -> Result<impl IntoResponse> {
+    let mut report = serde_json::to_value(state.client()?.token_status())?;
+    if params.check {
+        let check = state.client()?.verify_current_token().await;
+        report["valid"] = json!(check.is_ok());
+        if let Err(e) = check {
+            report["error"] = json!(e.to_string());
+        }
+    }
+    Ok(Json(report))
+}
+
+/// Refresh the access token now, e.g. after 115 invalidated it.
+async fn refresh_token(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse> {
+    Ok(Json(state.client()?.force_token_refresh().await?))
+}
+
+/// Recently emitted repository events, oldest first.
+async fn list_events(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<PageParams>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(paginate(state.events.recent_sequenced(), &params)?))
+}
+
+/// Storage quota and usage of the 115 account.
+async fn quota(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse> {
+    let space = state.client()?.space_info().await?;
+    Ok(Json(json!({
+        "total": space.total,
+        "used": space.used,
+        "free": space.free,
+        "used_percent": space.used_percent(),
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct PreBackupParams {
+    /// Fail with 507 if the account has less free space than this.
+    min_free_bytes: Option<u64>,
+}
+
+/// Prepare for a scheduled backup: refresh the token, warm the metadata
+/// cache and check the quota.
+async fn pre_backup(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<PreBackupParams>,
+) -> Result<Response> {
+    let client = state.client()?;
+    let token_refreshed = client
+        .ensure_token_valid_for(PRE_BACKUP_TOKEN_VALIDITY)
+        .await?;
+    client.warm_cache(false).await?;
+    let space = client.space_info().await?;
+    let enough_space = params.min_free_bytes.is_none_or(|min| space.free >= min);
+    tracing::info!(
+        "Admin: pre-backup hook (token_refreshed={}, free={} bytes, enough_space={})",
+        token_refreshed,
+        space.free,
+        enough_space
+    );
+
+    let report = json!({
+        "token_refreshed": token_refreshed,
+        "space": space,
+        "enough_space": enough_space,
+    });
+    if !enough_space {
+        return Ok((StatusCode::INSUFFICIENT_STORAGE, Json(report)).into_response());
+    }
+    state.events.emit(Event::BackupStarted {
+        repo: client.repo_id().to_string(),
+        timestamp: chrono::Utc::now().timestamp(),
+    });
+    Ok(Json(report).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+struct PostBackupParams {
+    /// Also purge this repository's entries from the recycle bin.
+    #[serde(default)]
+    purge_trash: bool,
+}
+
+/// Finalize a scheduled backup: drain the spool, report the snapshots written
+/// since the pre-backup hook and optionally purge the recycle bin.
+async fn post_backup(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<PostBackupParams>,
+) -> Result<impl IntoResponse> {
+    let flushed = match &state.spool {
+        Some(spool) => {
+            let pending = spool.pending().len();
+            spool.flush().await?;
+            pending
+        }
+        None => 0,
+    };
+    let repo = state.client()?.repo_id().to_string();
+    let snapshots = state.events.snapshots_since_backup_started(&repo);
+    let purged = if params.purge_trash {
+        Some(state.client()?.purge_recycle_bin(false).await?)
+    } else {
+        None
+    };
+    tracing::info!(
+        "Admin: post-backup hook (flushed={}, snapshots={}, purged={:?})",
+        flushed,
+        snapshots.len(),
+        purged
+    );
+
+    let report = json!({
+        "flushed": flushed,
+        "snapshots": snapshots,
+        "purged": purged,
+    });
+    state.events.emit(Event::BackupFinished {
+        repo,
+        snapshots,
+        purged,
+        timestamp: chrono::Utc::now().timestamp(),
+    });
+    Ok(Json(report))
+}
+
+#[derive(Debug, Deserialize)]
+struct DeletedParams {
+    /// Only deletes at or after this Unix time in seconds; the whole journal
+    /// when unset.
+    since: Option<i64>,
+    /// Only objects with this name.
+    name: Option<String>,
+}
+
+impl DeletedParams {
+    fn since(&self) -> chrono::DateTime<chrono::Utc> {
+        self.since
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+            .unwrap_or(chrono::DateTime::UNIX_EPOCH)
+    }
+}
+
+/// Objects in the delete journal, by file id like the cache listings.
+async fn list_deleted(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<DeletedParams>,
+    Query(page): Query<PageParams>,
+) -> Result<impl IntoResponse> {
+    let mut deleted = state.client()?.deleted_objects(params.since()).await?;
+    if let Some(name) = &params.name {
+        deleted.retain(|d| &d.name == name);
+    }
+    let deleted = by_file_id(deleted, |d| &d.file_id);
+    Ok(Json(paginate(deleted, &page)?))
+}
+
+/// Restore journaled deletes from the 115 recycle bin.
+async fn undelete(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<DeletedParams>,
+) -> Result<impl IntoResponse> {
+    let names: Vec<String> = params.name.iter().cloned().collect();
+    let restored = state.client()?.undelete(params.since(), &names).await?;
+    tracing::info!("Admin: restored {} deleted objects", restored.len());
+    Ok(Json(json!({ "restored": restored })))
+}
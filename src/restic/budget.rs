@@ -0,0 +1,127 @@
+//! Global budget for request bodies and downloads buffered in memory.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub struct InflightBudget {
+    limit: u64,
+    used: AtomicU64,
+}
+
+/// Bytes held against the budget; released on drop.
+pub struct InflightGuard {
+    budget: Arc<InflightBudget>,
+    bytes: u64,
+}
+
+impl InflightBudget {
+    pub fn new(limit: u64) -> Arc<Self> {
+        Arc::new(Self {
+            limit,
+            used: AtomicU64::new(0),
+        })
+    }
+
+    /// Reserve `bytes`, or return `None` if that would exceed the limit.
+    ///
+    /// A single request larger than the whole limit is still admitted when
+    /// nothing else is in flight, so oversized objects stay reachable.
+    pub fn try_acquire(self: &Arc<Self>, bytes: u64) -> Option<InflightGuard> {
+        let mut current = self.used.load(Ordering::Relaxed);
+        loop {
+            if current > 0 && current.saturating_add(bytes) > self.limit {
+                return None;
+            }
+            match self.used.compare_exchange_weak(
+                current,
+                current + bytes,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    return Some(InflightGuard {
+                        budget: self.clone(),
+                        bytes,
+                    });
+                }
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+}
+
+impl InflightGuard {
+    /// Add `bytes` to this reservation, or return false if that would exceed
+    /// the limit. Like [`InflightBudget::try_acquire`], a reservation that is
+    /// all that is in flight may grow past the limit.
+    pub fn try_grow(&mut self, bytes: u64) -> bool {
+        let used = &self.budget.used;
+        let mut current = used.load(Ordering::Relaxed);
+        loop {
+            if current > self.bytes && current.saturating_add(bytes) > self.budget.limit {
+                return false;
+            }
+            match used.compare_exchange_weak(
+                current,
+                current + bytes,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    self.bytes += bytes;
+                    return true;
+                }
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        self.budget.used.fetch_sub(self.bytes, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_admits_until_limit() {
+        let budget = InflightBudget::new(100);
+        let a = budget.try_acquire(60).unwrap();
+        assert!(budget.try_acquire(50).is_none());
+        let b = budget.try_acquire(40).unwrap();
+        assert_eq!(budget.used(), 100);
+        drop(a);
+        drop(b);
+        assert_eq!(budget.used(), 0);
+        // Oversized requests pass when the budget is idle.
+        let big = budget.try_acquire(500).unwrap();
+        assert!(budget.try_acquire(1).is_none());
+        drop(big);
+    }
+
+    #[test]
+    fn test_guard_grows_until_limit() {
+        let budget = InflightBudget::new(100);
+        let mut growing = budget.try_acquire(0).unwrap();
+        assert!(growing.try_grow(150), "alone, it may pass the limit");
+        drop(growing);
+
+        let other = budget.try_acquire(60).unwrap();
+        let mut growing = budget.try_acquire(0).unwrap();
+        assert!(growing.try_grow(40));
+        assert!(!growing.try_grow(1));
+        assert_eq!(budget.used(), 100);
+        drop(other);
+        assert!(growing.try_grow(50));
+        drop(growing);
+        assert_eq!(budget.used(), 0);
+    }
+}
@@ -0,0 +1,246 @@
+//! HTTP Basic credentials for the restic routes (`--credentials-file`).
+//!
+//! The file maps users to the repositories they may use, one
+//! `user:password[:prefix]` per line (`#` starts a comment). The password is
+//! either plain text without `:` or `sha256:` followed by the hex SHA-256 of
+//! it.
+//!
+//! A user with a prefix may only use repositories at or below that 115 path
+//! (like rest-server's `--private-repos`), so several servers sharing one
+//! file let each machine touch only its own sub-repository; a user without a
+//! prefix may use any.
+
+use axum::http::{HeaderMap, header};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+use super::admin::constant_time_eq;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Secret {
+    Plain(String),
+    /// SHA-256 of the password.
+    Sha256([u8; 32]),
+}
+
+impl Secret {
+    fn matches(&self, password: &str) -> bool {
+        match self {
+            Secret::Plain(expected) => constant_time_eq(password.as_bytes(), expected.as_bytes()),
+            Secret::Sha256(expected) => constant_time_eq(&Sha256::digest(password), expected),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    secret: Secret,
+    /// 115 path the user is confined to, without a trailing `/`.
+    prefix: Option<String>,
+}
+
+/// Outcome of checking a request's credentials.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Access {
+    Granted,
+    /// No or wrong credentials.
+    Unauthenticated,
+    /// Valid credentials for a user confined to other repositories.
+    Denied {
+        user: String,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct Credentials {
+    users: HashMap<String, Entry>,
+}
+
+impl Credentials {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut users = HashMap::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((user, rest)) = line.split_once(':') else {
+                return Err(format!("line {}: expected user:password[:prefix]", n + 1));
+            };
+            let (hashed, rest) = match rest.strip_prefix("sha256:") {
+                Some(rest) => (true, rest),
+                None => (false, rest),
+            };
+            let (password, prefix) = match rest.split_once(':') {
+                Some((password, prefix)) => (password, Some(prefix)),
+                None => (rest, None),
+            };
+            let secret = if hashed {
+                Secret::Sha256(
+                    hex::decode(password)
+                        .ok()
+                        .and_then(|d| d.try_into().ok())
+                        .ok_or_else(|| format!("line {}: invalid SHA-256 for {user}", n + 1))?,
+                )
+            } else {
+                Secret::Plain(password.to_string())
+            };
+            let prefix = prefix
+                .map(|p| format!("/{}", p.trim_matches('/')))
+                .filter(|p| p != "/");
+            if user.is_empty()
+                || users
+                    .insert(user.to_string(), Entry { secret, prefix })
+                    .is_some()
+            {
+                return Err(format!("line {}: empty or duplicate user {user:?}", n + 1));
+            }
+        }
+        Ok(Self { users })
+    }
+
+    /// Credentials of a single user allowed to use any repository.
+    pub fn single(user: &str, password: &str) -> Self {
+        let entry = Entry {
+            secret: Secret::Plain(password.to_string()),
+            prefix: None,
+        };
+        Self {
+            users: HashMap::from([(user.to_string(), entry)]),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    /// Users allowed to use the repository at `repo_path`.
+    pub fn users_for(&self, repo_path: &str) -> Vec<&str> {
+        let mut users: Vec<&str> = self
+            .users
+            .iter()
+            .filter(|(_, entry)| covers(entry.prefix.as_deref(), repo_path))
+            .map(|(user, _)| user.as_str())
+            .collect();
+        users.sort_unstable();
+        users
+    }
+
+    /// Check the Basic credentials in `headers` for the repository at
+    /// `repo_path`.
+    pub fn check(&self, headers: &HeaderMap, repo_path: &str) -> Access {
+        let Some((user, password)) = basic_auth(headers) else {
+            return Access::Unauthenticated;
+        };
+        match self.users.get(&user) {
+            Some(entry) if entry.secret.matches(&password) => {
+                if covers(entry.prefix.as_deref(), repo_path) {
+                    Access::Granted
+                } else {
+                    Access::Denied { user }
+                }
+            }
+            _ => Access::Unauthenticated,
+        }
+    }
+}
+
+/// Whether a user confined to `prefix` may use the repository at `repo_path`.
+fn covers(prefix: Option<&str>, repo_path: &str) -> bool {
+    let Some(prefix) = prefix else {
+        return true;
+    };
+    let repo_path = repo_path.trim_end_matches('/');
+    repo_path
+        .strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// User and password of an `Authorization: Basic` header.
+fn basic_auth(headers: &HeaderMap) -> Option<(String, String)> {
+    let encoded = headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn basic(user: &str, password: &str) -> HeaderMap {
+        let token = base64::engine::general_purpose::STANDARD.encode(format!("{user}:{password}"));
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&format!("Basic {token}")).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn test_credentials() {
+        let hash = hex::encode(Sha256::digest("s3cret"));
+        let creds = Credentials::parse(&format!(
+            "# backups\nadmin:root\n\nalice:sha256:{hash}:/backups/alice/\nbob:pw:backups/bob\n"
+        ))
+        .unwrap();
+        assert_eq!(creds.len(), 3);
+        assert_eq!(creds.users_for("/backups/alice"), ["admin", "alice"]);
+
+        let repo = "/backups/alice";
+        assert_eq!(
+            creds.check(&basic("alice", "s3cret"), repo),
+            Access::Granted
+        );
+        assert_eq!(creds.check(&basic("admin", "root"), repo), Access::Granted);
+        assert_eq!(
+            creds.check(&basic("bob", "pw"), repo),
+            Access::Denied {
+                user: "bob".to_string()
+            }
+        );
+        assert_eq!(
+            creds.check(&basic("alice", "wrong"), repo),
+            Access::Unauthenticated
+        );
+        assert_eq!(
+            creds.check(&HeaderMap::new(), repo),
+            Access::Unauthenticated
+        );
+        // A prefix is a path, not a string prefix.
+        assert_eq!(
+            creds.check(&basic("alice", "s3cret"), "/backups/alice2"),
+            Access::Denied {
+                user: "alice".to_string()
+            }
+        );
+
+        let single = Credentials::single("restic-115", "pw");
+        assert_eq!(
+            single.check(&basic("restic-115", "pw"), "/any/repo-self-test"),
+            Access::Granted
+        );
+        assert_eq!(
+            single.check(&basic("admin", "root"), repo),
+            Access::Unauthenticated
+        );
+
+        assert!(Credentials::parse("alice").is_err());
+        assert!(Credentials::parse("alice:a\nalice:b").is_err());
+        assert!(Credentials::parse("alice:sha256:abc").is_err());
+    }
+}
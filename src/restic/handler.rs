@@ -3,22 +3,117 @@
 use axum::{
     Router,
     body::{Body, Bytes},
-    extract::{Path, Query, State},
+    extract::{Path, Query, Request, State},
     http::{HeaderMap, StatusCode, header},
+    middleware::{self, Next},
     response::{IntoResponse, Response},
     routing::{get, head, post},
 };
+use futures::{StreamExt, TryStreamExt};
 use serde::Deserialize;
 use std::sync::Arc;
 
+use super::activity::Activity;
+use super::admin;
+use super::budget::{InflightBudget, InflightGuard};
+use super::credentials::{Access, Credentials};
+use super::health::{self, Health};
+use super::landing;
+use super::locks::LockReaper;
+use super::prefetch::Prefetcher;
+use super::recent::RecentWrites;
 use super::types::FileEntryV2;
+use crate::backend::Backend;
+use crate::config::DuplicatePolicy;
 use crate::error::{AppError, Result};
-use crate::open115::{Open115Client, ResticFileType};
+use crate::events::{Event, EventBus};
+use crate::open115::{Open115Client, ResticFileType, budget};
+use crate::read_cache::ReadCache;
+use crate::spool::Spool;
+use crate::telemetry;
 
 /// Application state shared across handlers.
 #[derive(Clone)]
 pub struct AppState {
-    pub client: Open115Client,
+    /// Storage of the restic objects.
+    pub backend: Arc<dyn Backend>,
+    /// 115 client behind the admin API and health checks; without one (a
+    /// router embedded over another [`Backend`]) those routes answer 404.
+    pub client: Option<Open115Client>,
+    /// Write-behind spool for data packs, when `--spool-dir` is set.
+    pub spool: Option<Spool>,
+    /// On-disk cache of downloaded objects, when `--read-cache-dir` is set.
+    pub read_cache: Option<ReadCache>,
+    /// Repository events for notifiers and `/admin/events`.
+    pub events: Arc<EventBus>,
+    /// Whether `DELETE /` may remove the repository (`--allow-repo-delete`).
+    pub allow_repo_delete: bool,
+    /// Set once the first write has made sure the repository exists, when
+    /// `--auto-create-repo` is set.
+    pub auto_create_repo: Option<Arc<tokio::sync::OnceCell<()>>>,
+    /// Largest accepted upload body, from `--max-blob-size`.
+    pub max_blob_size: u64,
+    /// Download-URL prefetching, when `--prefetch-window` is set.
+    pub prefetch: Option<Arc<Prefetcher>>,
+    /// Memory budget for buffered bodies, when `--max-inflight-bytes` is set.
+    pub inflight: Option<Arc<InflightBudget>>,
+    /// Bearer token for `/admin`; admin routes are not mounted without it.
+    pub admin_token: Option<String>,
+    /// Whether to send object SHA-1s as trailers (`--checksum-trailer`).
+    pub checksum_trailer: bool,
+    /// Progress of the runtime cache rebuild (`/admin/cache/rebuild`).
+    pub cache_rebuild: Arc<parking_lot::Mutex<admin::RebuildStatus>>,
+    /// Startup summary of the deployment, served at `/admin/info`.
+    pub info: Arc<serde_json::Value>,
+    /// Stale lock expiry, when `--lock-ttl` is set.
+    pub locks: Option<Arc<LockReaper>>,
+    /// Recently uploaded metadata objects, unless `--write-grace` is 0.
+    pub recent: Option<Arc<RecentWrites>>,
+    /// Cached 115 probe behind `/healthz`, set along with `client`.
+    pub health: Option<Arc<Health>>,
+    /// 115 API calls allowed per restic request (`--request-call-budget`).
+    pub call_budget: Option<u32>,
+    /// Users allowed to make restic requests, when `--credentials-file` is set.
+    pub credentials: Option<Arc<Credentials>>,
+    /// Restic requests in flight, watched by the periodic DB maintenance.
+    pub activity: Arc<Activity>,
+}
+
+/// Trailer carrying the SHA-1 of a whole-object download.
+const SHA1_TRAILER: &str = "x-checksum-sha1";
+
+/// Seconds restic is asked to wait when the in-flight budget is exhausted.
+const INFLIGHT_RETRY_AFTER_SECS: u64 = 5;
+
+impl AppState {
+    /// The 115 client, for routes that need one.
+    pub(super) fn client(&self) -> Result<&Open115Client> {
+        self.client
+            .as_ref()
+            .ok_or_else(|| AppError::NotFound("no 115 client configured".to_string()))
+    }
+
+    /// Hold `bytes` against the in-flight budget for the guard's lifetime.
+    fn reserve(&self, bytes: u64) -> Result<Option<InflightGuard>> {
+        let Some(budget) = &self.inflight else {
+            return Ok(None);
+        };
+        match budget.try_acquire(bytes) {
+            Some(guard) => Ok(Some(guard)),
+            None => Err(budget_exhausted(budget, bytes)),
+        }
+    }
+}
+
+fn budget_exhausted(budget: &InflightBudget, bytes: u64) -> AppError {
+    AppError::Overloaded {
+        message: format!(
+            "in-flight budget exhausted ({} bytes in use, {} requested)",
+            budget.used(),
+            bytes
+        ),
+        retry_after_secs: INFLIGHT_RETRY_AFTER_SECS,
+    }
 }
 
 /// Query parameters for repository creation.
@@ -30,12 +125,14 @@ pub struct CreateQuery {
 
 /// Restic REST API v2 content type.
 const V2_CONTENT_TYPE: &str = "application/vnd.x.restic.rest.v2";
+/// Restic REST API v1 content type, served to clients that don't ask for v2.
+const V1_CONTENT_TYPE: &str = "application/vnd.x.restic.rest.v1";
 
 /// Create the Axum router with all routes.
-pub fn create_router(client: Open115Client) -> Router {
-    let state = Arc::new(AppState { client });
+pub fn create_router(state: AppState) -> Router {
+    let state = Arc::new(state);
 
-    Router::new()
+    let mut router = Router::new()
         .route("/", post(create_repository).delete(delete_repository))
         .route(
             "/config",
@@ -49,7 +146,100 @@ pub fn create_router(client: Open115Client) -> Router {
                 .post(post_file)
                 .delete(delete_file),
         )
-        .with_state(state)
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            limit_upstream_calls,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            track_upstream,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            track_activity,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_credentials,
+        ))
+        // Scraped by monitoring, which has no restic credentials.
+        .route("/metrics", get(get_metrics))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        // Merged into the `/` route above, but outside the upstream tracking.
+        .route("/", get(landing::index))
+        .fallback(landing::not_found);
+    if state.admin_token.is_some() {
+        router = router
+            .nest("/admin", admin::router(state.clone()))
+            .nest("/api", admin::api_router(state.clone()));
+    }
+    router.with_state(state)
+}
+
+/// Feed the outcome of each restic request to the upstream failure streak
+/// behind [`Event::UpstreamFailing`]. 502 and 504 are what 115 errors and
+/// timeouts turn into; other 5xx are local (I/O, overload).
+async fn track_upstream(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let response = next.run(req).await;
+    let status = response.status();
+    if matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::GATEWAY_TIMEOUT
+    ) {
+        state
+            .events
+            .upstream_failed(state.backend.repo_id(), status.as_u16());
+    } else if !status.is_server_error() {
+        state.events.upstream_succeeded(state.backend.repo_id());
+    }
+    response
+}
+
+/// Count a restic request as in flight until its response body has been
+/// sent.
+async fn track_activity(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let active = state.activity.begin();
+    next.run(req).await.map(|body| {
+        Body::new(HeldBody {
+            body,
+            _guard: active,
+        })
+    })
+}
+
+/// Reject restic requests without the credentials of a user allowed to use
+/// the repository; see [`super::credentials`].
+async fn require_credentials(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Result<Response> {
+    let Some(credentials) = &state.credentials else {
+        return Ok(next.run(req).await);
+    };
+    match credentials.check(req.headers(), state.backend.repo_path()) {
+        Access::Granted => Ok(next.run(req).await),
+        Access::Unauthenticated => Err(AppError::Unauthorized(
+            "missing or invalid credentials".to_string(),
+        )),
+        Access::Denied { user } => Err(AppError::Forbidden(format!(
+            "{user} may not use this repository"
+        ))),
+    }
+}
+
+/// Charge the 115 API calls made while handling a restic request to its
+/// budget; see [`crate::open115::budget`].
+async fn limit_upstream_calls(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    match state.call_budget {
+        Some(limit) => budget::with_call_budget(limit, next.run(req)).await,
+        None => next.run(req).await,
+    }
 }
 
 // ============================================================================
@@ -67,12 +257,66 @@ async fn create_repository(
     }
 
     tracing::info!("Creating repository");
-    state.client.init_repository().await?;
+    init_repository(&state).await?;
     Ok(StatusCode::OK)
 }
 
-async fn delete_repository() -> impl IntoResponse {
-    StatusCode::NOT_IMPLEMENTED
+async fn init_repository(state: &AppState) -> Result<()> {
+    state.backend.init().await?;
+    state.events.emit(Event::RepoInitialized {
+        repo: state.backend.repo_id().to_string(),
+        timestamp: chrono::Utc::now().timestamp(),
+    });
+    Ok(())
+}
+
+/// With `--auto-create-repo`, create the repository before the first write
+/// of this process unless it already has a config. Later writes skip the
+/// check; concurrent first writes wait for the one doing it.
+async fn auto_create_repository(state: &AppState) -> Result<()> {
+    let Some(created) = &state.auto_create_repo else {
+        return Ok(());
+    };
+    created
+        .get_or_try_init(|| async {
+            if state
+                .backend
+                .stat(ResticFileType::Config, "config")
+                .await?
+                .is_none()
+            {
+                tracing::info!("Creating repository on its first write");
+                init_repository(state).await?;
+            }
+            Ok::<_, AppError>(())
+        })
+        .await?;
+    Ok(())
+}
+
+async fn delete_repository(State(state): State<Arc<AppState>>) -> Result<StatusCode> {
+    if !state.allow_repo_delete {
+        return Ok(StatusCode::NOT_IMPLEMENTED);
+    }
+    if let Some(spool) = &state.spool
+        && !spool.pending().is_empty()
+    {
+        return Err(AppError::Conflict(
+            "spooled uploads pending; flush the spool before deleting the repository".to_string(),
+        ));
+    }
+
+    if !state.backend.destroy().await? {
+        tracing::info!("Repository delete requested, but the repository does not exist");
+    }
+    Ok(StatusCode::OK)
+}
+
+async fn get_metrics() -> Response {
+    match telemetry::render() {
+        Some(body) => ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
 }
 
 // ============================================================================
@@ -80,15 +324,8 @@ async fn delete_repository() -> impl IntoResponse {
 // ============================================================================
 
 async fn head_config(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse> {
-    // Read-only: do NOT create directories on HEAD/GET.
-    let dir_id = state
-        .client
-        .find_type_dir_id(ResticFileType::Config)
-        .await?
-        .ok_or_else(|| AppError::NotFound("config".to_string()))?;
-
-    // After upload, search indexing can lag. Repo root is small; allow listing fallback.
-    match state.client.find_file(&dir_id, "config").await? {
+    let _timer = telemetry::time_request(ResticFileType::Config, "head");
+    match state.backend.stat(ResticFileType::Config, "config").await? {
         Some(file) => {
             let mut headers = HeaderMap::new();
             headers.insert(
@@ -102,20 +339,16 @@ async fn head_config(State(state): State<Arc<AppState>>) -> Result<impl IntoResp
 }
 
 async fn get_config(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse> {
-    // Read-only: do NOT create directories on HEAD/GET.
-    let dir_id = state
-        .client
-        .find_type_dir_id(ResticFileType::Config)
-        .await?
-        .ok_or_else(|| AppError::NotFound("config".to_string()))?;
-
+    let _timer = telemetry::time_request(ResticFileType::Config, "get");
     let file = state
-        .client
-        .find_file(&dir_id, "config")
+        .backend
+        .stat(ResticFileType::Config, "config")
         .await?
         .ok_or_else(|| AppError::NotFound("config".to_string()))?;
 
-    let data = state.client.download_file(&file.pick_code, None).await?;
+    let _inflight = state.reserve(file.size)?;
+    let data = state.backend.get(&file, None).await?;
+    telemetry::record_read(ResticFileType::Config, None, data.len() as u64);
 
     let mut headers = HeaderMap::new();
     headers.insert(
@@ -132,19 +365,88 @@ async fn get_config(State(state): State<Arc<AppState>>) -> Result<impl IntoRespo
 
 async fn post_config(
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
     body: axum::body::Body,
 ) -> Result<impl IntoResponse> {
-    let body = axum::body::to_bytes(body, 1024 * 1024 * 1024)
-        .await
-        .map_err(|e| AppError::BadRequest(format!("Failed to read request body: {}", e)))?;
+    let (body, _inflight) = read_body(&state, &headers, body).await?;
+    let _timer = telemetry::time_request(ResticFileType::Config, "post");
 
+    auto_create_repository(&state).await?;
     tracing::info!("Saving config ({} bytes)", body.len());
-    let dir_id = state.client.get_type_dir_id(ResticFileType::Config).await?;
-    // Config is immediately read by restic; local cache is updated by upload_file.
-    state.client.upload_file(&dir_id, "config", body).await?;
+    state
+        .backend
+        .put(ResticFileType::Config, "config", body)
+        .await?;
     Ok(StatusCode::OK)
 }
 
+/// Buffer a request body, enforcing `max_blob_size` and charging the body to
+/// the in-flight budget.
+///
+/// The declared Content-Length is checked and reserved up front so oversized
+/// or over-budget uploads are rejected before they are read; bodies without
+/// one are charged afterwards.
+async fn read_body(
+    state: &AppState,
+    headers: &HeaderMap,
+    body: Body,
+) -> Result<(Bytes, Option<InflightGuard>)> {
+    let declared = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    let too_large = || {
+        AppError::PayloadTooLarge(format!(
+            "request body exceeds the {} byte limit",
+            state.max_blob_size
+        ))
+    };
+    if declared.is_some_and(|len| len > state.max_blob_size) {
+        return Err(too_large());
+    }
+    let read_failed = |e: axum::Error| {
+        let e = e.into_inner();
+        if e.downcast_ref::<http_body_util::LengthLimitError>()
+            .is_some()
+        {
+            too_large()
+        } else {
+            AppError::BadRequest(format!("Failed to read request body: {}", e))
+        }
+    };
+    let limit = usize::try_from(state.max_blob_size).unwrap_or(usize::MAX);
+
+    if declared.is_none()
+        && let Some(budget) = &state.inflight
+    {
+        // Chunked or HTTP/2 bodies without a length are charged as they
+        // arrive, so they cannot pile up in memory past the budget.
+        let mut guard = state.reserve(0)?.expect("budget is set");
+        let mut buf = bytes::BytesMut::new();
+        let mut stream = body.into_data_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(read_failed)?;
+            if buf.len() + chunk.len() > limit {
+                return Err(too_large());
+            }
+            if !guard.try_grow(chunk.len() as u64) {
+                return Err(budget_exhausted(budget, chunk.len() as u64));
+            }
+            buf.extend_from_slice(&chunk);
+        }
+        return Ok((buf.freeze(), Some(guard)));
+    }
+
+    let guard = match declared {
+        Some(len) => state.reserve(len)?,
+        None => None,
+    };
+    let body = axum::body::to_bytes(body, limit)
+        .await
+        .map_err(read_failed)?;
+    Ok((body, guard))
+}
+
 // ============================================================================
 // File Listing
 // ============================================================================
@@ -152,11 +454,13 @@ async fn post_config(
 async fn list_files(
     State(state): State<Arc<AppState>>,
     Path(type_str): Path<String>,
+    headers: HeaderMap,
 ) -> Result<Response> {
     let file_type = type_str
         .parse::<ResticFileType>()
         .ok()
         .ok_or_else(|| AppError::BadRequest(format!("Invalid type: {}", type_str)))?;
+    let _timer = telemetry::time_request(file_type, "list");
 
     if file_type.is_config() {
         return Err(AppError::BadRequest(
@@ -164,34 +468,95 @@ async fn list_files(
         ));
     }
 
-    let files = if file_type == ResticFileType::Data {
-        state.client.list_all_data_files().await?
-    } else {
-        // Read-only listing: if the repo/type dir doesn't exist yet, return empty list.
-        match state.client.find_type_dir_id(file_type).await? {
-            Some(dir_id) => state.client.list_files(&dir_id).await?,
-            None => Vec::new(),
-        }
-    };
+    // Spooled packs are already acknowledged and must be visible to restic,
+    // and so are metadata uploads 115 does not list yet. Both are few; they
+    // go last, and the backend's pages skip the names they cover.
+    let mut covered = std::collections::HashSet::new();
+    let mut extra: Vec<FileEntryV2> = Vec::new();
+    if file_type == ResticFileType::Data
+        && let Some(spool) = &state.spool
+    {
+        extra.extend(
+            spool
+                .pending()
+                .into_iter()
+                .filter(|(name, _)| covered.insert(name.clone()))
+                .map(|(name, size)| FileEntryV2 { name, size }),
+        );
+    }
+    if let Some(recent) = &state.recent {
+        extra.extend(
+            recent
+                .list(file_type)
+                .into_iter()
+                .filter(|(name, _)| covered.insert(name.clone()))
+                .map(|(name, size)| FileEntryV2 { name, size }),
+        );
+    }
 
-    let entries: Vec<FileEntryV2> = files
-        .iter()
-        .filter(|f| !f.is_dir)
-        .map(|f| FileEntryV2 {
-            name: f.filename.clone(),
-            size: f.size as u64,
-        })
-        .collect();
+    let listed = state
+        .backend
+        .list_pages(file_type)
+        .await?
+        .map_ok(move |page| {
+            page.into_iter()
+                .filter(|f| !covered.contains(&f.name))
+                .map(|f| FileEntryV2 {
+                    name: f.name,
+                    size: f.size,
+                })
+                .collect()
+        });
+    let pages = listed.chain(futures::stream::once(async { Ok(extra) }));
 
-    let body = serde_json::to_string(&entries)?;
+    // v2 lists name and size; v1 is a plain array of names.
+    let wants_v2 = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.contains(V2_CONTENT_TYPE));
+    let content_type = if wants_v2 {
+        V2_CONTENT_TYPE
+    } else {
+        V1_CONTENT_TYPE
+    };
 
     Ok(Response::builder()
         .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, V2_CONTENT_TYPE)
-        .body(Body::from(body))
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from_stream(json_array(pages, wants_v2)))
         .unwrap())
 }
 
+/// A listing as a JSON array, written page by page: entries for v2, names
+/// for v1. A failing page ends the body early, which restic reports as a
+/// broken response.
+fn json_array(
+    pages: impl futures::Stream<Item = Result<Vec<FileEntryV2>>> + Send + 'static,
+    v2: bool,
+) -> impl futures::Stream<Item = Result<Bytes>> + Send + 'static {
+    let mut first = true;
+    let entries = pages.map(move |page| {
+        let mut buf = Vec::new();
+        for entry in page? {
+            if !std::mem::take(&mut first) {
+                buf.push(b',');
+            }
+            if v2 {
+                serde_json::to_writer(&mut buf, &entry)?;
+            } else {
+                serde_json::to_writer(&mut buf, &entry.name)?;
+            }
+        }
+        Ok(Bytes::from(buf))
+    });
+    futures::stream::once(async { Ok(Bytes::from_static(b"[")) })
+        .chain(entries)
+        .chain(futures::stream::once(async {
+            Ok(Bytes::from_static(b"]"))
+        }))
+}
+
 // ============================================================================
 // Individual File Operations
 // ============================================================================
@@ -204,24 +569,27 @@ async fn head_file(
         .parse::<ResticFileType>()
         .ok()
         .ok_or_else(|| AppError::BadRequest(format!("Invalid type: {}", type_str)))?;
+    let _timer = telemetry::time_request(file_type, "head");
 
-    // Read-only: do NOT create directories on HEAD/GET/DELETE.
-    let dir_id = if file_type == ResticFileType::Data {
-        state
-            .client
-            .find_data_file_dir_id(&name)
-            .await?
-            .ok_or_else(|| AppError::NotFound(name.clone()))?
-    } else {
-        state
-            .client
-            .find_type_dir_id(file_type)
-            .await?
-            .ok_or_else(|| AppError::NotFound(name.clone()))?
-    };
+    if file_type == ResticFileType::Data
+        && let Some(size) = state.spool.as_ref().and_then(|s| s.pending_size(&name))
+    {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_LENGTH, size.to_string().parse().unwrap());
+        return Ok((StatusCode::OK, headers));
+    }
+    if let Some(recent) = &state.recent
+        && let Some(data) = recent.get(file_type, &name).await
+    {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_LENGTH,
+            data.len().to_string().parse().unwrap(),
+        );
+        return Ok((StatusCode::OK, headers));
+    }
 
-    // Avoid listing inside data hash subdirs; allow listing fallback for non-data dirs only.
-    match state.client.find_file(&dir_id, &name).await? {
+    match state.backend.stat(file_type, &name).await? {
         Some(file) => {
             let mut headers = HeaderMap::new();
             headers.insert(
@@ -286,29 +654,50 @@ async fn get_file(
         .parse::<ResticFileType>()
         .ok()
         .ok_or_else(|| AppError::BadRequest(format!("Invalid type: {}", type_str)))?;
+    let _timer = telemetry::time_request(file_type, "get");
 
-    // Read-only: do NOT create directories on HEAD/GET/DELETE.
-    let dir_id = if file_type == ResticFileType::Data {
-        state
-            .client
-            .find_data_file_dir_id(&name)
-            .await?
-            .ok_or_else(|| AppError::NotFound(name.clone()))?
-    } else {
-        state
-            .client
-            .find_type_dir_id(file_type)
-            .await?
-            .ok_or_else(|| AppError::NotFound(name.clone()))?
-    };
+    if let Some(prefetch) = &state.prefetch {
+        match file_type {
+            ResticFileType::Index => prefetch.index_fetched(&name).await,
+            ResticFileType::Data => prefetch.pack_fetched(&name).await,
+            _ => {}
+        }
+    }
+
+    if file_type == ResticFileType::Data
+        && let Some(spool) = &state.spool
+        && let Some(size) = spool.pending_size(&name)
+    {
+        let inflight = state.reserve(size)?;
+        if let Some(data) = spool.read(&name).await? {
+            return Ok(held(serve_bytes(file_type, data, &headers)?, inflight));
+        }
+    }
+
+    if let Some(cache) = &state.read_cache
+        && let Some(size) = cache.size(file_type, &name).await
+    {
+        let inflight = state.reserve(size)?;
+        if let Some(data) = cache.get(file_type, &name).await {
+            tracing::debug!("Read cache hit for {}/{}", type_str, name);
+            return Ok(held(serve_bytes(file_type, data, &headers)?, inflight));
+        }
+    }
+    if let Some(recent) = &state.recent
+        && let Some(data) = recent.get(file_type, &name).await
+    {
+        tracing::debug!("Serving {}/{} from recent uploads", type_str, name);
+        let inflight = state.reserve(data.len() as u64)?;
+        return Ok(held(serve_bytes(file_type, data, &headers)?, inflight));
+    }
 
     let file = state
-        .client
-        .find_file(&dir_id, &name)
+        .backend
+        .stat(file_type, &name)
         .await?
         .ok_or_else(|| AppError::NotFound(name.clone()))?;
 
-    let file_size = file.size as u64;
+    let file_size = file.size;
 
     let range_hdr = headers.get(header::RANGE).and_then(|v| v.to_str().ok());
 
@@ -334,10 +723,9 @@ async fn get_file(
                     .into_response());
             }
         };
-        let data = state
-            .client
-            .download_file(&file.pick_code, Some((start, end)))
-            .await?;
+        let inflight = state.reserve(end - start + 1)?;
+        let data = state.backend.get(&file, Some((start, end))).await?;
+        telemetry::record_read(file_type, Some((start, end)), data.len() as u64);
 
         let content_range = format!("bytes {}-{}/{}", start, end, file_size);
         let mut resp_headers = HeaderMap::new();
@@ -351,49 +739,258 @@ async fn get_file(
             data.len().to_string().parse().unwrap(),
         );
         resp_headers.insert(header::CONTENT_RANGE, content_range.parse().unwrap());
-        Ok((StatusCode::PARTIAL_CONTENT, resp_headers, data).into_response())
+        let resp = (StatusCode::PARTIAL_CONTENT, resp_headers, data).into_response();
+        Ok(held(resp, inflight))
     } else {
-        let data = state.client.download_file(&file.pick_code, None).await?;
+        let inflight = state.reserve(file_size)?;
+        let data = state.backend.get(&file, None).await?;
+        telemetry::record_read(file_type, None, data.len() as u64);
+        if let Some(cache) = &state.read_cache {
+            cache.put(file_type, &name, data.clone()).await;
+        }
         let mut resp_headers = HeaderMap::new();
         resp_headers.insert(
             header::CONTENT_TYPE,
             "application/octet-stream".parse().unwrap(),
         );
         resp_headers.insert(header::ACCEPT_RANGES, "bytes".parse().unwrap());
+        if state.checksum_trailer
+            && accepts_trailers(&headers)
+            && let Some(sha1) = state.backend.known_sha1(&file).await
+        {
+            // Trailers need a chunked body, so no Content-Length here.
+            resp_headers.insert(header::TRAILER, SHA1_TRAILER.parse().unwrap());
+            let body = with_sha1_trailer(data, &sha1);
+            return Ok(held(
+                (StatusCode::OK, resp_headers, body).into_response(),
+                inflight,
+            ));
+        }
         resp_headers.insert(
             header::CONTENT_LENGTH,
             data.len().to_string().parse().unwrap(),
         );
-        Ok((StatusCode::OK, resp_headers, data).into_response())
+        Ok(held(
+            (StatusCode::OK, resp_headers, data).into_response(),
+            inflight,
+        ))
+    }
+}
+
+/// `resp` with its body holding `inflight` until the body has been sent, or
+/// dropped along with the connection.
+fn held(resp: Response, inflight: Option<InflightGuard>) -> Response {
+    match inflight {
+        Some(inflight) => resp.map(|body| {
+            Body::new(HeldBody {
+                body,
+                _guard: inflight,
+            })
+        }),
+        None => resp,
+    }
+}
+
+/// A response body and a guard (such as the in-flight reservation) released
+/// once it has been sent.
+struct HeldBody<G> {
+    body: Body,
+    _guard: G,
+}
+
+impl<G: Unpin> http_body::Body for HeldBody<G> {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<std::result::Result<http_body::Frame<Bytes>, Self::Error>>> {
+        std::pin::Pin::new(&mut self.body).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.body.size_hint()
+    }
+}
+
+/// Whether the request's `TE` header allows trailers.
+fn accepts_trailers(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::TE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|t| {
+            t.split(';')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .eq_ignore_ascii_case("trailers")
+        })
+}
+
+/// `data` followed by a trailer with its SHA-1.
+fn with_sha1_trailer(data: Bytes, sha1: &str) -> Body {
+    use http_body::Frame;
+    use http_body_util::StreamBody;
+
+    let mut trailers = HeaderMap::new();
+    trailers.insert(SHA1_TRAILER, sha1.parse().unwrap());
+    let frames = futures::stream::iter([
+        Ok::<_, std::convert::Infallible>(Frame::data(data)),
+        Ok(Frame::trailers(trailers)),
+    ]);
+    Body::new(StreamBody::new(frames))
+}
+
+/// Serve an object held in memory, honouring a single Range header.
+fn serve_bytes(file_type: ResticFileType, data: Bytes, headers: &HeaderMap) -> Result<Response> {
+    let file_size = data.len() as u64;
+    let range_hdr = headers.get(header::RANGE).and_then(|v| v.to_str().ok());
+    let (status, body, range) = match range_hdr.map(|r| parse_range(r, file_size)) {
+        None => (StatusCode::OK, data, None),
+        Some(Ok((start, end))) => (
+            StatusCode::PARTIAL_CONTENT,
+            data.slice(start as usize..=end as usize),
+            Some((start, end)),
+        ),
+        Some(Err(RangeParseError::Invalid)) => {
+            return Err(AppError::BadRequest("Invalid Range header".to_string()));
+        }
+        Some(Err(RangeParseError::Unsatisfiable)) => {
+            let mut resp_headers = HeaderMap::new();
+            resp_headers.insert(header::ACCEPT_RANGES, "bytes".parse().unwrap());
+            resp_headers.insert(
+                header::CONTENT_RANGE,
+                format!("bytes */{}", file_size).parse().unwrap(),
+            );
+            resp_headers.insert(header::CONTENT_LENGTH, "0".parse().unwrap());
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                resp_headers,
+                Bytes::new(),
+            )
+                .into_response());
+        }
+    };
+    telemetry::record_read(file_type, range, body.len() as u64);
+
+    let mut resp_headers = HeaderMap::new();
+    resp_headers.insert(
+        header::CONTENT_TYPE,
+        "application/octet-stream".parse().unwrap(),
+    );
+    resp_headers.insert(header::ACCEPT_RANGES, "bytes".parse().unwrap());
+    resp_headers.insert(
+        header::CONTENT_LENGTH,
+        body.len().to_string().parse().unwrap(),
+    );
+    if let Some((start, end)) = range {
+        resp_headers.insert(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, end, file_size)
+                .parse()
+                .unwrap(),
+        );
     }
+    Ok((status, resp_headers, body).into_response())
 }
 
 async fn post_file(
     State(state): State<Arc<AppState>>,
     Path((type_str, name)): Path<(String, String)>,
+    headers: HeaderMap,
     body: axum::body::Body,
 ) -> Result<impl IntoResponse> {
-    let body = axum::body::to_bytes(body, 1024 * 1024 * 1024)
-        .await
-        .map_err(|e| AppError::BadRequest(format!("Failed to read request body: {}", e)))?;
+    let (body, _inflight) = read_body(&state, &headers, body).await?;
 
     let file_type = type_str
         .parse::<ResticFileType>()
         .ok()
         .ok_or_else(|| AppError::BadRequest(format!("Invalid type: {}", type_str)))?;
+    let _timer = telemetry::time_request(file_type, "post");
+    auto_create_repository(&state).await?;
 
-    tracing::info!("Uploading {}/{} ({} bytes)", type_str, name, body.len());
+    if file_type == ResticFileType::Data
+        && let Some(spool) = &state.spool
+    {
+        // The spool acknowledges before uploading, so reject conflicts now.
+        if state.backend.duplicate_policy() == DuplicatePolicy::Reject
+            && (spool.pending_size(&name).is_some()
+                || state.backend.exists(file_type, &name).await?)
+        {
+            return Err(AppError::Conflict(format!("{} already exists", name)));
+        }
+        tracing::info!("Spooling {}/{} ({} bytes)", type_str, name, body.len());
+        spool.put(&name, body.clone()).await?;
+        if let Some(cache) = &state.read_cache {
+            cache.put(file_type, &name, body).await;
+        }
+        return Ok(StatusCode::OK);
+    }
 
-    let dir_id = if file_type == ResticFileType::Data {
-        state.client.get_data_file_dir_id(&name).await?
-    } else {
-        state.client.get_type_dir_id(file_type).await?
-    };
+    tracing::info!("Uploading {}/{} ({} bytes)", type_str, name, body.len());
 
-    state.client.upload_file(&dir_id, &name, body).await?;
+    state.backend.put(file_type, &name, body.clone()).await?;
+    let size = body.len() as u64;
+    if let Some(recent) = &state.recent {
+        recent.put(file_type, &name, body.clone()).await;
+    }
+    if let Some(cache) = &state.read_cache {
+        cache.put(file_type, &name, body).await;
+    }
+    if file_type == ResticFileType::Snapshots {
+        notify_snapshot_completed(&state, &name, size).await;
+    }
+    if file_type == ResticFileType::Locks
+        && let Some(locks) = &state.locks
+    {
+        locks.created(&name);
+    }
     Ok(StatusCode::OK)
 }
 
+/// Emit [`Event::SnapshotCompleted`] once the storage reports the uploaded
+/// snapshot with the expected size. The upload itself has succeeded, so a
+/// failed check is only logged.
+async fn notify_snapshot_completed(state: &AppState, name: &str, size: u64) {
+    let reported = match state.backend.stat(ResticFileType::Snapshots, name).await {
+        Ok(Some(file)) => state.backend.reported_size(&file).await,
+        Ok(None) => Ok(None),
+        Err(e) => Err(e),
+    };
+    match reported {
+        Ok(Some(reported)) if reported == size => {
+            state.events.emit(Event::SnapshotCompleted {
+                repo: state.backend.repo_id().to_string(),
+                id: name.to_string(),
+                size,
+                timestamp: chrono::Utc::now().timestamp(),
+            });
+        }
+        Ok(Some(reported)) => tracing::warn!(
+            "Snapshot {} stored with size {} instead of {}; not reporting completion",
+            name,
+            reported,
+            size
+        ),
+        Ok(None) => tracing::warn!(
+            "Snapshot {} not confirmed by the storage after upload; not reporting completion",
+            name
+        ),
+        Err(e) => tracing::warn!(
+            "Could not confirm snapshot {}: {}; not reporting completion",
+            name,
+            e
+        ),
+    }
+}
+
 async fn delete_file(
     State(state): State<Arc<AppState>>,
     Path((type_str, name)): Path<(String, String)>,
@@ -402,27 +999,335 @@ async fn delete_file(
         .parse::<ResticFileType>()
         .ok()
         .ok_or_else(|| AppError::BadRequest(format!("Invalid type: {}", type_str)))?;
+    let _timer = telemetry::time_request(file_type, "delete");
 
     tracing::info!("Deleting {}/{}", type_str, name);
 
-    // Read-only: do NOT create directories on HEAD/GET/DELETE.
-    let dir_id = if file_type == ResticFileType::Data {
-        match state.client.find_data_file_dir_id(&name).await? {
-            Some(id) => id,
-            None => return Ok(StatusCode::OK),
+    if file_type == ResticFileType::Data
+        && let Some(spool) = &state.spool
+    {
+        // A pack may exist both in the spool and on 115 (re-uploads); drop both.
+        spool.remove(&name).await?;
+    }
+    if let Some(cache) = &state.read_cache {
+        cache.remove(file_type, &name).await;
+    }
+    if let Some(recent) = &state.recent {
+        recent.remove(file_type, &name).await;
+    }
+    if file_type == ResticFileType::Locks
+        && let Some(locks) = &state.locks
+    {
+        locks.removed(&name);
+    }
+
+    state.backend.delete(file_type, &name).await?;
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::ObjectInfo;
+    use http_body_util::BodyExt;
+    use std::collections::HashMap;
+    use tower::ServiceExt;
+
+    /// Objects kept in memory, keyed by type folder and name.
+    #[derive(Default)]
+    struct MemoryBackend {
+        objects: parking_lot::Mutex<HashMap<(&'static str, String), Bytes>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Backend for MemoryBackend {
+        fn repo_id(&self) -> &str {
+            "memory"
         }
-    } else {
-        match state.client.find_type_dir_id(file_type).await? {
-            Some(id) => id,
-            None => return Ok(StatusCode::OK),
+
+        fn repo_path(&self) -> &str {
+            "/memory"
+        }
+
+        async fn init(&self) -> Result<()> {
+            Ok(())
         }
-    };
 
-    if let Some(file) = state.client.find_file(&dir_id, &name).await? {
-        // Best-effort: delete_file handles API call and local cache removal.
+        async fn destroy(&self) -> Result<bool> {
+            self.objects.lock().clear();
+            Ok(true)
+        }
+
+        async fn list(&self, file_type: ResticFileType) -> Result<Vec<ObjectInfo>> {
+            let objects = self.objects.lock();
+            let mut list: Vec<ObjectInfo> = objects
+                .iter()
+                .filter(|((dir, _), _)| *dir == file_type.dirname())
+                .map(|((_, name), data)| ObjectInfo {
+                    name: name.clone(),
+                    size: data.len() as u64,
+                    id: name.clone(),
+                })
+                .collect();
+            list.sort_by(|a, b| a.name.cmp(&b.name));
+            Ok(list)
+        }
+
+        async fn stat(&self, file_type: ResticFileType, name: &str) -> Result<Option<ObjectInfo>> {
+            let key = (file_type.dirname(), name.to_string());
+            Ok(self.objects.lock().get(&key).map(|data| ObjectInfo {
+                name: name.to_string(),
+                size: data.len() as u64,
+                id: format!("{}/{}", file_type.dirname(), name),
+            }))
+        }
+
+        async fn get(&self, object: &ObjectInfo, range: Option<(u64, u64)>) -> Result<Bytes> {
+            let (dir, name) = object.id.split_once('/').unwrap();
+            let objects = self.objects.lock();
+            let data = objects
+                .iter()
+                .find(|((d, n), _)| *d == dir && n == name)
+                .map(|(_, data)| data.clone())
+                .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+            Ok(match range {
+                Some((start, end)) => data.slice(start as usize..=end as usize),
+                None => data,
+            })
+        }
 
-        state.client.delete_file(&dir_id, &file.file_id).await?;
+        async fn put(&self, file_type: ResticFileType, name: &str, data: Bytes) -> Result<()> {
+            self.objects
+                .lock()
+                .insert((file_type.dirname(), name.to_string()), data);
+            Ok(())
+        }
+
+        async fn delete(&self, file_type: ResticFileType, name: &str) -> Result<()> {
+            self.objects
+                .lock()
+                .remove(&(file_type.dirname(), name.to_string()));
+            Ok(())
+        }
     }
 
-    Ok(StatusCode::OK)
+    #[tokio::test]
+    async fn test_router_with_memory_backend() {
+        let app = create_router(memory_state(MemoryBackend::default()));
+        let send = |method: &str, uri: &str, header: Option<(&str, &str)>, body: &'static str| {
+            let mut req = Request::builder().method(method).uri(uri);
+            if let Some((name, value)) = header {
+                req = req.header(name, value);
+            }
+            app.clone().oneshot(req.body(Body::from(body)).unwrap())
+        };
+        let text = |resp: Response| async {
+            let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+            String::from_utf8(bytes.to_vec()).unwrap()
+        };
+
+        let resp = send("POST", "/keys/k1", None, "abc").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = send("GET", "/keys/", Some(("accept", V2_CONTENT_TYPE)), "")
+            .await
+            .unwrap();
+        assert_eq!(text(resp).await, r#"[{"name":"k1","size":3}]"#);
+        send("POST", "/keys/k2", None, "de").await.unwrap();
+        let resp = send("GET", "/keys/", None, "").await.unwrap();
+        assert_eq!(text(resp).await, r#"["k1","k2"]"#);
+        let resp = send("GET", "/keys/k1", Some(("range", "bytes=1-1")), "")
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(text(resp).await, "b");
+
+        let resp = send("DELETE", "/keys/k1", None, "").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = send("HEAD", "/keys/k1", None, "").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let resp = send("GET", "/config", None, "").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// State serving `backend`, with every optional feature off.
+    fn memory_state(backend: MemoryBackend) -> AppState {
+        AppState {
+            backend: Arc::new(backend),
+            health: None,
+            client: None,
+            spool: None,
+            read_cache: None,
+            events: Arc::new(EventBus::new()),
+            allow_repo_delete: false,
+            auto_create_repo: None,
+            max_blob_size: 1 << 20,
+            prefetch: None,
+            inflight: None,
+            admin_token: None,
+            checksum_trailer: false,
+            cache_rebuild: Default::default(),
+            info: Arc::new(serde_json::Value::Null),
+            locks: None,
+            recent: None,
+            call_budget: None,
+            credentials: None,
+            activity: Activity::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_auto_create_repo() {
+        let initialized = |events: &EventBus| {
+            events
+                .recent()
+                .iter()
+                .filter(|e| matches!(e, Event::RepoInitialized { .. }))
+                .count()
+        };
+
+        // Without a config, the first write creates the repository, once.
+        let events = Arc::new(EventBus::new());
+        let app = create_router(AppState {
+            events: events.clone(),
+            auto_create_repo: Some(Default::default()),
+            ..memory_state(MemoryBackend::default())
+        });
+        for name in ["/keys/k1", "/keys/k2"] {
+            let req = Request::post(name).body(Body::from("abc")).unwrap();
+            let resp = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+        assert_eq!(initialized(&events), 1);
+
+        // An existing repository is left alone.
+        let backend = MemoryBackend::default();
+        backend
+            .objects
+            .lock()
+            .insert(("config", "config".to_string()), Bytes::from_static(b"c"));
+        let events = Arc::new(EventBus::new());
+        let app = create_router(AppState {
+            events: events.clone(),
+            auto_create_repo: Some(Default::default()),
+            ..memory_state(backend)
+        });
+        let req = Request::post("/keys/k1").body(Body::from("abc")).unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(initialized(&events), 0);
+    }
+
+    #[tokio::test]
+    async fn test_inflight_held_until_sent() {
+        let backend = MemoryBackend::default();
+        backend.objects.lock().insert(
+            ("data", "d1".to_string()),
+            Bytes::from_static(b"0123456789"),
+        );
+        let budget = InflightBudget::new(15);
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ReadCache::open(dir.path().to_path_buf(), 1 << 20, false)
+            .await
+            .unwrap();
+        let app = create_router(AppState {
+            inflight: Some(budget.clone()),
+            read_cache: Some(cache),
+            ..memory_state(backend)
+        });
+        let get = || {
+            let req = Request::get("/data/d1").body(Body::empty()).unwrap();
+            app.clone().oneshot(req)
+        };
+
+        // Fetched from the backend, then served from the read cache: either
+        // way the bytes stay charged until the body has been read.
+        for _ in 0..2 {
+            let resp = get().await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(budget.used(), 10);
+            let busy = get().await.unwrap();
+            assert_eq!(busy.status(), StatusCode::SERVICE_UNAVAILABLE);
+            let body = resp.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, "0123456789");
+            assert_eq!(budget.used(), 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_inflight_charges_chunked_uploads() {
+        let budget = InflightBudget::new(10);
+        let app = create_router(AppState {
+            inflight: Some(budget.clone()),
+            ..memory_state(MemoryBackend::default())
+        });
+        // A streamed body carries no Content-Length.
+        let post = || {
+            let chunks = ["abc", "def"].map(|c| Ok::<_, std::io::Error>(Bytes::from(c)));
+            let body = Body::from_stream(futures::stream::iter(chunks));
+            app.clone()
+                .oneshot(Request::post("/keys/k1").body(body).unwrap())
+        };
+
+        let held = budget.try_acquire(10).unwrap();
+        let resp = post().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(budget.used(), 10);
+
+        drop(held);
+        let resp = post().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_routes_without_115_client() {
+        let app = create_router(AppState {
+            admin_token: Some("admin".to_string()),
+            ..memory_state(MemoryBackend::default())
+        });
+        let get = |uri| {
+            let req = Request::get(uri).header(header::AUTHORIZATION, "Bearer admin");
+            app.clone().oneshot(req.body(Body::empty()).unwrap())
+        };
+
+        for uri in ["/healthz", "/readyz", "/admin/token", "/api/quota"] {
+            let resp = get(uri).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{uri}");
+        }
+        assert_eq!(get("/admin/info").await.unwrap().status(), StatusCode::OK);
+        assert_eq!(get("/keys/").await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_skip_credentials() {
+        let app = create_router(AppState {
+            credentials: Some(Arc::new(Credentials::single("user", "secret"))),
+            ..memory_state(MemoryBackend::default())
+        });
+        let get = |uri| {
+            app.clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+        };
+
+        let resp = get("/config").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = get("/metrics").await.unwrap();
+        assert_ne!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_sha1_trailer_body() {
+        let mut headers = HeaderMap::new();
+        assert!(!accepts_trailers(&headers));
+        headers.insert(header::TE, "gzip, Trailers;q=1".parse().unwrap());
+        assert!(accepts_trailers(&headers));
+
+        let sha1 = "a9993e364706816aba3e25717850c26c9cd0d89d";
+        let collected = with_sha1_trailer(Bytes::from_static(b"abc"), sha1)
+            .collect()
+            .await
+            .unwrap();
+        assert_eq!(collected.trailers().unwrap()[SHA1_TRAILER], sha1);
+        assert_eq!(collected.to_bytes(), "abc");
+    }
 }
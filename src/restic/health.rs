@@ -0,0 +1,241 @@
+//! Health endpoints, both unauthenticated.
+//!
+//! `GET /healthz` answers whether the server is up and can reach 115, for
+//! container health checks (`restic-115 healthcheck`).
+//!
+//! `GET /readyz` tells a load balancer or supervisor whether to send restic
+//! traffic here and, if not, why, with a distinct status per cause:
+//!
+//! | Status | `reason`             | Meaning                                           |
+//! |--------|----------------------|---------------------------------------------------|
+//! | 200    | -                    | ready                                             |
+//! | 500    | `cache_db_read_only` | local fault; restart once the disk is fixed       |
+//! | 503    | `token_invalid`      | 115 rejects the tokens; replace them              |
+//! | 502    | `upstream_down`      | 115 unreachable or failing; restarting won't help |
+//! | 507    | `quota_exhausted`    | the account is (nearly) full                      |
+//!
+//! The 115 probe (an account info call, which also reports the quota) is
+//! cached for a few seconds so frequent checks do not spend API quota.
+
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::handler::AppState;
+use crate::error::{AppError, Result};
+use crate::open115::{Open115Client, SpaceInfo};
+
+/// How long a 115 probe result is reused.
+const PROBE_TTL: Duration = Duration::from_secs(10);
+
+/// Below this much free space the account counts as full: restic could not
+/// even write one default-sized pack.
+const MIN_FREE_BYTES: u64 = 128 * 1024 * 1024;
+
+/// Outcome of probing 115.
+#[derive(Debug, Clone)]
+pub enum Probe {
+    Ok(SpaceInfo),
+    /// 115 answered but rejected the tokens.
+    Auth(String),
+    /// 115 could not be reached or returned an error.
+    Unreachable(String),
+}
+
+pub struct Health {
+    client: Open115Client,
+    /// Latest probe and when it was taken.
+    last: tokio::sync::Mutex<Option<(Instant, Probe)>>,
+}
+
+impl Health {
+    pub fn new(client: Open115Client) -> Self {
+        Self {
+            client,
+            last: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// Probe 115, or reuse a probe taken within [`PROBE_TTL`]. Concurrent
+    /// callers wait for and share one probe.
+    pub async fn probe(&self) -> Probe {
+        let mut last = self.last.lock().await;
+        if let Some((at, probe)) = &*last
+            && at.elapsed() < PROBE_TTL
+        {
+            return probe.clone();
+        }
+        let probe = match self.client.space_info().await {
+            Ok(space) => Probe::Ok(space),
+            Err(e @ AppError::Auth(_)) => Probe::Auth(e.to_string()),
+            Err(e) => Probe::Unreachable(e.to_string()),
+        };
+        if !matches!(probe, Probe::Ok(_)) {
+            tracing::warn!("Health check: 115 probe failed: {:?}", probe);
+        }
+        *last = Some((Instant::now(), probe.clone()));
+        probe
+    }
+}
+
+/// Probe 115 and read the cache DB state; 404 without a 115 client.
+pub(super) async fn check(state: &AppState) -> Result<(Probe, bool)> {
+    let (Some(health), Ok(client)) = (&state.health, state.client()) else {
+        return Err(AppError::NotFound("no 115 client configured".to_string()));
+    };
+    Ok((health.probe().await, client.cache_db_degraded()))
+}
+
+pub async fn healthz(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse> {
+    let (probe, cache_db_degraded) = check(&state).await?;
+    Ok(health_report(&probe, cache_db_degraded))
+}
+
+pub async fn readyz(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse> {
+    let (probe, cache_db_degraded) = check(&state).await?;
+    Ok(readiness_report(
+        &probe,
+        cache_db_degraded,
+        state.events.upstream_is_failing(),
+    ))
+}
+
+fn health_report(probe: &Probe, cache_db_degraded: bool) -> (StatusCode, Json<serde_json::Value>) {
+    // A degraded cache DB runs from memory and keeps serving, so it is
+    // reported but not unhealthy.
+    let cache_db = if cache_db_degraded { "degraded" } else { "ok" };
+    match probe {
+        Probe::Ok(_) => (
+            StatusCode::OK,
+            Json(json!({ "status": "ok", "upstream": "ok", "cache_db": cache_db })),
+        ),
+        Probe::Auth(e) | Probe::Unreachable(e) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "unhealthy", "upstream": e, "cache_db": cache_db })),
+        ),
+    }
+}
+
+pub(super) fn readiness_report(
+    probe: &Probe,
+    cache_db_degraded: bool,
+    upstream_failing: bool,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let checks = json!({
+        "cache_db": if cache_db_degraded { "read_only" } else { "ok" },
+        "token": match probe {
+            Probe::Auth(_) => "invalid",
+            Probe::Ok(_) => "ok",
+            Probe::Unreachable(_) => "unknown",
+        },
+        "upstream": match probe {
+            Probe::Unreachable(_) => "down",
+            _ if upstream_failing => "failing",
+            _ => "ok",
+        },
+        "free_bytes": match probe {
+            Probe::Ok(space) => Some(space.free),
+            _ => None,
+        },
+    });
+
+    // Local faults first: they are the only ones a restart fixes.
+    let (status, reason, action, detail) = match probe {
+        _ if cache_db_degraded => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "cache_db_read_only",
+            "restart",
+            "the cache DB became read-only and the cache now lives in memory".to_string(),
+        ),
+        Probe::Auth(e) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "token_invalid",
+            "replace_tokens",
+            e.clone(),
+        ),
+        Probe::Unreachable(e) => (StatusCode::BAD_GATEWAY, "upstream_down", "wait", e.clone()),
+        Probe::Ok(_) if upstream_failing => (
+            StatusCode::BAD_GATEWAY,
+            "upstream_down",
+            "wait",
+            "recent restic requests keep failing upstream".to_string(),
+        ),
+        Probe::Ok(space) if space.free < MIN_FREE_BYTES => (
+            StatusCode::INSUFFICIENT_STORAGE,
+            "quota_exhausted",
+            "free_space",
+            format!("{} bytes free", space.free),
+        ),
+        Probe::Ok(_) => {
+            return (
+                StatusCode::OK,
+                Json(json!({ "status": "ready", "checks": checks })),
+            );
+        }
+    };
+    (
+        status,
+        Json(json!({
+            "status": "not_ready",
+            "reason": reason,
+            "action": action,
+            "detail": detail,
+            "checks": checks,
+        })),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn space(free: u64) -> Probe {
+        Probe::Ok(SpaceInfo {
+            total: u64::MAX,
+            used: 0,
+            free,
+        })
+    }
+
+    #[test]
+    fn test_health_report() {
+        let (status, Json(body)) = health_report(&space(u64::MAX), true);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["cache_db"], "degraded");
+
+        let (status, Json(body)) = health_report(&Probe::Unreachable("timed out".into()), false);
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "unhealthy");
+        assert_eq!(body["upstream"], "timed out");
+    }
+
+    #[test]
+    fn test_readiness_report() {
+        let reason = |probe: Probe, degraded: bool, failing: bool| {
+            let (status, Json(body)) = readiness_report(&probe, degraded, failing);
+            (status.as_u16(), body["reason"].as_str().map(String::from))
+        };
+        assert_eq!(reason(space(u64::MAX), false, false), (200, None));
+        assert_eq!(
+            reason(space(u64::MAX), true, true),
+            (500, Some("cache_db_read_only".into()))
+        );
+        assert_eq!(
+            reason(Probe::Auth("expired".into()), false, false),
+            (503, Some("token_invalid".into()))
+        );
+        assert_eq!(
+            reason(Probe::Unreachable("dns".into()), false, false),
+            (502, Some("upstream_down".into()))
+        );
+        assert_eq!(
+            reason(space(u64::MAX), false, true),
+            (502, Some("upstream_down".into()))
+        );
+        assert_eq!(
+            reason(space(1024), false, false),
+            (507, Some("quota_exhausted".into()))
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! What a browser sees: a small HTML page at `GET /` and for unknown paths,
+//! so someone checking whether the server is up gets an answer instead of a
+//! bare JSON 404 or 405. Other clients get JSON as before. With the admin
+//! bearer token the page also summarises `/readyz`.
+
+use axum::{
+    Json,
+    extract::State,
+    http::{HeaderMap, StatusCode, Uri, header},
+    response::{Html, IntoResponse, Response},
+};
+use serde_json::json;
+use std::sync::Arc;
+
+use super::admin;
+use super::handler::AppState;
+use super::health;
+use crate::error::AppError;
+
+const DOCS_URL: &str = env!("CARGO_PKG_REPOSITORY");
+
+pub async fn index(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    if !wants_html(&headers) {
+        return Json(json!({
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+            "docs": DOCS_URL,
+        }))
+        .into_response();
+    }
+    let health = health_summary(&state, &headers).await;
+    let body = page(
+        "restic REST server for 115 storage",
+        "This server speaks the restic REST protocol. Point restic at this URL, e.g. \
+         <code>restic -r rest:http://host:8000/ snapshots</code>.",
+        health.as_deref(),
+    );
+    Html(body).into_response()
+}
+
+/// Unknown paths: an HTML 404 for browsers, the usual JSON error otherwise.
+pub async fn not_found(headers: HeaderMap, uri: Uri) -> Response {
+    if !wants_html(&headers) {
+        return AppError::NotFound(format!("no route for {}", uri.path())).into_response();
+    }
+    let body = page(
+        "Not found",
+        &format!(
+            "<code>{}</code> is not a restic repository path.",
+            escape(uri.path())
+        ),
+        None,
+    );
+    (StatusCode::NOT_FOUND, Html(body)).into_response()
+}
+
+/// Browsers ask for HTML first; restic and curl never mention it.
+fn wants_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"))
+}
+
+/// One line on readiness, only for callers holding the admin token: it
+/// reveals the account quota and spends a (cached) 115 API call.
+async fn health_summary(state: &AppState, headers: &HeaderMap) -> Option<String> {
+    if !admin::is_admin(state, headers) {
+        return None;
+    }
+    let (probe, cache_db_degraded) = health::check(state).await.ok()?;
+    let (status, Json(report)) = health::readiness_report(
+        &probe,
+        cache_db_degraded,
+        state.events.upstream_is_failing(),
+    );
+    Some(if status.is_success() {
+        "Ready.".to_string()
+    } else {
+        format!(
+            "Not ready: {} ({}).",
+            escape(report["reason"].as_str().unwrap_or_default()),
+            escape(report["detail"].as_str().unwrap_or_default())
+        )
+    })
+}
+
+fn page(title: &str, message: &str, health: Option<&str>) -> String {
+    let health = health
+        .map(|h| format!("<p><strong>Health:</strong> {h}</p>\n"))
+        .unwrap_or_default();
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>restic-115</title></head>\n\
+         <body style=\"font-family: sans-serif; max-width: 40em; margin: 3em auto\">\n\
+         <h1>{title}</h1>\n<p>{message}</p>\n{health}\
+         <p><small>{name} {version} &middot; <a href=\"{docs}\">documentation</a></small></p>\n\
+         </body></html>\n",
+        name = env!("CARGO_PKG_NAME"),
+        version = env!("CARGO_PKG_VERSION"),
+        docs = DOCS_URL,
+    )
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_not_found_page() {
+        let uri: Uri = "/nope".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        let json = not_found(headers.clone(), uri.clone()).await;
+        assert_eq!(json.status(), StatusCode::NOT_FOUND);
+        assert_eq!(json.headers()[header::CONTENT_TYPE], "application/json");
+
+        headers.insert(
+            header::ACCEPT,
+            "text/html,application/xhtml+xml;q=0.9".parse().unwrap(),
+        );
+        let html = not_found(headers, uri).await;
+        assert_eq!(html.status(), StatusCode::NOT_FOUND);
+        assert!(
+            html.headers()[header::CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .starts_with("text/html")
+        );
+        assert_eq!(
+            escape("<a href=\"x\">&"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;"
+        );
+    }
+}
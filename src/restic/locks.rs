@@ -0,0 +1,133 @@
+//! Expiry of stale restic locks (`--lock-ttl`).
+//!
+//! restic refreshes a live lock by uploading a new lock object and deleting
+//! the old one every few minutes, so a lock object that has existed for much
+//! longer belongs to a client that crashed. Creation times are tracked in
+//! memory: locks POSTed through this server are stamped when uploaded, and
+//! locks found in the cache without a stamp (e.g. after a restart) start
+//! their clock when first seen, so a restart only ever delays expiry.
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::error::Result;
+use crate::open115::{Open115Client, ResticFileType};
+
+/// Longest pause between two sweeps of the locks directory.
+const MAX_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+pub struct LockReaper {
+    client: Open115Client,
+    ttl: Duration,
+    /// Lock name -> when it was created or first seen.
+    seen: Mutex<HashMap<String, Instant>>,
+}
+
+impl LockReaper {
+    pub fn new(client: Open115Client, ttl: Duration) -> Arc<Self> {
+        Arc::new(Self {
+            client,
+            ttl,
+            seen: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Record that a lock was just uploaded.
+    pub fn created(&self, name: &str) {
+        self.seen.lock().insert(name.to_string(), Instant::now());
+    }
+
+    /// Forget a lock deleted by restic.
+    pub fn removed(&self, name: &str) {
+        self.seen.lock().remove(name);
+    }
+
+    /// Sweep the locks directory periodically for as long as the process runs.
+    pub fn spawn(self: Arc<Self>) {
+        let period = (self.ttl / 4).clamp(Duration::from_secs(1), MAX_SWEEP_INTERVAL);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.sweep().await {
+                    tracing::warn!("Stale lock sweep failed: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Delete every lock older than the TTL. Returns how many were deleted.
+    async fn sweep(&self) -> Result<usize> {
+        let Some(dir_id) = self.client.find_type_dir_id(ResticFileType::Locks).await? else {
+            return Ok(0);
+        };
+        let files = self.client.list_files(&dir_id).await?;
+        let listed: Vec<&str> = files
+            .iter()
+            .filter(|f| !f.is_dir)
+            .map(|f| f.filename.as_str())
+            .collect();
+        let expired = expire(&mut self.seen.lock(), &listed, Instant::now(), self.ttl);
+
+        let mut deleted = 0;
+        for name in &expired {
+            let Some(file) = files.iter().find(|f| &f.filename == name) else {
+                continue;
+            };
+            tracing::warn!(
+                "Deleting stale lock {} (older than {}s)",
+                name,
+                self.ttl.as_secs()
+            );
+            self.client.delete_file(&dir_id, &file.file_id).await?;
+            self.seen.lock().remove(name);
+            deleted += 1;
+        }
+        Ok(deleted)
+    }
+}
+
+/// Stamp newly listed locks with `now`, drop stamps of locks that are gone
+/// and return the names whose stamp is older than `ttl`.
+fn expire(
+    seen: &mut HashMap<String, Instant>,
+    listed: &[&str],
+    now: Instant,
+    ttl: Duration,
+) -> Vec<String> {
+    seen.retain(|name, _| listed.contains(&name.as_str()));
+    listed
+        .iter()
+        .filter(|name| {
+            let since = *seen.entry(name.to_string()).or_insert(now);
+            now.duration_since(since) > ttl
+        })
+        .map(|name| name.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expire() {
+        let ttl = Duration::from_secs(30 * 60);
+        let start = Instant::now();
+        let mut seen = HashMap::from([("old".to_string(), start)]);
+
+        // Unstamped locks start their clock; nothing is old enough yet.
+        assert!(expire(&mut seen, &["old", "new"], start, ttl).is_empty());
+        assert_eq!(seen.len(), 2);
+
+        let later = start + ttl + Duration::from_secs(1);
+        seen.insert("new".to_string(), later);
+        assert_eq!(expire(&mut seen, &["old", "new"], later, ttl), ["old"]);
+
+        // Locks deleted by restic are forgotten.
+        assert!(expire(&mut seen, &[], later, ttl).is_empty());
+        assert!(seen.is_empty());
+    }
+}
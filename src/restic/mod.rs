@@ -1,7 +1,24 @@
 //! Restic REST API handlers.
 
+mod activity;
+mod admin;
+mod budget;
+mod credentials;
 mod handler;
+mod health;
+mod landing;
+mod locks;
+mod pagination;
+mod prefetch;
+mod recent;
+pub mod request_id;
 mod types;
 
-pub use handler::create_router;
-
+pub use activity::Activity;
+pub use budget::InflightBudget;
+pub use credentials::Credentials;
+pub use handler::{AppState, create_router};
+pub use health::Health;
+pub use locks::LockReaper;
+pub use prefetch::Prefetcher;
+pub use recent::RecentWrites;
@@ -0,0 +1,97 @@
+//! Cursor pagination shared by the admin listings.
+//!
+//! Listings are ordered by a monotonically increasing `u64` key (e.g. an
+//! event sequence number). The cursor handed out is the key of the last item
+//! returned, so pages stay stable while new items are appended.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AppError, Result};
+
+pub const DEFAULT_PAGE_LIMIT: usize = 100;
+pub const MAX_PAGE_LIMIT: usize = 1000;
+
+/// `?cursor=...&limit=...` query parameters.
+#[derive(Debug, Default, Deserialize)]
+pub struct PageParams {
+    /// `next_cursor` of the previous page; the first page when unset.
+    pub cursor: Option<String>,
+    /// Maximum number of items, capped at [`MAX_PAGE_LIMIT`].
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Cursor of the following page; `null` on the last one.
+    pub next_cursor: Option<String>,
+}
+
+/// Return the page of `items` selected by `params`.
+///
+/// `items` must be sorted by ascending key.
+pub fn paginate<T>(items: Vec<(u64, T)>, params: &PageParams) -> Result<Page<T>> {
+    let after = params
+        .cursor
+        .as_deref()
+        .map(|c| {
+            c.parse::<u64>()
+                .map_err(|_| AppError::BadRequest(format!("invalid cursor: {c}")))
+        })
+        .transpose()?;
+    let limit = match params.limit {
+        Some(0) => return Err(AppError::BadRequest("limit must be positive".to_string())),
+        Some(limit) => limit.min(MAX_PAGE_LIMIT),
+        None => DEFAULT_PAGE_LIMIT,
+    };
+
+    let mut rest = items
+        .into_iter()
+        .filter(|(key, _)| after.is_none_or(|after| *key > after))
+        .peekable();
+    let mut page = Vec::with_capacity(limit.min(64));
+    let mut last_key = None;
+    while page.len() < limit {
+        let Some((key, item)) = rest.next() else {
+            break;
+        };
+        last_key = Some(key);
+        page.push(item);
+    }
+    let next_cursor = if rest.peek().is_some() {
+        last_key.map(|k| k.to_string())
+    } else {
+        None
+    };
+    Ok(Page {
+        items: page,
+        next_cursor,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paginate_follows_cursor() {
+        let items = || (1..=5u64).map(|k| (k * 10, k)).collect::<Vec<_>>();
+        let params = |cursor: Option<&str>| PageParams {
+            cursor: cursor.map(str::to_string),
+            limit: Some(2),
+        };
+
+        let first = paginate(items(), &params(None)).unwrap();
+        assert_eq!(first.items, vec![1, 2]);
+        assert_eq!(first.next_cursor.as_deref(), Some("20"));
+        let second = paginate(items(), &params(first.next_cursor.as_deref())).unwrap();
+        assert_eq!(second.items, vec![3, 4]);
+        let last = paginate(items(), &params(second.next_cursor.as_deref())).unwrap();
+        assert_eq!(last.items, vec![5]);
+        assert_eq!(last.next_cursor, None);
+
+        assert!(paginate(items(), &params(Some("x"))).is_err());
+        let all = paginate(items(), &PageParams::default()).unwrap();
+        assert_eq!(all.items.len(), 5);
+    }
+}
@@ -0,0 +1,104 @@
+//! Download-URL prefetching learned from restic's access pattern.
+//!
+//! A restore reads index files and then the packs they point to. Indexes are
+//! encrypted, so instead of parsing them the server remembers which packs
+//! were fetched within a short window after each index. The next time that
+//! index is fetched, download URLs for those packs are resolved in the
+//! background, so the pack GETs that follow skip the `downurl` round trip.
+
+use futures::StreamExt;
+use moka::future::Cache;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::open115::Open115Client;
+
+/// Counter of download URLs resolved ahead of a pack GET.
+pub const PREFETCHED_URLS_TOTAL: &str = "restic115_prefetched_urls_total";
+
+const MAX_TRACKED_INDEXES: u64 = 4096;
+const MAX_PACKS_PER_INDEX: usize = 512;
+const PREFETCH_CONCURRENCY: usize = 4;
+
+pub struct Prefetcher {
+    client: Open115Client,
+    window: Duration,
+    last_index: Mutex<Option<(String, Instant)>>,
+    /// Packs fetched after each index, in first-access order.
+    followers: Cache<String, Arc<Mutex<Vec<String>>>>,
+}
+
+impl Prefetcher {
+    pub fn new(client: Open115Client, window: Duration) -> Arc<Self> {
+        Arc::new(Self {
+            client,
+            window,
+            last_index: Mutex::new(None),
+            followers: Cache::new(MAX_TRACKED_INDEXES),
+        })
+    }
+
+    /// Note that restic fetched index `name`, and prefetch the URLs of the
+    /// packs that followed it last time.
+    pub async fn index_fetched(self: &Arc<Self>, name: &str) {
+        *self.last_index.lock() = Some((name.to_string(), Instant::now()));
+        let Some(packs) = self.followers.get(name).await else {
+            return;
+        };
+        let packs = packs.lock().clone();
+        if packs.is_empty() {
+            return;
+        }
+        let this = self.clone();
+        tokio::spawn(async move { this.prefetch(packs).await });
+    }
+
+    /// Note that restic fetched data pack `name`.
+    pub async fn pack_fetched(&self, name: &str) {
+        let index = match &*self.last_index.lock() {
+            Some((index, at)) if at.elapsed() <= self.window => index.clone(),
+            _ => return,
+        };
+        let packs = self
+            .followers
+            .get_with(index, async { Arc::new(Mutex::new(Vec::new())) })
+            .await;
+        let mut packs = packs.lock();
+        if packs.len() < MAX_PACKS_PER_INDEX && !packs.iter().any(|p| p == name) {
+            packs.push(name.to_string());
+        }
+    }
+
+    async fn prefetch(&self, packs: Vec<String>) {
+        let count = packs.len();
+        let resolved = futures::stream::iter(packs)
+            .map(|name| async move {
+                match self.resolve(&name).await {
+                    Ok(found) => found,
+                    Err(e) => {
+                        tracing::debug!("Prefetch of data/{} failed: {}", name, e);
+                        false
+                    }
+                }
+            })
+            .buffer_unordered(PREFETCH_CONCURRENCY)
+            .filter(|found| std::future::ready(*found))
+            .count()
+            .await;
+        metrics::counter!(PREFETCHED_URLS_TOTAL).increment(resolved as u64);
+        tracing::debug!("Prefetched {} of {} download URLs", resolved, count);
+    }
+
+    /// Resolve (and thereby cache) the download URL of a pack.
+    async fn resolve(&self, name: &str) -> crate::error::Result<bool> {
+        let Some(dir_id) = self.client.find_data_file_dir_id(name).await? else {
+            return Ok(false);
+        };
+        let Some(file) = self.client.find_file(&dir_id, name).await? else {
+            return Ok(false);
+        };
+        self.client.get_download_url(&file.pick_code).await?;
+        Ok(true)
+    }
+}
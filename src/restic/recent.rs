@@ -0,0 +1,109 @@
+//! Read-after-write grace window for metadata objects (`--write-grace`).
+//!
+//! 115 can take a moment before a freshly uploaded file shows up in search
+//! and listings, and restic reads back snapshots, indexes and keys right
+//! after writing them. Uploads of those types are remembered for a short
+//! window, and HEAD, GET and list requests within it are answered from
+//! memory when 115 has not caught up yet.
+
+use bytes::Bytes;
+use moka::future::Cache;
+use std::time::Duration;
+
+use crate::open115::ResticFileType;
+
+/// Cap on the bytes held for the grace window; older uploads are dropped
+/// first when it is exceeded.
+const MAX_RECENT_BYTES: u64 = 256 * 1024 * 1024;
+
+pub struct RecentWrites {
+    objects: Cache<(&'static str, String), Bytes>,
+}
+
+impl RecentWrites {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            objects: Cache::builder()
+                .max_capacity(MAX_RECENT_BYTES)
+                .weigher(|_, data: &Bytes| u32::try_from(data.len()).unwrap_or(u32::MAX))
+                .time_to_live(window)
+                .build(),
+        }
+    }
+
+    /// Whether uploads of `file_type` get a grace window.
+    pub fn tracks(file_type: ResticFileType) -> bool {
+        matches!(
+            file_type,
+            ResticFileType::Snapshots | ResticFileType::Index | ResticFileType::Keys
+        )
+    }
+
+    /// Remember an object that was just uploaded.
+    pub async fn put(&self, file_type: ResticFileType, name: &str, data: Bytes) {
+        if Self::tracks(file_type) {
+            self.objects
+                .insert((file_type.dirname(), name.to_string()), data)
+                .await;
+        }
+    }
+
+    /// Contents of a recent upload still within the window.
+    pub async fn get(&self, file_type: ResticFileType, name: &str) -> Option<Bytes> {
+        self.objects
+            .get(&(file_type.dirname(), name.to_string()))
+            .await
+    }
+
+    /// Forget an object deleted by restic.
+    pub async fn remove(&self, file_type: ResticFileType, name: &str) {
+        self.objects
+            .invalidate(&(file_type.dirname(), name.to_string()))
+            .await;
+    }
+
+    /// Name and size of every recent upload of `file_type`.
+    pub fn list(&self, file_type: ResticFileType) -> Vec<(String, u64)> {
+        self.objects
+            .iter()
+            .filter(|(key, _)| key.0 == file_type.dirname())
+            .map(|(key, data)| (key.1.clone(), data.len() as u64))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_recent_writes_window() {
+        let recent = RecentWrites::new(Duration::from_millis(200));
+        recent
+            .put(ResticFileType::Snapshots, "a1", Bytes::from_static(b"snap"))
+            .await;
+        recent
+            .put(ResticFileType::Data, "b2", Bytes::from_static(b"pack"))
+            .await;
+
+        assert_eq!(
+            recent.get(ResticFileType::Snapshots, "a1").await.unwrap(),
+            "snap"
+        );
+        assert!(recent.get(ResticFileType::Index, "a1").await.is_none());
+        assert!(recent.get(ResticFileType::Data, "b2").await.is_none());
+        assert_eq!(
+            recent.list(ResticFileType::Snapshots),
+            [("a1".to_string(), 4)]
+        );
+
+        recent.remove(ResticFileType::Snapshots, "a1").await;
+        assert!(recent.get(ResticFileType::Snapshots, "a1").await.is_none());
+
+        recent
+            .put(ResticFileType::Keys, "k", Bytes::from_static(b"key"))
+            .await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(recent.get(ResticFileType::Keys, "k").await.is_none());
+    }
+}
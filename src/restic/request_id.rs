@@ -0,0 +1,113 @@
+//! Per-request IDs for log correlation.
+//!
+//! Every request gets an ID, taken from the client's `X-Request-Id` header
+//! when it carries a sane one and generated otherwise. The ID is echoed in
+//! the response header, recorded on the request's tracing span (so the 115
+//! calls made for it log under the same ID) and included in error bodies.
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::Arc;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+/// Longer client-supplied IDs are replaced rather than logged.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: Arc<str>;
+}
+
+/// ID of the request being handled by the current task, if any.
+pub fn current() -> Option<Arc<str>> {
+    REQUEST_ID.try_with(Arc::clone).ok()
+}
+
+/// Middleware assigning the request ID. Must wrap the trace layer so the
+/// span sees the header.
+pub async fn assign(mut req: Request, next: Next) -> Response {
+    let id: Arc<str> = match req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| is_valid(v))
+    {
+        Some(id) => id.into(),
+        None => uuid::Uuid::new_v4().simple().to_string().into(),
+    };
+    let value = HeaderValue::from_str(&id).expect("request ID is visible ASCII");
+    req.headers_mut().insert(REQUEST_ID_HEADER, value.clone());
+
+    let mut response = REQUEST_ID.scope(id, next.run(req)).await;
+    response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    response
+}
+
+/// Span for the trace layer, carrying the request ID.
+pub fn make_span(req: &Request) -> tracing::Span {
+    let id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    tracing::info_span!(
+        "request",
+        method = %req.method(),
+        uri = %req.uri(),
+        request_id = %id,
+    )
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AppError;
+    use axum::{Router, middleware, routing::get};
+
+    async fn spawn() -> String {
+        let app = Router::new()
+            .route(
+                "/missing",
+                get(|| async { AppError::NotFound("missing".into()) }),
+            )
+            .layer(middleware::from_fn(assign));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/missing", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_request_id_in_header_and_error_body() {
+        let url = spawn().await;
+        let client = reqwest::Client::new();
+
+        let resp = client
+            .get(&url)
+            .header("x-request-id", "restic-42")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.headers()["x-request-id"], "restic-42");
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["request_id"], "restic-42");
+
+        let resp = client
+            .get(&url)
+            .header("x-request-id", "has space")
+            .send()
+            .await
+            .unwrap();
+        let id = resp.headers()["x-request-id"].to_str().unwrap().to_string();
+        assert_eq!(id.len(), 32);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["request_id"], id.as_str());
+    }
+}
@@ -0,0 +1,162 @@
+//! Disposable repositories for tests and scripted experiments.
+//!
+//! Every [`ScratchRepo`] lives in its own uniquely named folder under a
+//! shared parent folder and gets its own cache namespace, so any number of
+//! them can run concurrently against one account and one cache DB.
+//! [`ScratchRepo::teardown`] deletes the folder and purges it from the
+//! recycle bin, so experiments leak neither folders nor quota. Folders left
+//! behind by runs that died before teardown are removed by
+//! [`ScratchRepo::sweep`].
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::open115::Open115Client;
+
+/// Distinguishes scratch repositories created in the same millisecond.
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+pub struct ScratchRepo {
+    client: Open115Client,
+    parent: String,
+    name: String,
+    torn_down: bool,
+}
+
+impl ScratchRepo {
+    /// Create and initialize a new repository under `parent` (e.g.
+    /// `/restic-115-tests`), using the account and cache DB of `config`.
+    pub async fn create(config: &Config, parent: &str) -> Result<Self> {
+        let parent = format!("/{}", parent.trim_matches('/'));
+        let name = format!(
+            "{}-{}-{}",
+            chrono::Utc::now().timestamp_millis(),
+            std::process::id(),
+            SEQ.fetch_add(1, Ordering::Relaxed)
+        );
+        let client = Open115Client::new(Config {
+            repo_path: format!("{}/{}", parent.trim_end_matches('/'), name),
+            ..config.clone()
+        })
+        .await?;
+        let repo = Self {
+            client,
+            parent,
+            name,
+            torn_down: false,
+        };
+        repo.client.init_repository().await?;
+        tracing::info!("Created scratch repository {}", repo.path());
+        Ok(repo)
+    }
+
+    /// Client scoped to this repository.
+    pub fn client(&self) -> &Open115Client {
+        &self.client
+    }
+
+    /// Absolute 115 path of the repository folder.
+    pub fn path(&self) -> String {
+        format!("{}/{}", self.parent.trim_end_matches('/'), self.name)
+    }
+
+    /// Delete the repository and purge it, and everything deleted from it,
+    /// from the recycle bin.
+    pub async fn teardown(mut self) -> Result<()> {
+        self.torn_down = true;
+        teardown(&self.client, &self.parent, &self.name).await
+    }
+
+    /// Delete scratch repositories under `parent` created more than
+    /// `older_than` ago, e.g. by test runs that crashed before teardown.
+    /// Returns how many were removed.
+    pub async fn sweep(config: &Config, parent: &str, older_than: Duration) -> Result<usize> {
+        let parent = format!("/{}", parent.trim_matches('/'));
+        let client = Open115Client::new(Config {
+            repo_path: parent.clone(),
+            ..config.clone()
+        })
+        .await?;
+        let parent_id = client.ensure_path(&parent, true).await?;
+        client.refresh_dir(&parent).await?;
+        let cutoff = chrono::Utc::now().timestamp_millis()
+            - i64::try_from(older_than.as_millis()).unwrap_or(i64::MAX);
+
+        let mut removed = 0;
+        for dir in client.list_files(&parent_id).await? {
+            if !dir.is_dir || created_at(&dir.filename).is_none_or(|t| t >= cutoff) {
+                continue;
+            }
+            tracing::info!(
+                "Sweeping stale scratch repository {}/{}",
+                parent,
+                dir.filename
+            );
+            client.delete_file(&parent_id, &dir.file_id).await?;
+            client
+                .purge_deleted_folder(&parent_id, &dir.filename)
+                .await?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+}
+
+impl Drop for ScratchRepo {
+    /// Best effort only: the runtime may stop before the teardown finishes,
+    /// so call [`ScratchRepo::teardown`] explicitly.
+    fn drop(&mut self) {
+        if self.torn_down {
+            return;
+        }
+        tracing::warn!(
+            "Scratch repository {} dropped without teardown",
+            self.path()
+        );
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let client = self.client.clone();
+            let parent = self.parent.clone();
+            let name = self.name.clone();
+            handle.spawn(async move {
+                if let Err(e) = teardown(&client, &parent, &name).await {
+                    tracing::warn!("Scratch repository teardown failed: {}", e);
+                }
+            });
+        }
+    }
+}
+
+async fn teardown(client: &Open115Client, parent: &str, name: &str) -> Result<()> {
+    // Objects deleted during the run sit in the recycle bin under the
+    // repository's folders, which are only known while it still exists.
+    client.purge_recycle_bin(false).await?;
+    let parent_id = client.find_path_id(parent).await?;
+    if client.delete_repository().await?
+        && let Some(parent_id) = parent_id
+    {
+        client.purge_deleted_folder(&parent_id, name).await?;
+    }
+    tracing::info!("Tore down scratch repository {}/{}", parent, name);
+    Ok(())
+}
+
+/// Creation time, in Unix milliseconds, encoded in a scratch folder name.
+fn created_at(name: &str) -> Option<i64> {
+    let (millis, rest) = name.split_once('-')?;
+    rest.contains('-').then(|| millis.parse().ok()).flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_created_at() {
+        assert_eq!(created_at("1700000000000-42-0"), Some(1_700_000_000_000));
+        assert_eq!(created_at("1700000000000"), None);
+        assert_eq!(created_at("restic-backup"), None);
+        assert_eq!(created_at("abc-1-2"), None);
+    }
+}
@@ -0,0 +1,431 @@
+//! HTTP listener for the REST API.
+//!
+//! Connections are served by hyper directly instead of `axum::serve` so the
+//! protocol (HTTP/1.1, HTTP/2 over TLS via ALPN, cleartext h2c) and
+//! keep-alive behavior can be configured.
+//!
+//! The TLS certificate is re-read when its files change (polled every
+//! `--tls-reload-interval`) or on SIGHUP, and new handshakes use it right
+//! away, so certificate renewals do not restart the server or drop the
+//! connections of running backups. With `--acme-domain` the certificate is
+//! instead obtained from Let's Encrypt, answering the TLS-ALPN-01 challenge
+//! on the listener itself, and renewed when a third of its lifetime is left.
+
+use axum::Router;
+use axum::extract::{ConnectInfo, Request};
+use futures::StreamExt;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls_acme::acme::ACME_TLS_ALPN_NAME;
+use rustls_acme::caches::DirCache;
+use rustls_acme::{AcmeConfig, AcmeState};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+
+use crate::config::Config;
+
+/// Connections that have not finished the TLS handshake by then are dropped.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Pause after a failed accept (e.g. out of file descriptors).
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Protocol and keep-alive settings shared by every listener.
+#[derive(Clone)]
+pub struct ServerOptions {
+    tls: Option<TlsAcceptor>,
+    /// Source of the acceptor's certificate, when TLS is on.
+    certs: Option<CertSource>,
+    tls_reload_interval: Option<Duration>,
+    h2c: bool,
+    http1_keep_alive: bool,
+    http2_keep_alive_interval: Option<Duration>,
+    http2_keep_alive_timeout: Duration,
+}
+
+impl ServerOptions {
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let certs = match (&config.tls_cert, &config.tls_key) {
+            _ if !config.acme_domain.is_empty() => Some(CertSource::acme(config)?),
+            (Some(cert), Some(key)) => Some(CertSource::Files(CertReloader::new(cert, key)?)),
+            (None, None) => None,
+            _ => anyhow::bail!("--tls-cert and --tls-key must be given together"),
+        };
+        Ok(Self {
+            tls: certs.as_ref().map(CertSource::acceptor).transpose()?,
+            certs,
+            tls_reload_interval: (config.tls_reload_interval > 0)
+                .then(|| Duration::from_secs(config.tls_reload_interval)),
+            h2c: config.h2c,
+            http1_keep_alive: config.http_keep_alive,
+            http2_keep_alive_interval: config.http2_keep_alive_interval.map(Duration::from_secs),
+            http2_keep_alive_timeout: Duration::from_secs(config.http2_keep_alive_timeout),
+        })
+    }
+
+    /// URL scheme clients use to reach the listener.
+    pub fn scheme(&self) -> &'static str {
+        if self.tls.is_some() { "https" } else { "http" }
+    }
+
+    /// Reload the TLS certificate when its files change and on SIGHUP, or
+    /// obtain and renew it with ACME. Does nothing without TLS.
+    pub fn spawn_tls_reload(&self) {
+        let certs = match &self.certs {
+            None => return,
+            Some(CertSource::Files(certs)) => certs.clone(),
+            Some(CertSource::Acme { state, .. }) => {
+                if let Some(mut state) = state.lock().take() {
+                    tokio::spawn(async move {
+                        while let Some(event) = state.next().await {
+                            match event {
+                                Ok(event) => tracing::info!("ACME: {:?}", event),
+                                Err(e) => tracing::warn!("ACME: {}", e),
+                            }
+                        }
+                    });
+                }
+                return;
+            }
+        };
+        if let Some(interval) = self.tls_reload_interval {
+            let certs = certs.clone();
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(interval);
+                ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                loop {
+                    ticker.tick().await;
+                    certs.reload(false);
+                }
+            });
+        }
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{SignalKind, signal};
+            match signal(SignalKind::hangup()) {
+                Ok(mut hangup) => {
+                    tokio::spawn(async move {
+                        while hangup.recv().await.is_some() {
+                            tracing::info!("SIGHUP: reloading the TLS certificate");
+                            certs.reload(true);
+                        }
+                    });
+                }
+                Err(e) => tracing::warn!("Cannot listen for SIGHUP: {}", e),
+            }
+        }
+    }
+
+    fn builder(&self, http2: bool) -> auto::Builder<TokioExecutor> {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        builder.http1().keep_alive(self.http1_keep_alive);
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .keep_alive_interval(self.http2_keep_alive_interval)
+            .keep_alive_timeout(self.http2_keep_alive_timeout);
+        if http2 { builder } else { builder.http1_only() }
+    }
+}
+
+/// Where the TLS certificate comes from.
+#[derive(Clone)]
+enum CertSource {
+    /// `--tls-cert` and `--tls-key`.
+    Files(Arc<CertReloader>),
+    /// `--acme-domain`. The state is taken by the task driving it.
+    Acme {
+        resolver: Arc<rustls_acme::ResolvesServerCertAcme>,
+        state: Arc<parking_lot::Mutex<Option<AcmeState<std::io::Error>>>>,
+    },
+}
+
+impl CertSource {
+    fn acme(config: &Config) -> anyhow::Result<Self> {
+        let Some(cache_dir) = &config.acme_cache_dir else {
+            anyhow::bail!(
+                "--acme-domain requires --acme-cache-dir, or every restart would order a new certificate"
+            );
+        };
+        let state = AcmeConfig::new_with_provider(
+            &config.acme_domain,
+            Arc::new(rustls::crypto::ring::default_provider()),
+        )
+        .contact(config.acme_email.iter().map(|e| format!("mailto:{e}")))
+        .cache(DirCache::new(cache_dir.clone()))
+        .directory_lets_encrypt(!config.acme_staging)
+        .state();
+        Ok(CertSource::Acme {
+            resolver: state.resolver(),
+            state: Arc::new(parking_lot::Mutex::new(Some(state))),
+        })
+    }
+
+    /// Build a TLS acceptor serving the certificate, offering HTTP/2 and
+    /// HTTP/1.1 via ALPN (and the ACME challenge protocol with ACME).
+    fn acceptor(&self) -> anyhow::Result<TlsAcceptor> {
+        let (resolver, mut alpn): (Arc<dyn ResolvesServerCert>, _) = match self {
+            CertSource::Files(certs) => (certs.clone(), Vec::new()),
+            CertSource::Acme { resolver, .. } => {
+                (resolver.clone(), vec![ACME_TLS_ALPN_NAME.to_vec()])
+            }
+        };
+        let mut config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+        alpn.splice(0..0, [b"h2".to_vec(), b"http/1.1".to_vec()]);
+        config.alpn_protocols = alpn;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// The current certificate and key from a pair of PEM files.
+#[derive(Debug)]
+struct CertReloader {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: parking_lot::RwLock<Arc<CertifiedKey>>,
+    /// Modification times of the files when last loaded.
+    loaded: parking_lot::Mutex<Option<(SystemTime, SystemTime)>>,
+}
+
+impl CertReloader {
+    fn new(cert: &Path, key: &Path) -> anyhow::Result<Arc<Self>> {
+        let loaded = modified(cert, key);
+        Ok(Arc::new(Self {
+            current: parking_lot::RwLock::new(Arc::new(load_certified_key(cert, key)?)),
+            cert_path: cert.to_path_buf(),
+            key_path: key.to_path_buf(),
+            loaded: parking_lot::Mutex::new(loaded),
+        }))
+    }
+
+    /// Re-read the files if they changed since the last load, or always with
+    /// `force`. A pair that fails to load (e.g. a key written after its
+    /// certificate) is logged and the current certificate kept, so the next
+    /// attempt picks it up. Returns whether the certificate was replaced.
+    fn reload(&self, force: bool) -> bool {
+        let mtimes = modified(&self.cert_path, &self.key_path);
+        if !force && mtimes == *self.loaded.lock() {
+            return false;
+        }
+        match load_certified_key(&self.cert_path, &self.key_path) {
+            Ok(key) => {
+                *self.current.write() = Arc::new(key);
+                *self.loaded.lock() = mtimes;
+                tracing::info!("Reloaded TLS certificate {}", self.cert_path.display());
+                true
+            }
+            Err(e) => {
+                tracing::warn!("Keeping the current TLS certificate: {:#}", e);
+                false
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for CertReloader {
+    fn resolve(&self, _hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().clone())
+    }
+}
+
+fn modified(cert: &Path, key: &Path) -> Option<(SystemTime, SystemTime)> {
+    let mtime = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    Some((mtime(cert)?, mtime(key)?))
+}
+
+fn load_certified_key(cert: &Path, key: &Path) -> anyhow::Result<CertifiedKey> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| anyhow::anyhow!("failed to read TLS certificate {}: {e}", cert.display()))?;
+    let key_der = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| anyhow::anyhow!("failed to read TLS key {}: {e}", key.display()))?;
+    let provider = rustls::crypto::ring::default_provider();
+    let certified = CertifiedKey::from_der(certs, key_der, &provider)
+        .map_err(|e| anyhow::anyhow!("invalid TLS key {}: {e}", key.display()))?;
+    Ok(certified)
+}
+
+/// Accept connections on `listener` and serve `app` on each until the
+/// process exits.
+pub async fn serve(listener: TcpListener, app: Router, options: ServerOptions) {
+    let options = Arc::new(options);
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                tracing::warn!("Failed to accept connection: {}", e);
+                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                continue;
+            }
+        };
+        let _ = stream.set_nodelay(true);
+        let service =
+            TowerToHyperService::new(app.clone().map_request(move |mut req: Request<_>| {
+                req.extensions_mut().insert(ConnectInfo(peer));
+                req
+            }));
+        let options = options.clone();
+        tokio::spawn(async move {
+            let result = match &options.tls {
+                Some(acceptor) => {
+                    let stream =
+                        match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+                            .await
+                        {
+                            Ok(Ok(stream)) => stream,
+                            Ok(Err(e)) => {
+                                tracing::debug!("TLS handshake with {} failed: {}", peer, e);
+                                return;
+                            }
+                            Err(_) => {
+                                tracing::debug!("TLS handshake with {} timed out", peer);
+                                return;
+                            }
+                        };
+                    let alpn = stream.get_ref().1.alpn_protocol();
+                    // A TLS-ALPN-01 validation is done once the handshake is.
+                    if alpn == Some(ACME_TLS_ALPN_NAME) {
+                        tracing::info!("Answered ACME TLS-ALPN-01 challenge from {}", peer);
+                        return;
+                    }
+                    let http2 = alpn == Some(b"h2");
+                    options
+                        .builder(http2)
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await
+                }
+                None => {
+                    options
+                        .builder(options.h2c)
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await
+                }
+            };
+            if let Err(e) = result {
+                tracing::debug!("Connection from {} ended with error: {}", peer, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+
+    fn options(h2c: bool) -> ServerOptions {
+        ServerOptions {
+            tls: None,
+            certs: None,
+            tls_reload_interval: None,
+            h2c,
+            http1_keep_alive: true,
+            http2_keep_alive_interval: Some(Duration::from_secs(5)),
+            http2_keep_alive_timeout: Duration::from_secs(5),
+        }
+    }
+
+    async fn spawn(options: ServerOptions) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let app = Router::new().route("/", get(|| async { "ok" }));
+        tokio::spawn(serve(listener, app, options));
+        url
+    }
+
+    #[test]
+    fn test_cert_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert_path, key_path) = (dir.path().join("cert.pem"), dir.path().join("key.pem"));
+        let write = |host: &str| {
+            let cert = rcgen::generate_simple_self_signed(vec![host.to_string()]).unwrap();
+            std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+            std::fs::write(&key_path, cert.key_pair.serialize_pem()).unwrap();
+            cert.cert.der().to_vec()
+        };
+        let current = |certs: &CertReloader| certs.current.read().cert[0].to_vec();
+
+        let first = write("a.test");
+        let certs = CertReloader::new(&cert_path, &key_path).unwrap();
+        assert_eq!(current(&certs), first);
+        assert!(!certs.reload(false));
+
+        // Dated ahead, as a rewrite within the file system's mtime
+        // granularity would otherwise look unchanged.
+        let second = write("b.test");
+        let later = SystemTime::now() + Duration::from_secs(60);
+        for path in [&cert_path, &key_path] {
+            let file = std::fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(later).unwrap();
+        }
+        assert!(certs.reload(false));
+        assert_eq!(current(&certs), second);
+
+        // A broken pair keeps the last good certificate.
+        std::fs::write(&key_path, "not a key").unwrap();
+        assert!(!certs.reload(true));
+        assert_eq!(current(&certs), second);
+    }
+
+    #[test]
+    fn test_acme_options() {
+        use clap::Parser;
+        let parse = |args: &[&str]| {
+            let config =
+                Config::try_parse_from(["restic-115"].iter().chain(args).copied()).unwrap();
+            ServerOptions::from_config(&config)
+        };
+        let err = parse(&["--acme-domain", "backup.example.com"])
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("--acme-cache-dir"), "{err}");
+
+        let dir = tempfile::tempdir().unwrap();
+        let options = parse(&[
+            "--acme-domain",
+            "backup.example.com",
+            "--acme-cache-dir",
+            dir.path().to_str().unwrap(),
+        ])
+        .unwrap();
+        assert_eq!(options.scheme(), "https");
+        assert!(matches!(options.certs, Some(CertSource::Acme { .. })));
+
+        assert!(
+            Config::try_parse_from(["restic-115", "--acme-domain", "a", "--tls-cert", "c"])
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_h2c_is_opt_in() {
+        let h2 = reqwest::Client::builder()
+            .http2_prior_knowledge()
+            .build()
+            .unwrap();
+
+        let url = spawn(options(true)).await;
+        let resp = h2.get(&url).send().await.unwrap();
+        assert_eq!(resp.version(), reqwest::Version::HTTP_2);
+        assert_eq!(resp.text().await.unwrap(), "ok");
+        let resp = reqwest::get(&url).await.unwrap();
+        assert_eq!(resp.version(), reqwest::Version::HTTP_11);
+
+        let url = spawn(options(false)).await;
+        assert!(h2.get(&url).send().await.is_err());
+        assert!(reqwest::get(&url).await.unwrap().status().is_success());
+    }
+}
@@ -0,0 +1,745 @@
+//! Local write-behind spool for data pack uploads.
+//!
+//! POSTed packs are made durable in the spool directory and acknowledged
+//! immediately; background workers then upload them to 115 with retry. The
+//! directory doubles as the journal: a pack is committed once it has been
+//! fsynced and atomically renamed to its final name, so after a crash every
+//! committed file is simply re-queued on startup and partial `.tmp` files are
+//! discarded. Every write gets a new generation, so an upload that finishes
+//! after the pack was POSTed again leaves the newer bytes in place.
+//!
+//! With `--spool-compress`, packs are stored lz4- or zstd-compressed as
+//! `<name>.lz4` / `<name>.zst` whenever that is smaller, and decompressed
+//! again when read or uploaded. restic encrypts packs, so most of them do not
+//! shrink; the saving is reported as a metric.
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, mpsc};
+
+use crate::config::SpoolCompression;
+use crate::error::{AppError, Result};
+use crate::open115::Open115Client;
+use crate::open115::retry::{BackoffStrategy, Exponential};
+
+/// Counter of disk bytes saved by `--spool-compress`, by `codec`.
+pub const SPOOL_COMPRESSION_SAVED_BYTES_TOTAL: &str =
+    "restic115_spool_compression_saved_bytes_total";
+
+const TMP_SUFFIX: &str = ".tmp";
+const LZ4_SUFFIX: &str = ".lz4";
+const ZSTD_SUFFIX: &str = ".zst";
+const ZSTD_LEVEL: i32 = 3;
+/// Spooled packs are already durable, so uploads retry patiently.
+const SPOOL_BACKOFF: Exponential = Exponential {
+    base: Duration::from_secs(2),
+    cap: Duration::from_secs(300),
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EntryState {
+    Queued,
+    Uploading,
+    /// Deleted by restic while an upload was in flight; the worker removes
+    /// the uploaded object once the upload finishes.
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    /// Uncompressed size.
+    size: u64,
+    state: EntryState,
+    /// How the spool file is stored.
+    codec: SpoolCompression,
+    /// Write that produced the spool file; bumped as soon as a newer write
+    /// of the same name starts.
+    generation: u64,
+}
+
+struct Inner {
+    dir: PathBuf,
+    client: Open115Client,
+    compression: SpoolCompression,
+    entries: Mutex<HashMap<String, Entry>>,
+    queue: mpsc::UnboundedSender<String>,
+    drained: Notify,
+    next_generation: AtomicU64,
+    flush_timeout: Duration,
+}
+
+/// Handle to the spool; cheap to clone.
+#[derive(Clone)]
+pub struct Spool {
+    inner: Arc<Inner>,
+}
+
+impl Spool {
+    /// Open (or create) the spool at `dir`, re-queue committed packs left by
+    /// a previous run and start `concurrency` upload workers. New packs are
+    /// stored with `compression`; [`Spool::flush`] gives up after
+    /// `flush_timeout`.
+    pub async fn start(
+        dir: PathBuf,
+        client: Open115Client,
+        concurrency: usize,
+        compression: SpoolCompression,
+        flush_timeout: Duration,
+    ) -> Result<Self> {
+        let data_dir = dir.join("data");
+        tokio::fs::create_dir_all(&data_dir).await?;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let spool = Self {
+            inner: Arc::new(Inner {
+                dir: data_dir.clone(),
+                client,
+                compression,
+                entries: Mutex::new(HashMap::new()),
+                queue: tx,
+                drained: Notify::new(),
+                next_generation: AtomicU64::new(0),
+                flush_timeout,
+            }),
+        };
+
+        let mut recovered = 0usize;
+        let mut read_dir = tokio::fs::read_dir(&data_dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.ends_with(TMP_SUFFIX) {
+                tracing::warn!("Discarding partial spool file {}", name);
+                let _ = tokio::fs::remove_file(entry.path()).await;
+                continue;
+            }
+            let (name, codec) = split_codec(&name);
+            let path = entry.path();
+            let size = tokio::task::spawn_blocking(move || original_size(&path, codec))
+                .await
+                .map_err(|e| AppError::Internal(format!("spool read task failed: {e}")))??;
+            let generation = spool.next_generation();
+            spool.enqueue(name.to_string(), size, codec, generation);
+            recovered += 1;
+        }
+        if recovered > 0 {
+            tracing::info!(
+                "Spool: re-queued {} pending uploads from {}",
+                recovered,
+                dir.display()
+            );
+        }
+
+        let rx = Arc::new(tokio::sync::Mutex::new(rx));
+        for _ in 0..concurrency.max(1) {
+            let spool = spool.clone();
+            let rx = rx.clone();
+            tokio::spawn(async move {
+                loop {
+                    let Some(name) = rx.lock().await.recv().await else {
+                        return;
+                    };
+                    spool.upload_with_retry(&name).await;
+                }
+            });
+        }
+
+        Ok(spool)
+    }
+
+    fn path(&self, name: &str, codec: SpoolCompression) -> PathBuf {
+        self.inner.dir.join(format!("{name}{}", suffix(codec)))
+    }
+
+    /// Spool file of a pending pack, with the codec it is stored with.
+    fn stored(&self, name: &str) -> Option<(PathBuf, SpoolCompression)> {
+        let codec = self.inner.entries.lock().get(name)?.codec;
+        Some((self.path(name, codec), codec))
+    }
+
+    fn next_generation(&self) -> u64 {
+        self.inner.next_generation.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn enqueue(&self, name: String, size: u64, codec: SpoolCompression, generation: u64) {
+        self.inner.entries.lock().insert(
+            name.clone(),
+            Entry {
+                size,
+                state: EntryState::Queued,
+                codec,
+                generation,
+            },
+        );
+        // The receiver lives as long as the workers, i.e. the process.
+        let _ = self.inner.queue.send(name);
+    }
+
+    /// Durably store a pack and queue it for upload.
+    pub async fn put(&self, name: &str, data: Bytes) -> Result<()> {
+        validate_name(name)?;
+        let size = data.len() as u64;
+        let generation = self.next_generation();
+        // Claim the name before replacing the file, so an upload of the older
+        // bytes that finishes meanwhile does not remove the new ones.
+        if let Some(e) = self.inner.entries.lock().get_mut(name) {
+            e.generation = generation;
+        }
+        let dir = self.inner.dir.clone();
+        let file = name.to_string();
+        let compression = self.inner.compression;
+        let (codec, disk_size) =
+            tokio::task::spawn_blocking(move || store(&dir, &file, generation, &data, compression))
+                .await
+                .map_err(|e| AppError::Internal(format!("spool write task failed: {e}")))??;
+        if codec != SpoolCompression::None {
+            metrics::counter!(SPOOL_COMPRESSION_SAVED_BYTES_TOTAL, "codec" => suffix(codec).trim_start_matches('.'))
+                .increment(size - disk_size);
+        }
+
+        let replaced_in_flight = {
+            let entries = self.inner.entries.lock();
+            matches!(entries.get(name), Some(e) if e.state != EntryState::Queued)
+        };
+        if replaced_in_flight {
+            // The in-flight upload may have read the old bytes; upload again.
+            tracing::debug!("Spool: {} rewritten during upload, re-queueing", name);
+        }
+        self.enqueue(name.to_string(), size, codec, generation);
+        Ok(())
+    }
+
+    /// Size of a pack that is still waiting in the spool.
+    pub fn pending_size(&self, name: &str) -> Option<u64> {
+        self.inner
+            .entries
+            .lock()
+            .get(name)
+            .filter(|e| e.state != EntryState::Cancelled)
+            .map(|e| e.size)
+    }
+
+    /// All packs still waiting in the spool as `(name, size)`.
+    pub fn pending(&self) -> Vec<(String, u64)> {
+        self.inner
+            .entries
+            .lock()
+            .iter()
+            .filter(|(_, e)| e.state != EntryState::Cancelled)
+            .map(|(name, e)| (name.clone(), e.size))
+            .collect()
+    }
+
+    /// Read a spooled pack, if it has not been uploaded yet.
+    pub async fn read(&self, name: &str) -> Result<Option<Bytes>> {
+        if self.pending_size(name).is_none() {
+            return Ok(None);
+        }
+        let Some((path, codec)) = self.stored(name) else {
+            return Ok(None);
+        };
+        match load(path, codec).await {
+            Ok(data) => Ok(Some(data)),
+            // Uploaded and removed between the check and the read.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Drop a pending pack. Returns true if the pack was still in the spool.
+    pub async fn remove(&self, name: &str) -> Result<bool> {
+        let mut entries = self.inner.entries.lock();
+        let codec = match entries.get_mut(name) {
+            Some(e) if e.state == EntryState::Queued => {
+                let codec = e.codec;
+                entries.remove(name);
+                codec
+            }
+            Some(e) if e.state == EntryState::Uploading => {
+                e.state = EntryState::Cancelled;
+                e.codec
+            }
+            _ => return Ok(false),
+        };
+        // Unlinked under the lock, like in `finish`.
+        let removed = remove_if_exists(&self.path(name, codec));
+        drop(entries);
+        self.notify_if_drained();
+        removed?;
+        Ok(true)
+    }
+
+    /// Wait until every spooled pack has been uploaded, or fail with
+    /// [`AppError::Timeout`] after the flush timeout. Packs still pending
+    /// then stay queued.
+    pub async fn flush(&self) -> Result<()> {
+        let started = Instant::now();
+        let drained = async {
+            loop {
+                let drained = self.inner.drained.notified();
+                if self.inner.entries.lock().is_empty() {
+                    return;
+                }
+                drained.await;
+            }
+        };
+        tokio::time::timeout(self.inner.flush_timeout, drained)
+            .await
+            .map_err(|_| AppError::Timeout {
+                operation: format!("spool flush ({} uploads pending)", self.pending().len()),
+                elapsed_secs: started.elapsed().as_secs(),
+                retries: Vec::new(),
+            })
+    }
+
+    fn notify_if_drained(&self) {
+        if self.inner.entries.lock().is_empty() {
+            self.inner.drained.notify_waiters();
+        }
+    }
+
+    async fn upload_with_retry(&self, name: &str) {
+        let mut attempt = 0usize;
+        loop {
+            let generation = {
+                let mut entries = self.inner.entries.lock();
+                match entries.get_mut(name) {
+                    Some(e) if e.state == EntryState::Queued => {
+                        e.state = EntryState::Uploading;
+                        e.generation
+                    }
+                    // Removed, or a duplicate queue message for an entry
+                    // another worker is already handling.
+                    _ => return,
+                }
+            };
+
+            match self.upload_once(name).await {
+                Ok(()) => {
+                    self.finish(name, generation).await;
+                    return;
+                }
+                Err(AppError::Conflict(msg)) => {
+                    // Permanent under --duplicate-policy=reject; retrying can't help.
+                    tracing::error!("Spool: dropping {}: {}", name, msg);
+                    let mut entries = self.inner.entries.lock();
+                    match entries.get(name) {
+                        Some(e) if e.generation == generation => {
+                            let _ = remove_if_exists(&self.path(name, e.codec));
+                            entries.remove(name);
+                        }
+                        // Re-posted meanwhile; the newer bytes are queued.
+                        _ => {}
+                    }
+                    drop(entries);
+                    self.notify_if_drained();
+                    return;
+                }
+                Err(e) => {
+                    attempt += 1;
+                    let delay = SPOOL_BACKOFF.delay(attempt);
+                    tracing::warn!(
+                        "Spool upload of {} failed (attempt {}), retrying in {}s: {}",
+                        name,
+                        attempt,
+                        delay.as_secs(),
+                        e
+                    );
+                    {
+                        let mut entries = self.inner.entries.lock();
+                        match entries.get_mut(name) {
+                            Some(e) if e.state == EntryState::Cancelled => {
+                                entries.remove(name);
+                                drop(entries);
+                                self.notify_if_drained();
+                                return;
+                            }
+                            Some(e) => e.state = EntryState::Queued,
+                            None => return,
+                        }
+                    }
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+    }
+
+    async fn upload_once(&self, name: &str) -> Result<()> {
+        let (path, codec) = self
+            .stored(name)
+            .ok_or_else(|| AppError::NotFound(name.to_string()))?;
+        let data = load(path, codec).await?;
+        let client = &self.inner.client;
+        let dir_id = client.get_data_file_dir_id(name).await?;
+        tracing::info!("Spool: uploading data/{} ({} bytes)", name, data.len());
+        client.upload_file(&dir_id, name, data).await
+    }
+
+    /// Retire the upload of `generation`. The file is unlinked while holding
+    /// the lock, so `put` either claimed the name first (and the file is
+    /// kept) or renames its new file afterwards.
+    async fn finish(&self, name: &str, generation: u64) {
+        let cancelled = {
+            let mut entries = self.inner.entries.lock();
+            let Some(entry) = entries.get_mut(name) else {
+                return;
+            };
+            match entry.state {
+                EntryState::Uploading if entry.generation == generation => {
+                    if let Err(e) = remove_if_exists(&self.path(name, entry.codec)) {
+                        tracing::warn!("Spool: failed to remove uploaded {}: {}", name, e);
+                    }
+                    entries.remove(name);
+                    false
+                }
+                EntryState::Cancelled if entry.generation == generation => {
+                    entries.remove(name);
+                    true
+                }
+                // Re-posted while uploading: upload the newer bytes. If their
+                // write failed, this retries the file that is there.
+                EntryState::Uploading | EntryState::Cancelled => {
+                    entry.state = EntryState::Queued;
+                    let _ = self.inner.queue.send(name.to_string());
+                    false
+                }
+                EntryState::Queued => false,
+            }
+        };
+        if cancelled && let Err(e) = self.delete_uploaded(name).await {
+            tracing::warn!("Spool: failed to delete cancelled {} on 115: {}", name, e);
+        }
+        self.notify_if_drained();
+    }
+
+    async fn delete_uploaded(&self, name: &str) -> Result<()> {
+        let client = &self.inner.client;
+        if let Some(dir_id) = client.find_data_file_dir_id(name).await?
+            && let Some(file) = client.find_file(&dir_id, name).await?
+        {
+            client.delete_file(&dir_id, &file.file_id).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `name` can be a file in the spool directory on every platform, so
+/// a spool moved between hosts stays valid: besides path separators and the
+/// spool's own suffixes, Windows forbids a trailing `.` and device names such
+/// as `NUL` or `com1.txt`.
+fn validate_name(name: &str) -> Result<()> {
+    let ok = !name.is_empty()
+        && !name.starts_with('.')
+        && !name.ends_with('.')
+        && !is_device_name(name)
+        && !name.ends_with(TMP_SUFFIX)
+        && split_codec(name).1 == SpoolCompression::None
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if ok {
+        Ok(())
+    } else {
+        Err(AppError::BadRequest(format!("Invalid object name: {name}")))
+    }
+}
+
+/// Whether `name` is a Windows device name, with or without an extension.
+fn is_device_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).to_ascii_uppercase();
+    match stem.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        _ => {
+            (stem.starts_with("COM") || stem.starts_with("LPT"))
+                && matches!(stem.as_bytes()[3..], [b'1'..=b'9'])
+        }
+    }
+}
+
+fn suffix(codec: SpoolCompression) -> &'static str {
+    match codec {
+        SpoolCompression::None => "",
+        SpoolCompression::Lz4 => LZ4_SUFFIX,
+        SpoolCompression::Zstd => ZSTD_SUFFIX,
+    }
+}
+
+/// Split a spool file name into the pack name and its codec.
+fn split_codec(file: &str) -> (&str, SpoolCompression) {
+    if let Some(name) = file.strip_suffix(LZ4_SUFFIX) {
+        (name, SpoolCompression::Lz4)
+    } else if let Some(name) = file.strip_suffix(ZSTD_SUFFIX) {
+        (name, SpoolCompression::Zstd)
+    } else {
+        (file, SpoolCompression::None)
+    }
+}
+
+/// Durably write a pack, compressed with `compression` if that makes it
+/// smaller, and remove any copy stored with another codec. Returns the codec
+/// used and the size on disk.
+fn store(
+    dir: &Path,
+    name: &str,
+    generation: u64,
+    data: &[u8],
+    compression: SpoolCompression,
+) -> std::io::Result<(SpoolCompression, u64)> {
+    let compressed = match compression {
+        SpoolCompression::None => None,
+        SpoolCompression::Lz4 => Some(lz4_flex::compress_prepend_size(data)),
+        SpoolCompression::Zstd => Some(zstd::bulk::compress(data, ZSTD_LEVEL)?),
+    }
+    .filter(|c| c.len() < data.len());
+    let codec = if compressed.is_some() {
+        compression
+    } else {
+        SpoolCompression::None
+    };
+    let path = dir.join(format!("{name}{}", suffix(codec)));
+    // Concurrent writes of one name must not share a temporary file.
+    let tmp = dir.join(format!("{name}.{generation}{TMP_SUFFIX}"));
+    let contents = compressed.as_deref().unwrap_or(data);
+    write_durably(&tmp, &path, contents)?;
+    for other in [
+        SpoolCompression::None,
+        SpoolCompression::Lz4,
+        SpoolCompression::Zstd,
+    ] {
+        if other != codec {
+            remove_if_exists(&dir.join(format!("{name}{}", suffix(other))))?;
+        }
+    }
+    Ok((codec, contents.len() as u64))
+}
+
+/// Read a spool file and decompress it.
+async fn load(path: PathBuf, codec: SpoolCompression) -> std::io::Result<Bytes> {
+    let raw = tokio::fs::read(path).await?;
+    let data = match codec {
+        SpoolCompression::None => raw,
+        SpoolCompression::Lz4 => tokio::task::spawn_blocking(move || {
+            lz4_flex::decompress_size_prepended(&raw).map_err(std::io::Error::other)
+        })
+        .await
+        .map_err(std::io::Error::other)??,
+        SpoolCompression::Zstd => {
+            tokio::task::spawn_blocking(move || zstd::decode_all(raw.as_slice()))
+                .await
+                .map_err(std::io::Error::other)??
+        }
+    };
+    Ok(Bytes::from(data))
+}
+
+/// Uncompressed size of a spool file, read from the compressed frame header
+/// so recovery does not decompress every pack.
+fn original_size(path: &Path, codec: SpoolCompression) -> std::io::Result<u64> {
+    use std::io::Read;
+
+    let invalid = || {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("corrupt spool file {}", path.display()),
+        )
+    };
+    match codec {
+        SpoolCompression::None => Ok(std::fs::metadata(path)?.len()),
+        SpoolCompression::Lz4 => {
+            let mut len = [0u8; 4];
+            std::fs::File::open(path)?.read_exact(&mut len)?;
+            Ok(u32::from_le_bytes(len) as u64)
+        }
+        SpoolCompression::Zstd => {
+            let mut header = Vec::with_capacity(18);
+            std::fs::File::open(path)?
+                .take(18)
+                .read_to_end(&mut header)?;
+            zstd::zstd_safe::get_frame_content_size(&header)
+                .ok()
+                .flatten()
+                .ok_or_else(invalid)
+        }
+    }
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn write_durably(tmp: &Path, path: &Path, data: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut file = std::fs::File::create(tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(tmp, path)?;
+    // Persist the rename itself; directories can't be opened for sync on Windows.
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        std::fs::File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use clap::Parser;
+
+    /// Spool without upload workers; tests drive uploads by hand.
+    async fn idle_spool(dir: &Path, flush_timeout: Duration) -> Spool {
+        let config = Config::try_parse_from([
+            "restic-115",
+            "--db-path",
+            ":memory:",
+            "--access-token",
+            "unused",
+            "--refresh-token",
+            "unused",
+        ])
+        .unwrap();
+        let (queue, rx) = mpsc::unbounded_channel();
+        // Keep the receiver so queueing succeeds.
+        std::mem::forget(rx);
+        Spool {
+            inner: Arc::new(Inner {
+                dir: dir.to_path_buf(),
+                client: Open115Client::new(config).await.unwrap(),
+                compression: SpoolCompression::None,
+                entries: Mutex::new(HashMap::new()),
+                queue,
+                drained: Notify::new(),
+                next_generation: AtomicU64::new(0),
+                flush_timeout,
+            }),
+        }
+    }
+
+    /// Mark `name` as taken by a worker, returning the generation it uploads.
+    fn start_upload(spool: &Spool, name: &str) -> u64 {
+        let mut entries = spool.inner.entries.lock();
+        let entry = entries.get_mut(name).unwrap();
+        entry.state = EntryState::Uploading;
+        entry.generation
+    }
+
+    #[tokio::test]
+    async fn test_finish_keeps_reposted_pack() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = idle_spool(dir.path(), Duration::from_secs(1)).await;
+        spool.put("ab12", Bytes::from_static(b"old")).await.unwrap();
+        let uploading = start_upload(&spool, "ab12");
+
+        // restic POSTs the pack again before the upload of the old bytes ends.
+        spool.put("ab12", Bytes::from_static(b"new")).await.unwrap();
+        spool.finish("ab12", uploading).await;
+        assert_eq!(spool.read("ab12").await.unwrap().unwrap(), "new");
+
+        // Even when the new write only claimed the name so far.
+        let uploading = start_upload(&spool, "ab12");
+        spool
+            .inner
+            .entries
+            .lock()
+            .get_mut("ab12")
+            .unwrap()
+            .generation += 1;
+        spool.finish("ab12", uploading).await;
+        assert_eq!(spool.pending_size("ab12"), Some(3));
+        assert!(dir.path().join("ab12").exists());
+
+        let uploading = start_upload(&spool, "ab12");
+        spool.finish("ab12", uploading).await;
+        assert_eq!(spool.pending_size("ab12"), None);
+        assert!(!dir.path().join("ab12").exists());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_puts_of_one_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = idle_spool(dir.path(), Duration::from_secs(1)).await;
+        let puts: Vec<_> = (0..16)
+            .map(|_| {
+                let spool = spool.clone();
+                tokio::spawn(async move { spool.put("ab12", Bytes::from(vec![1u8; 4096])).await })
+            })
+            .collect();
+        for put in puts {
+            put.await.unwrap().unwrap();
+        }
+        assert_eq!(spool.read("ab12").await.unwrap().unwrap(), vec![1u8; 4096]);
+        let files: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(files, ["ab12"]);
+    }
+
+    #[tokio::test]
+    async fn test_flush_gives_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = idle_spool(dir.path(), Duration::from_millis(50)).await;
+        spool.flush().await.unwrap();
+
+        // Nothing uploads this pack, as if every attempt failed.
+        spool.put("ab12", Bytes::from_static(b"abc")).await.unwrap();
+        assert!(matches!(spool.flush().await, Err(AppError::Timeout { .. })));
+        assert_eq!(spool.pending_size("ab12"), Some(3));
+
+        let uploading = start_upload(&spool, "ab12");
+        spool.finish("ab12", uploading).await;
+        spool.flush().await.unwrap();
+    }
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("0123abcd").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("..").is_err());
+        assert!(validate_name("a/b").is_err());
+        assert!(validate_name("abcd.tmp").is_err());
+        assert!(validate_name("abcd.lz4").is_err());
+        assert!(validate_name("abcd.").is_err());
+        assert!(validate_name("nul").is_err());
+        assert!(validate_name("Com1.txt").is_err());
+        assert!(validate_name("lpt9").is_err());
+        assert!(validate_name("com0").is_ok());
+        assert!(validate_name("console").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_compressed_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = vec![7u8; 64 * 1024];
+        for codec in [SpoolCompression::Lz4, SpoolCompression::Zstd] {
+            let (stored, disk_size) = store(dir.path(), "ab12", 0, &data, codec).unwrap();
+            assert_eq!(stored, codec);
+            assert!(disk_size < data.len() as u64);
+            let path = dir.path().join(format!("ab12{}", suffix(codec)));
+            assert_eq!(original_size(&path, codec).unwrap(), data.len() as u64);
+            assert_eq!(load(path, codec).await.unwrap(), data);
+        }
+        // Only one copy is kept.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // Incompressible packs are stored as received.
+        let noise: Vec<u8> = (0..256u32)
+            .flat_map(|i| {
+                use sha1::Digest;
+                sha1::Sha1::digest(i.to_le_bytes())
+            })
+            .collect();
+        let (stored, _) = store(dir.path(), "cd34", 0, &noise, SpoolCompression::Lz4).unwrap();
+        assert_eq!(stored, SpoolCompression::None);
+    }
+}
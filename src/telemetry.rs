@@ -0,0 +1,112 @@
+//! Prometheus metrics exported at `/metrics`.
+//!
+//! Recording goes through the `metrics` facade and is a no-op until
+//! [`install`] has been called, so instrumented code paths cost nothing when
+//! metrics are disabled.
+
+use metrics_exporter_prometheus::{BuildError, Matcher, PrometheusBuilder, PrometheusHandle};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::open115::ResticFileType;
+
+/// Size of each byte range served to restic.
+pub const READ_RANGE_SIZE_BYTES: &str = "restic115_read_range_size_bytes";
+/// Start offset of each byte range served to restic.
+pub const READ_RANGE_OFFSET_BYTES: &str = "restic115_read_range_offset_bytes";
+
+/// Time spent handling each restic request, by `type` and `op`.
+pub const REQUEST_DURATION_SECONDS: &str = "restic115_request_duration_seconds";
+/// Time spent in each upstream call, by `phase` (`downurl`, `upload_init`,
+/// `oss_put`).
+pub const UPSTREAM_DURATION_SECONDS: &str = "restic115_upstream_duration_seconds";
+
+/// Power-of-four buckets from 1 KiB to 1 GiB.
+const BYTE_BUCKETS: &[f64] = &[
+    1024.0,
+    4096.0,
+    16384.0,
+    65536.0,
+    262144.0,
+    1048576.0,
+    4194304.0,
+    16777216.0,
+    67108864.0,
+    268435456.0,
+    1073741824.0,
+];
+
+/// Latency buckets from 5 ms to 5 minutes.
+const SECONDS_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0,
+];
+
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Install the global Prometheus recorder. Must run inside a Tokio runtime.
+pub fn install() -> Result<(), BuildError> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_bytes".to_string()), BYTE_BUCKETS)?
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), SECONDS_BUCKETS)?
+        .install_recorder()?;
+
+    let upkeep = handle.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(UPKEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            upkeep.run_upkeep();
+        }
+    });
+
+    let _ = HANDLE.set(handle);
+    Ok(())
+}
+
+/// Render the current metrics in Prometheus text format, if enabled.
+pub fn render() -> Option<String> {
+    HANDLE.get().map(|h| h.render())
+}
+
+/// Record a read served to restic.
+///
+/// `range` is `None` for whole-object reads, which are recorded as a range
+/// starting at offset 0 covering `len` bytes.
+pub fn record_read(file_type: ResticFileType, range: Option<(u64, u64)>, len: u64) {
+    let kind = if range.is_some() { "range" } else { "full" };
+    let offset = range.map_or(0, |(start, _)| start);
+    let labels = [("type", file_type.dirname()), ("kind", kind)];
+    metrics::histogram!(READ_RANGE_SIZE_BYTES, &labels).record(len as f64);
+    metrics::histogram!(READ_RANGE_OFFSET_BYTES, &labels).record(offset as f64);
+}
+
+/// Records the time from its creation until it is dropped in a latency
+/// histogram, so early returns and errors are timed too.
+pub struct Timer {
+    histogram: metrics::Histogram,
+    start: Instant,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.histogram.record(self.start.elapsed().as_secs_f64());
+    }
+}
+
+/// Time a restic request; `op` is `list`, `head`, `get`, `post` or `delete`.
+pub fn time_request(file_type: ResticFileType, op: &'static str) -> Timer {
+    Timer {
+        histogram: metrics::histogram!(REQUEST_DURATION_SECONDS, "type" => file_type.dirname(), "op" => op),
+        start: Instant::now(),
+    }
+}
+
+/// Time one upstream call.
+pub fn time_upstream(phase: &'static str) -> Timer {
+    Timer {
+        histogram: metrics::histogram!(UPSTREAM_DURATION_SECONDS, "phase" => phase),
+        start: Instant::now(),
+    }
+}
@@ -0,0 +1,132 @@
+//! Webhook delivery of repository events (`--webhook`).
+//!
+//! Each event is POSTed as the same JSON object `/admin/events` returns,
+//! plus a human-readable `text` field so chat services such as Slack show a
+//! message. ntfy and healthchecks.io accept any body, so pointing a webhook
+//! at a topic or check URL is enough. Delivery runs in the background, is
+//! retried a few times and never holds up restic.
+
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use crate::config::WebhookTarget;
+use crate::events::{Event, EventBus};
+
+/// Counter of webhook deliveries, by `result` (`ok` or `failed`).
+pub const WEBHOOK_DELIVERIES_TOTAL: &str = "restic115_webhook_deliveries_total";
+
+const DELIVERY_ATTEMPTS: u32 = 3;
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Deliver every event emitted on `events` to the matching `targets` for as
+/// long as the process runs.
+pub fn spawn(events: &EventBus, targets: Vec<WebhookTarget>) -> reqwest::Result<()> {
+    if targets.is_empty() {
+        return Ok(());
+    }
+    let http = reqwest::Client::builder()
+        .timeout(DELIVERY_TIMEOUT)
+        .user_agent(concat!("restic-115/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    let targets = Arc::new(targets);
+    let mut rx = events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let event = match rx.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    tracing::warn!("Webhooks: skipped {} events", missed);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+            let body = payload(&event);
+            for target in targets.iter().filter(|t| t.wants(event.name())) {
+                let http = http.clone();
+                let url = target.url.clone();
+                let body = body.clone();
+                tokio::spawn(async move { deliver(&http, &url, &body).await });
+            }
+        }
+    });
+    Ok(())
+}
+
+fn payload(event: &Event) -> serde_json::Value {
+    let mut body = json!(event);
+    body["text"] = event.summary().into();
+    body
+}
+
+async fn deliver(http: &reqwest::Client, url: &str, body: &serde_json::Value) {
+    let event = body["event"].as_str().unwrap_or_default();
+    for attempt in 1..=DELIVERY_ATTEMPTS {
+        let error = match http.post(url).json(body).send().await {
+            Ok(resp) if resp.status().is_success() => {
+                metrics::counter!(WEBHOOK_DELIVERIES_TOTAL, "result" => "ok").increment(1);
+                return;
+            }
+            Ok(resp) => format!("HTTP {}", resp.status()),
+            Err(e) => e.to_string(),
+        };
+        // Never log the URL itself; webhook URLs usually embed a secret.
+        tracing::warn!(
+            "Webhook delivery of {} failed (attempt {}/{}): {}",
+            event,
+            attempt,
+            DELIVERY_ATTEMPTS,
+            error.replace(url, "<webhook>")
+        );
+        if attempt < DELIVERY_ATTEMPTS {
+            tokio::time::sleep(RETRY_DELAY).await;
+        }
+    }
+    metrics::counter!(WEBHOOK_DELIVERIES_TOTAL, "result" => "failed").increment(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, routing::post};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_delivers_matching_events() {
+        let (tx, mut rx) = mpsc::unbounded_channel::<serde_json::Value>();
+        let app = Router::new().route(
+            "/hook",
+            post(move |Json(body): Json<serde_json::Value>| {
+                let tx = tx.clone();
+                async move {
+                    let _ = tx.send(body);
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let events = EventBus::new();
+        let target: WebhookTarget = format!("repo_initialized={url}").parse().unwrap();
+        spawn(&events, vec![target]).unwrap();
+        events.emit(Event::BackupStarted {
+            repo: "r".into(),
+            timestamp: 1,
+        });
+        events.emit(Event::RepoInitialized {
+            repo: "r".into(),
+            timestamp: 2,
+        });
+
+        let body = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(body["event"], "repo_initialized");
+        assert_eq!(body["timestamp"], 2);
+        assert_eq!(body["text"], "restic-115 r: repository initialized");
+    }
+}
@@ -1,22 +1,28 @@
+use clap::Parser;
 use restic_115::{config::Config, open115::Open115Client};
 use std::env;
 
 async fn get_test_config(repo_path: &str) -> Option<Config> {
     let access = env::var("OPEN115_ACCESS_TOKEN").ok()?;
     let refresh = env::var("OPEN115_REFRESH_TOKEN").ok()?;
-    Some(Config {
-        access_token: Some(access),
-        refresh_token: Some(refresh),
-        repo_path: repo_path.to_string(),
-        listen_addr: "127.0.0.1".to_string(),
-        listen_port: 0,
-        log_level: "info".to_string(),
-        api_base: "https://proapi.115.com".to_string(),
-        user_agent: "restic-115-tests".to_string(),
-        callback_server: "https://api.oplist.org/115cloud/callback".to_string(),
-        db_path: "test-persistence.db".to_string(),
-        force_cache_rebuild: false,
-    })
+    Some(
+        Config::try_parse_from([
+            "restic-115",
+            "--access-token",
+            &access,
+            "--refresh-token",
+            &refresh,
+            "--repo-path",
+            repo_path,
+            "--listen-port",
+            "0",
+            "--user-agent",
+            "restic-115-tests",
+            "--db-path",
+            "test-persistence.db",
+        ])
+        .unwrap(),
+    )
 }
 
 #[tokio::test]
@@ -6,10 +6,9 @@
 
 use std::env;
 use std::fs;
-use std::io::{Read};
 use std::io::Write;
 use std::net::TcpListener;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Output, Stdio};
 use std::thread::JoinHandle;
 use std::time::Duration;
@@ -30,10 +29,10 @@ fn wait_for_server(port: u16, timeout: Duration) -> bool {
     let start = std::time::Instant::now();
     let url = format!("http://127.0.0.1:{}/", port);
     while start.elapsed() < timeout {
-        if let Ok(resp) = reqwest::blocking::get(&url) {
-            if resp.status().is_client_error() || resp.status().is_success() {
-                return true;
-            }
+        if let Ok(resp) = reqwest::blocking::get(&url)
+            && (resp.status().is_client_error() || resp.status().is_success())
+        {
+            return true;
         }
         std::thread::sleep(Duration::from_millis(100));
     }
@@ -80,7 +79,7 @@ fn run_with_timeout(mut cmd: Command, timeout: Duration, label: &str) -> Output
     }
 }
 
-fn create_test_files(dir: &PathBuf) {
+fn create_test_files(dir: &Path) {
     let mut file1 = fs::File::create(dir.join("test1.txt")).expect("Failed to create file");
     writeln!(file1, "This is test file 1").unwrap();
 
@@ -96,12 +95,35 @@ fn create_test_files(dir: &PathBuf) {
     binary.write_all(&[0u8, 1, 2, 3, 4, 5, 255, 254, 253]).unwrap();
 }
 
-/// Create ~100MB of random, incompressible data using /dev/urandom.
+/// Portable xorshift64* byte stream: incompressible enough for restic and
+/// available on every platform, unlike `/dev/urandom`.
+struct Noise(u64);
+
+impl Noise {
+    fn new() -> Self {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Self(seed | 1)
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            let word = self.0.wrapping_mul(0x2545_f491_4f6c_dd1d).to_le_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+    }
+}
+
+/// Create ~100MB of random, incompressible data.
 /// This mirrors the `restic-123pan` large-scale test strategy.
-fn create_large_test_files(dir: &PathBuf, total_size_mb: usize) {
+fn create_large_test_files(dir: &Path, total_size_mb: usize) {
     use std::io::BufWriter;
 
-    let mut urandom = fs::File::open("/dev/urandom").expect("Failed to open /dev/urandom");
+    let mut noise = Noise::new();
     let total_bytes = total_size_mb * 1024 * 1024;
 
     // Mix:
@@ -133,7 +155,7 @@ fn create_large_test_files(dir: &PathBuf, total_size_mb: usize) {
         while written < size {
             let to_write = (size - written).min(chunk_size);
             let mut buf = vec![0u8; to_write];
-            urandom.read_exact(&mut buf).expect("Failed to read urandom");
+            noise.fill(&mut buf);
             writer.write_all(&buf).expect("Failed to write");
             written += to_write;
         }
@@ -152,7 +174,7 @@ fn create_large_test_files(dir: &PathBuf, total_size_mb: usize) {
         while written < size {
             let to_write = (size - written).min(chunk_size);
             let mut buf = vec![0u8; to_write];
-            urandom.read_exact(&mut buf).expect("Failed to read urandom");
+            noise.fill(&mut buf);
             writer.write_all(&buf).expect("Failed to write");
             written += to_write;
         }
@@ -167,7 +189,7 @@ fn create_large_test_files(dir: &PathBuf, total_size_mb: usize) {
         let path = small_dir.join(format!("small_{:04}.bin", file_counter));
         let mut file = fs::File::create(&path).expect("Failed to create small file");
         let mut buf = vec![0u8; size];
-        urandom.read_exact(&mut buf).expect("Failed to read urandom");
+        noise.fill(&mut buf);
         file.write_all(&buf).expect("Failed to write");
         small_created += size;
         file_counter += 1;
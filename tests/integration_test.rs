@@ -5,9 +5,11 @@
 //! - OPEN115_REFRESH_TOKEN
 
 use bytes::Bytes;
-use restic_115::{config::Config, open115::Open115Client};
+use clap::Parser;
+use restic_115::{config::Config, open115::Open115Client, scratch::ScratchRepo};
 use std::env;
 use std::sync::Once;
+use std::time::Duration;
 
 fn init_tracing_once() {
     static INIT: Once = Once::new();
@@ -35,23 +37,46 @@ macro_rules! skip_if_no_tokens {
     };
 }
 
-async fn make_test_client(repo_path: &str) -> Option<Open115Client> {
+/// Parent folder of the scratch repositories created by these tests.
+const SCRATCH_PARENT: &str = "/restic-115-tests";
+
+fn make_test_config(repo_path: &str) -> Option<Config> {
     let (access, refresh) = get_test_tokens()?;
-    Open115Client::new(Config {
-        access_token: Some(access),
-        refresh_token: Some(refresh),
-        repo_path: repo_path.to_string(),
-        listen_addr: "127.0.0.1".to_string(),
-        listen_port: 0,
-        log_level: "info".to_string(),
-        api_base: "https://proapi.115.com".to_string(),
-        user_agent: "restic-115-tests".to_string(),
-        callback_server: "https://api.oplist.org/115cloud/callback".to_string(),
-        db_path: "test-integration.db".to_string(),
-        force_cache_rebuild: false,
-    })
-    .await
-    .ok()
+    Some(
+        Config::try_parse_from([
+            "restic-115",
+            "--access-token",
+            &access,
+            "--refresh-token",
+            &refresh,
+            "--repo-path",
+            repo_path,
+            "--listen-port",
+            "0",
+            "--user-agent",
+            "restic-115-tests",
+            "--db-path",
+            "test-integration.db",
+        ])
+        .unwrap(),
+    )
+}
+
+async fn make_test_client(repo_path: &str) -> Option<Open115Client> {
+    Open115Client::new(make_test_config(repo_path)?).await.ok()
+}
+
+async fn make_scratch_repo() -> Option<ScratchRepo> {
+    match ScratchRepo::create(&make_test_config("/")?, SCRATCH_PARENT).await {
+        Ok(repo) => Some(repo),
+        Err(e) => {
+            eprintln!(
+                "Failed to create scratch repository (maybe rate limited): {:?}",
+                e
+            );
+            None
+        }
+    }
 }
 
 fn unique_repo_path(prefix: &str) -> String {
@@ -81,41 +106,35 @@ async fn test_refresh_token() {
 async fn test_create_list_delete_directory() {
     skip_if_no_tokens!();
     init_tracing_once();
-    let repo_path = unique_repo_path("restic-115-integration-dir");
-    let client = make_test_client(&repo_path).await.unwrap();
-
-    let dir_id = match client.ensure_path(&repo_path, false).await {
-        Ok(id) => id,
-        Err(e) => {
-            eprintln!("Failed to create directory (maybe rate limited): {:?}", e);
-            return;
-        }
+    let Some(repo) = make_scratch_repo().await else {
+        return;
     };
+    let client = repo.client();
 
+    let dir_id = client
+        .find_path_id(&repo.path())
+        .await
+        .unwrap()
+        .expect("scratch repository not cached");
     let listing = client.list_files(&dir_id).await;
     assert!(listing.is_ok(), "list_files should work");
 
-    // cleanup
-    let _ = client.delete_file("0", &dir_id).await;
+    repo.teardown().await.expect("teardown failed");
 }
 
 #[tokio::test]
 async fn test_upload_and_download_small_file() {
     skip_if_no_tokens!();
     init_tracing_once();
-    let repo_path = unique_repo_path("restic-115-integration-upload");
-    let client = make_test_client(&repo_path).await.unwrap();
-
-    let dir_id = match client.ensure_path(&repo_path, false).await {
-        Ok(id) => id,
-        Err(e) => {
-            eprintln!("Failed to create directory (maybe rate limited): {:?}", e);
-            return;
-        }
+    let Some(repo) = make_scratch_repo().await else {
+        return;
     };
-
-    // init cache
-    let _ = client.list_files(&dir_id).await;
+    let client = repo.client();
+    let dir_id = client
+        .find_path_id(&repo.path())
+        .await
+        .unwrap()
+        .expect("scratch repository not cached");
 
     // Use unique content each run to avoid 115 "fast upload" (秒传, status=2),
     // so we can observe the real OSS PutObject + callback response and compare ids.
@@ -128,6 +147,7 @@ async fn test_upload_and_download_small_file() {
         .await;
     if let Err(e) = uploaded {
         eprintln!("Upload failed (maybe API shape changed): {:?}", e);
+        repo.teardown().await.expect("teardown failed");
         return;
     }
 
@@ -147,6 +167,21 @@ async fn test_upload_and_download_small_file() {
         .expect("download failed");
     assert_eq!(downloaded, content);
 
-    let _ = client.delete_file(&dir_id, &info.file_id).await;
-    let _ = client.delete_file("0", &dir_id).await;
+    client
+        .delete_file(&dir_id, &info.file_id)
+        .await
+        .expect("delete failed");
+    repo.teardown().await.expect("teardown failed");
+}
+
+#[tokio::test]
+async fn test_sweep_stale_scratch_repos() {
+    skip_if_no_tokens!();
+    init_tracing_once();
+    let config = make_test_config("/").unwrap();
+    // Leftovers of earlier runs that died before teardown.
+    match ScratchRepo::sweep(&config, SCRATCH_PARENT, Duration::from_secs(24 * 60 * 60)).await {
+        Ok(removed) => eprintln!("Swept {removed} stale scratch repositories"),
+        Err(e) => eprintln!("Sweep failed (maybe rate limited): {:?}", e),
+    }
 }
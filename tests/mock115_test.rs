@@ -0,0 +1,356 @@
+//! Upload, download and caching paths against the in-process 115 mock.
+//!
+//! Runs without 115 tokens: `cargo test --features mock115`.
+
+#![cfg(feature = "mock115")]
+
+use bytes::Bytes;
+use restic_115::{
+    backend::{Backend, ObjectInfo},
+    config::{BenchArgs, DuplicatePolicy},
+    mock115::Mock115,
+    open115::{Open115Client, ResticFileType},
+};
+
+#[tokio::test]
+async fn test_upload_download_against_mock() {
+    let mock = Mock115::start().await.unwrap();
+    let client = Open115Client::new(mock.config("/backups/repo"))
+        .await
+        .unwrap();
+    client.init_repository().await.unwrap();
+
+    // A regular upload goes through the signed OSS PutObject.
+    let data = Bytes::from(
+        (0..200_000u32)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<u8>>(),
+    );
+    let keys = client.get_type_dir_id(ResticFileType::Keys).await.unwrap();
+    client.upload_file(&keys, "k1", data.clone()).await.unwrap();
+    assert_eq!(mock.calls("/oss"), 1);
+    assert_eq!(mock.read("/backups/repo/keys/k1").unwrap(), data);
+
+    let listed = client.list_files(&keys).await.unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].size, data.len() as i64);
+
+    // Whole and ranged downloads share one download URL.
+    let file = client.find_file(&keys, "k1").await.unwrap().unwrap();
+    let whole = client
+        .download_whole_file(&file.pick_code, data.len() as u64)
+        .await
+        .unwrap();
+    assert_eq!(whole, data);
+    let range = client
+        .download_file(&file.pick_code, Some((10, 19)))
+        .await
+        .unwrap();
+    assert_eq!(range, data.slice(10..20));
+    assert_eq!(mock.calls("/open/ufile/downurl"), 1);
+    assert_eq!(mock.calls("/download"), 2);
+
+    // Known content completes at init, without an OSS upload.
+    let snapshots = client
+        .get_type_dir_id(ResticFileType::Snapshots)
+        .await
+        .unwrap();
+    client
+        .upload_file(&snapshots, "s1", data.clone())
+        .await
+        .unwrap();
+    assert_eq!(mock.calls("/oss"), 1);
+    assert!(client.find_file(&snapshots, "s1").await.unwrap().is_some());
+
+    // A file another client uploaded is found by search on a cache miss.
+    mock.put("/backups/repo/index/i1", "index");
+    let index = client
+        .find_type_dir_id(ResticFileType::Index)
+        .await
+        .unwrap()
+        .unwrap();
+    let found = client
+        .get_file_info_with_fallback(&index, "i1", false)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.size, 5);
+
+    client.delete_file(&keys, &file.file_id).await.unwrap();
+    assert!(mock.read("/backups/repo/keys/k1").is_none());
+    assert!(client.find_file(&keys, "k1").await.unwrap().is_none());
+    assert_eq!(mock.count("/backups/repo", "keys"), 1);
+
+    let space = client.space_info().await.unwrap();
+    // The snapshot and the index are left.
+    assert_eq!(space.used, data.len() as u64 + 5);
+}
+
+#[tokio::test]
+async fn test_bench_against_mock() {
+    let mock = Mock115::start().await.unwrap();
+    let config = mock.config("/backups/repo");
+    let args = BenchArgs {
+        size: vec![1000, 300_000],
+        count: 3,
+        concurrency: 2,
+        path: Some("/bench".to_string()),
+        keep: false,
+    };
+    restic_115::commands::bench::run(&config, &args)
+        .await
+        .unwrap();
+    assert_eq!(mock.calls("/oss"), 6);
+    assert_eq!(mock.calls("/download"), 6);
+    assert_eq!(mock.count("/", "bench"), 0, "the folder is deleted");
+}
+
+#[tokio::test]
+async fn test_download_urls_survive_restart() {
+    let mock = Mock115::start().await.unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = mock.config("/backups/repo");
+    config.db_path = dir.path().join("cache.db").to_string_lossy().into_owned();
+    mock.put("/backups/repo/keys/k1", "key");
+
+    let client = Open115Client::new(config.clone()).await.unwrap();
+    client.warm_cache(false).await.unwrap();
+    let keys = client
+        .find_type_dir_id(ResticFileType::Keys)
+        .await
+        .unwrap()
+        .unwrap();
+    let file = client.find_file(&keys, "k1").await.unwrap().unwrap();
+    client.get_download_url(&file.pick_code).await.unwrap();
+    drop(client);
+
+    let restarted = Open115Client::new(config).await.unwrap();
+    let data = restarted
+        .download_file(&file.pick_code, None)
+        .await
+        .unwrap();
+    assert_eq!(data, "key");
+    assert_eq!(mock.calls("/open/ufile/downurl"), 1);
+}
+
+#[tokio::test]
+async fn test_negative_lookups_cleared_by_upload() {
+    let mock = Mock115::start().await.unwrap();
+    let client = Open115Client::new(mock.config("/backups/repo"))
+        .await
+        .unwrap();
+    client.init_repository().await.unwrap();
+    let data = client.get_type_dir_id(ResticFileType::Data).await.unwrap();
+
+    // The second miss is answered without searching again.
+    for _ in 0..2 {
+        let missing = client
+            .get_file_info_with_fallback(&data, "d1", false)
+            .await
+            .unwrap();
+        assert!(missing.is_none());
+    }
+    assert_eq!(mock.calls("/open/ufile/search"), 1);
+
+    client
+        .upload_file(&data, "d1", Bytes::from_static(b"pack"))
+        .await
+        .unwrap();
+    let found = client
+        .get_file_info_with_fallback(&data, "d1", false)
+        .await
+        .unwrap();
+    assert_eq!(found.unwrap().size, 4);
+}
+
+#[tokio::test]
+async fn test_cut_downloads_resume() {
+    let mock = Mock115::start().await.unwrap();
+    let client = Open115Client::new(mock.config("/backups/repo"))
+        .await
+        .unwrap();
+    let data = Bytes::from((0..100_000u32).map(|i| i as u8).collect::<Vec<u8>>());
+    mock.put("/backups/repo/keys/k1", data.clone());
+    client.warm_cache(false).await.unwrap();
+    let keys = client
+        .find_type_dir_id(ResticFileType::Keys)
+        .await
+        .unwrap()
+        .unwrap();
+    let file = client.find_file(&keys, "k1").await.unwrap().unwrap();
+
+    // Two cuts: the whole download resumes twice from where each stopped.
+    mock.cut_downloads(2, 30_000);
+    let whole = client
+        .download_whole_file(&file.pick_code, data.len() as u64)
+        .await
+        .unwrap();
+    assert_eq!(whole, data);
+    assert_eq!(mock.calls("/download"), 3);
+
+    // So does a ranged one.
+    mock.cut_downloads(1, 100);
+    let range = client
+        .download_file(&file.pick_code, Some((1000, 1999)))
+        .await
+        .unwrap();
+    assert_eq!(range, data.slice(1000..2000));
+    assert_eq!(mock.calls("/download"), 5);
+
+    // A link that keeps dropping fails after the bounded resumes.
+    mock.cut_downloads(10, 10);
+    assert!(
+        client
+            .download_whole_file(&file.pick_code, data.len() as u64)
+            .await
+            .is_err()
+    );
+    assert_eq!(mock.calls("/download"), 9);
+}
+
+#[tokio::test]
+async fn test_size_mismatch_refreshes_entry() {
+    let mock = Mock115::start().await.unwrap();
+    let client = Open115Client::new(mock.config("/backups/repo"))
+        .await
+        .unwrap();
+    mock.put("/backups/repo/keys/k1", "short");
+    client.warm_cache(false).await.unwrap();
+    let keys = client
+        .find_type_dir_id(ResticFileType::Keys)
+        .await
+        .unwrap()
+        .unwrap();
+    let file = client.find_file(&keys, "k1").await.unwrap().unwrap();
+    assert_eq!(file.size, 5);
+
+    // The cached size is stale: the entry is re-read and the download retried.
+    mock.rewrite("/backups/repo/keys/k1", "a longer body");
+    let listings = mock.calls("/open/ufile/files");
+    let object = ObjectInfo {
+        name: "k1".to_string(),
+        size: 5,
+        id: file.pick_code.clone(),
+    };
+    let data = client.get(&object, None).await.unwrap();
+    assert_eq!(data, "a longer body");
+    let file = client.find_file(&keys, "k1").await.unwrap().unwrap();
+    assert_eq!(file.size, 13);
+    assert_eq!(mock.calls("/download"), 2);
+    // Only that one object was looked up, not its whole directory.
+    assert_eq!(mock.calls("/open/ufile/files"), listings);
+    assert_eq!(mock.calls("/open/ufile/search"), 1);
+
+    // A download that keeps disagreeing is refused rather than served short.
+    assert!(matches!(
+        client.download_whole_file(&file.pick_code, 20).await,
+        Err(restic_115::error::AppError::SizeMismatch {
+            expected: 20,
+            actual: 13,
+            ..
+        })
+    ));
+}
+
+#[tokio::test]
+async fn test_reported_size_asks_115() {
+    let mock = Mock115::start().await.unwrap();
+    let client = Open115Client::new(mock.config("/backups/repo"))
+        .await
+        .unwrap();
+    client.init().await.unwrap();
+    client
+        .put(
+            ResticFileType::Snapshots,
+            "s1",
+            Bytes::from_static(b"snapshot"),
+        )
+        .await
+        .unwrap();
+    let object = client
+        .stat(ResticFileType::Snapshots, "s1")
+        .await
+        .unwrap()
+        .unwrap();
+    let resolved = mock.calls("/open/ufile/downurl");
+    assert_eq!(
+        Backend::reported_size(&client, &object).await.unwrap(),
+        Some(8)
+    );
+    assert_eq!(mock.calls("/open/ufile/downurl"), resolved + 1);
+}
+
+#[tokio::test]
+async fn test_throttled_downloads_are_not_split() {
+    let mock = Mock115::start().await.unwrap();
+    let mut config = mock.config("/backups/repo");
+    config.download_parallelism = 4;
+    let data = Bytes::from(vec![7u8; 17 << 20]);
+    mock.put("/backups/repo/keys/k1", data.clone());
+
+    let find = |client: Open115Client| async move {
+        client.warm_cache(false).await.unwrap();
+        let keys = client
+            .find_type_dir_id(ResticFileType::Keys)
+            .await
+            .unwrap()
+            .unwrap();
+        let file = client.find_file(&keys, "k1").await.unwrap().unwrap();
+        (client, file)
+    };
+    let (client, file) = find(Open115Client::new(config.clone()).await.unwrap()).await;
+    let whole = client
+        .download_whole_file(&file.pick_code, data.len() as u64)
+        .await
+        .unwrap();
+    assert_eq!(whole, data);
+    assert_eq!(mock.calls("/download"), 3, "split into three parts");
+
+    // With a limit, the parts would share it: one request.
+    config.limit_download = Some(1 << 30);
+    let (client, file) = find(Open115Client::new(config).await.unwrap()).await;
+    let whole = client
+        .download_whole_file(&file.pick_code, data.len() as u64)
+        .await
+        .unwrap();
+    assert_eq!(whole, data);
+    assert_eq!(mock.calls("/download"), 4);
+}
+
+#[tokio::test]
+async fn test_overwrite_survives_failed_rename() {
+    let mock = Mock115::start().await.unwrap();
+    let mut config = mock.config("/backups/repo");
+    config.duplicate_policy = DuplicatePolicy::Overwrite;
+    let client = Open115Client::new(config).await.unwrap();
+    mock.put("/backups/repo/keys/k1", "old");
+    client.warm_cache(false).await.unwrap();
+    let keys = client
+        .find_type_dir_id(ResticFileType::Keys)
+        .await
+        .unwrap()
+        .unwrap();
+    // Names in keys/ on 115, re-listed past the cache.
+    let names_on_115 = || async {
+        client.refresh_dir("/backups/repo/keys").await.unwrap();
+        let files = client.list_files(&keys).await.unwrap();
+        files.into_iter().map(|f| f.filename).collect::<Vec<_>>()
+    };
+
+    // Moving the old copy aside fails, or moving the upload into place does:
+    // either way the old copy keeps its name and the upload is discarded.
+    for after in [0, 1] {
+        mock.fail_renames(1, after);
+        let upload = client.upload_file(&keys, "k1", Bytes::from("new")).await;
+        assert!(upload.is_err(), "rename {after} fails");
+        assert_eq!(mock.read("/backups/repo/keys/k1").unwrap(), "old");
+        assert_eq!(names_on_115().await, ["k1"]);
+    }
+
+    client
+        .upload_file(&keys, "k1", Bytes::from("new"))
+        .await
+        .unwrap();
+    assert_eq!(mock.read("/backups/repo/keys/k1").unwrap(), "new");
+    assert_eq!(names_on_115().await, ["k1"]);
+}
@@ -3,6 +3,7 @@ import sqlite3
 import sys
 import argparse
 import os
+from pathlib import Path
 
 
 def export_tokens(db_path):
@@ -11,8 +12,10 @@ def export_tokens(db_path):
         sys.exit(1)
 
     try:
-        # Open in read-only mode just in case
-        conn = sqlite3.connect(f"file:{db_path}?mode=ro", uri=True)
+        # Open in read-only mode just in case. Build the URI from the path so
+        # Windows drive letters and special characters are encoded correctly.
+        uri = Path(db_path).resolve().as_uri() + "?mode=ro"
+        conn = sqlite3.connect(uri, uri=True)
         cursor = conn.cursor()
 
         # The Rust implementation uses id=1 for the single token row